@@ -0,0 +1,473 @@
+use std::future::{ready, Ready};
+
+use actix_web::{
+    delete, dev::Payload, get, http::header, post, put, web, FromRequest, HttpRequest,
+    HttpResponse, Responder,
+};
+use festival_tickets_core::db::export::ExportFilter;
+use festival_tickets_core::TicketingService;
+use futures::TryStreamExt;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use super::error::ApiError;
+use super::types::{
+    CreateWebhookEndpointRequest, CreateWebhookEndpointResponse, ExportOrdersQuery, GetJobsQuery,
+    GetWebhookDeliveriesQuery, ImportCompTicketsQuery, ImportPresaleCodesQuery,
+    SetPurchaseLimitOverrideRequest, UpsertPresaleRequest,
+};
+use super::WebResult;
+
+/// Bearer token for the admin API. Admin routes are rejected if unset.
+#[derive(Clone)]
+pub struct AdminToken(pub Option<String>);
+
+/// Extractor guarding admin routes. Requires an `Authorization: Bearer <ADMIN_TOKEN>` header.
+pub struct AdminAuth;
+
+/// Check the request has an `Authorization: Bearer <token>` header for `api`
+pub(super) fn check_bearer_token(
+    req: &HttpRequest,
+    token: Option<&str>,
+    api: &str,
+) -> Result<(), ApiError> {
+    let Some(token) = token else {
+        return Err(ApiError::Unauthorized(format!("{} API is disabled", api)));
+    };
+
+    let bearer = req
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    // In constant time, so the token can't be guessed a byte at a time from response times
+    match bearer {
+        Some(bearer) if bool::from(bearer.as_bytes().ct_eq(token.as_bytes())) => Ok(()),
+        _ => Err(ApiError::Unauthorized(format!("invalid {} token", api))),
+    }
+}
+
+impl FromRequest for AdminAuth {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = req.app_data::<web::Data<AdminToken>>();
+        let token = token.and_then(|t| t.0.as_deref());
+        ready(check_bearer_token(req, token, "admin").map(|_| AdminAuth))
+    }
+}
+
+pub(super) fn configure(config: &mut web::ServiceConfig) {
+    config
+        .service(upsert_presale)
+        .service(import_presale_codes)
+        .service(get_presale_codes)
+        .service(set_purchase_limit_override)
+        .service(delete_purchase_limit_override)
+        .service(get_jobs)
+        .service(retry_job)
+        .service(create_webhook_endpoint)
+        .service(get_webhook_endpoints)
+        .service(delete_webhook_endpoint)
+        .service(get_webhook_deliveries)
+        .service(test_webhook_endpoint)
+        .service(export_orders)
+        .service(import_comp_tickets);
+}
+
+/// Create or update a presale window
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Presale window saved",
+            body = Presale
+        ),
+        (
+            status = 400,
+            description = "Presale window invalid",
+            body = ApiError,
+            example = json!(
+                ApiError::FailedPrecondition(
+                    String::from("presale early-bird must start before it ends")
+                )
+            )
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[put("/admin/presales/{presale_id}")]
+pub async fn upsert_presale(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    presale_id: web::Path<String>,
+    body: web::Json<UpsertPresaleRequest>,
+) -> WebResult<impl Responder> {
+    let res = ticketing
+        .upsert_presale(&presale_id, body.starts_at, body.ends_at)
+        .await?;
+    Ok(web::Json(res))
+}
+
+/// Bulk import presale codes from a CSV body, with header `code,email,ticket_limit`.
+/// Either code or email must be set; rows with only an email are allow-list entries.
+#[utoipa::path(
+    security(("admin_token" = [])),
+    params(ImportPresaleCodesQuery),
+    request_body(content = String, content_type = "text/csv"),
+    responses(
+        (
+            status = 200,
+            description = "Number of imported codes, and rows which were rejected",
+            body = ImportReport
+        ),
+        (
+            status = 400,
+            description = "Presale not found",
+            body = ApiError,
+            example = json!(
+                ApiError::FailedPrecondition(String::from("presale early-bird not found"))
+            )
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[post("/admin/presales/{presale_id}/codes")]
+pub async fn import_presale_codes(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    presale_id: web::Path<String>,
+    query: web::Query<ImportPresaleCodesQuery>,
+    body: String,
+) -> WebResult<impl Responder> {
+    let res = ticketing
+        .import_presale_codes(&presale_id, &body, query.default_ticket_limit)
+        .await?;
+    Ok(web::Json(res))
+}
+
+/// List presale codes with their redemption status
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Presale codes",
+            body = Vec<PresaleCode>
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[get("/admin/presales/{presale_id}/codes")]
+pub async fn get_presale_codes(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    presale_id: web::Path<String>,
+) -> WebResult<impl Responder> {
+    let res = ticketing.get_presale_codes(&presale_id).await?;
+    Ok(web::Json(res))
+}
+
+/// Override the per-customer ticket limit for an email, e.g. for group bookers
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Purchase limit override saved",
+            body = PurchaseLimitOverride
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[put("/admin/purchase-limits/{email}")]
+pub async fn set_purchase_limit_override(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    email: web::Path<String>,
+    body: web::Json<SetPurchaseLimitOverrideRequest>,
+) -> WebResult<impl Responder> {
+    let res = ticketing
+        .set_purchase_limit_override(&email, body.ticket_limit, body.note.as_deref())
+        .await?;
+    Ok(web::Json(res))
+}
+
+/// Remove a purchase limit override, so the default limit applies again
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "Purchase limit override removed"),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[delete("/admin/purchase-limits/{email}")]
+pub async fn delete_purchase_limit_override(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    email: web::Path<String>,
+) -> WebResult<impl Responder> {
+    ticketing.delete_purchase_limit_override(&email).await?;
+    Ok(HttpResponse::NoContent())
+}
+
+/// List background jobs, most recently updated first
+#[utoipa::path(
+    security(("admin_token" = [])),
+    params(GetJobsQuery),
+    responses(
+        (
+            status = 200,
+            description = "Jobs",
+            body = Vec<Job>
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[get("/admin/jobs")]
+pub async fn get_jobs(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    query: web::Query<GetJobsQuery>,
+) -> WebResult<impl Responder> {
+    let res = ticketing
+        .get_jobs(query.state.as_deref(), query.kind.as_deref(), query.limit)
+        .await?;
+    Ok(web::Json(res))
+}
+
+/// Run a dead or pending job now, with its attempts reset
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Job queued",
+            body = Job
+        ),
+        (
+            status = 400,
+            description = "Job not found, or running or succeeded",
+            body = ApiError,
+            example = json!(ApiError::FailedPrecondition(String::from("job 42 is running")))
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[post("/admin/jobs/{job_id}/retry")]
+pub async fn retry_job(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    job_id: web::Path<i64>,
+) -> WebResult<impl Responder> {
+    let res = ticketing.retry_job(*job_id).await?;
+    Ok(web::Json(res))
+}
+
+/// Register an endpoint for order events. Deliveries are signed with the returned secret.
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Endpoint registered",
+            body = CreateWebhookEndpointResponse
+        ),
+        (
+            status = 400,
+            description = "Invalid url or events",
+            body = ApiError,
+            example = json!(
+                ApiError::FailedPrecondition(String::from("invalid webhook url example.com"))
+            )
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[post("/admin/webhooks")]
+pub async fn create_webhook_endpoint(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    body: web::Json<CreateWebhookEndpointRequest>,
+) -> WebResult<impl Responder> {
+    let (endpoint, secret) = ticketing
+        .create_webhook_endpoint(&body.url, &body.events)
+        .await?;
+    Ok(web::Json(CreateWebhookEndpointResponse {
+        endpoint,
+        secret,
+    }))
+}
+
+/// List webhook endpoints
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Webhook endpoints",
+            body = Vec<WebhookEndpoint>
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[get("/admin/webhooks")]
+pub async fn get_webhook_endpoints(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+) -> WebResult<impl Responder> {
+    let res = ticketing.get_webhook_endpoints().await?;
+    Ok(web::Json(res))
+}
+
+/// Remove a webhook endpoint, with its delivery log
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "Webhook endpoint removed"),
+        (
+            status = 400,
+            description = "Webhook endpoint not found",
+            body = ApiError
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[delete("/admin/webhooks/{endpoint_id}")]
+pub async fn delete_webhook_endpoint(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    endpoint_id: web::Path<Uuid>,
+) -> WebResult<impl Responder> {
+    ticketing.delete_webhook_endpoint(&endpoint_id).await?;
+    Ok(HttpResponse::NoContent())
+}
+
+/// Delivery log of a webhook endpoint, newest first
+#[utoipa::path(
+    security(("admin_token" = [])),
+    params(GetWebhookDeliveriesQuery),
+    responses(
+        (
+            status = 200,
+            description = "Webhook deliveries",
+            body = Vec<WebhookDelivery>
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[get("/admin/webhooks/{endpoint_id}/deliveries")]
+pub async fn get_webhook_deliveries(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    endpoint_id: web::Path<Uuid>,
+    query: web::Query<GetWebhookDeliveriesQuery>,
+) -> WebResult<impl Responder> {
+    let res = ticketing
+        .get_webhook_deliveries(&endpoint_id, query.limit)
+        .await?;
+    Ok(web::Json(res))
+}
+
+/// Send a webhook.test event to an endpoint now, and return the outcome
+#[utoipa::path(
+    security(("admin_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Delivery, including failed ones",
+            body = WebhookDelivery
+        ),
+        (
+            status = 400,
+            description = "Webhook endpoint not found",
+            body = ApiError
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[post("/admin/webhooks/{endpoint_id}/test")]
+pub async fn test_webhook_endpoint(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    endpoint_id: web::Path<Uuid>,
+) -> WebResult<impl Responder> {
+    let res = ticketing.test_webhook_endpoint(&endpoint_id).await?;
+    Ok(web::Json(res))
+}
+
+/// Purchased orders with their attendees, oldest purchase first. The file is streamed in chunks.
+#[utoipa::path(
+    security(("admin_token" = [])),
+    params(ExportOrdersQuery),
+    responses(
+        (
+            status = 200,
+            description = "Export file",
+            content_type = ["text/csv", "application/x-ndjson"]
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[get("/admin/exports/orders")]
+pub async fn export_orders(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    query: web::Query<ExportOrdersQuery>,
+) -> WebResult<impl Responder> {
+    let query = query.into_inner();
+    let format = query.format;
+    let filter = ExportFilter {
+        ticket_type_id: query.ticket_type_id,
+        duration: query.duration,
+        purchased_from: query.purchased_from,
+        purchased_to: query.purchased_to,
+    };
+
+    let chunks = ticketing
+        .export_orders(filter, format)
+        .map_ok(web::Bytes::from)
+        .map_err(|e| {
+            log::error!("{:#?}", e);
+            ApiError::from(e)
+        });
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"orders.{}\"", format.extension()),
+        ))
+        .streaming(chunks))
+}
+
+/// Bulk import complimentary tickets, i.e. for artists and crew, as purchased zero-price orders.
+/// The CSV body has header `ticket_type_id,duration,name,email,address,allocation`.
+#[utoipa::path(
+    security(("admin_token" = [])),
+    params(ImportCompTicketsQuery),
+    request_body(content = String, content_type = "text/csv"),
+    responses(
+        (
+            status = 200,
+            description = "Number of imported tickets, and rows which were rejected",
+            body = CompImportReport
+        ),
+        (status = 401, description = "Missing or invalid admin token", body = ApiError)
+    )
+)]
+#[post("/admin/comp-tickets")]
+pub async fn import_comp_tickets(
+    _auth: AdminAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    query: web::Query<ImportCompTicketsQuery>,
+    body: String,
+) -> WebResult<impl Responder> {
+    let res = ticketing
+        .import_comp_tickets(&body, query.allocation.as_deref(), query.dry_run)
+        .await?;
+    Ok(web::Json(res))
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ContentType, ETag, EntityTag, Header, IfNoneMatch,
+};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// JSON response with an `ETag` of the body, which HTTP caches and CDNs may reuse for `max_age`.
+/// `304 Not Modified` if the request's `If-None-Match` already has the body. `max-age` is in whole
+/// seconds, so `max_age` is rounded up, rather than a sub-second TTL disabling caching altogether
+pub(crate) fn cached_json<T: Serialize>(
+    req: &HttpRequest,
+    value: &T,
+    max_age: Duration,
+) -> HttpResponse {
+    let body = serde_json::to_vec(value).unwrap();
+    // Same for the same body on every instance, so a CDN can revalidate against any of them
+    let tag = EntityTag::new_strong(hex::encode(&Sha256::digest(&body)[..16]));
+    let max_age_secs = max_age.as_secs() + u64::from(max_age.subsec_nanos() > 0);
+    let cache_control = CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::MaxAge(max_age_secs.try_into().unwrap_or(u32::MAX)),
+    ]);
+
+    let not_modified = match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|t| t.weak_eq(&tag)),
+        Err(_) => false,
+    };
+    if not_modified {
+        return HttpResponse::NotModified()
+            .insert_header(ETag(tag))
+            .insert_header(cache_control)
+            .finish();
+    }
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .insert_header(ETag(tag))
+        .insert_header(cache_control)
+        .body(body)
+}
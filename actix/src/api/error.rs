@@ -1,8 +1,11 @@
-use crate::db::error::DbError;
 use actix_web::{
-    http::{header::ContentType, StatusCode},
+    http::{
+        header::{ContentType, RETRY_AFTER},
+        StatusCode,
+    },
     HttpResponse,
 };
+use festival_tickets_core::error::RETRY_AFTER_SECS;
 use serde::Serialize;
 use thiserror::Error;
 use utoipa::ToSchema;
@@ -15,16 +18,30 @@ pub enum ApiError {
     FailedPrecondition(String),
     #[error("not found: {0}")]
     NotFound(String),
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("unavailable: {0}")]
+    Unavailable(String),
     #[error("unknown service error")]
     Unknown,
 }
 
-impl From<DbError> for ApiError {
-    fn from(value: DbError) -> Self {
+impl From<festival_tickets_core::Error> for ApiError {
+    fn from(value: festival_tickets_core::Error) -> Self {
+        use festival_tickets_core::Error;
+
         match value {
-            DbError::FailedPrecondition(e) => Self::FailedPrecondition(e),
-            DbError::ExecutionError(e) => Self::DbExecutionError(e.to_string()),
-            DbError::Unknown => Self::Unknown,
+            Error::NotFound(e) => Self::NotFound(e),
+            Error::FailedPrecondition(e) => Self::FailedPrecondition(e),
+            Error::SoldOut(e) => Self::FailedPrecondition(e),
+            Error::Db(e) => Self::DbExecutionError(e.to_string()),
+            Error::Ticket(e) => {
+                // Not sent to the client, but logged with the request
+                tracing::Span::current().record("source", tracing::field::display(e));
+                Self::Unknown
+            }
+            Error::Unavailable(e) => Self::Unavailable(e),
+            Error::Unknown => Self::Unknown,
         }
     }
 }
@@ -35,17 +52,21 @@ impl actix_web::error::ResponseError for ApiError {
             ApiError::DbExecutionError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::FailedPrecondition(_) => StatusCode::BAD_REQUEST,
             ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             ApiError::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
         let status_code = self.status_code();
-        if !status_code.is_success() {
-            log::error!("response error ({}): {}", status_code, self);
+        // Logged with the request once the response is sent, at a level for the status
+        tracing::Span::current().record("error", tracing::field::display(self));
+        let mut res = HttpResponse::build(status_code);
+        if let ApiError::Unavailable(_) = self {
+            res.insert_header((RETRY_AFTER, RETRY_AFTER_SECS));
         }
-        HttpResponse::build(status_code)
-            .insert_header(ContentType::json())
+        res.insert_header(ContentType::json())
             .body(serde_json::to_vec(self).unwrap())
     }
 }
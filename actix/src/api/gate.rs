@@ -0,0 +1,109 @@
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, get, post, web, FromRequest, HttpRequest, Responder};
+use festival_tickets_core::TicketingService;
+
+use super::admin::check_bearer_token;
+use super::error::ApiError;
+use super::types::{CheckInRequest, CheckInResponse, SyncCheckInsRequest, SyncCheckInsResponse};
+use super::WebResult;
+
+/// Bearer token for gate scanners. Gate routes are rejected if unset.
+#[derive(Clone)]
+pub struct ScannerToken(pub Option<String>);
+
+/// Extractor guarding gate routes. Requires an `Authorization: Bearer <SCANNER_TOKEN>` header.
+pub struct ScannerAuth;
+
+impl FromRequest for ScannerAuth {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = req.app_data::<web::Data<ScannerToken>>();
+        let token = token.and_then(|t| t.0.as_deref());
+        ready(check_bearer_token(req, token, "scanner").map(|_| ScannerAuth))
+    }
+}
+
+pub(super) fn configure(config: &mut web::ServiceConfig) {
+    config
+        .service(check_in)
+        .service(get_scanner_config)
+        .service(sync_check_ins);
+}
+
+/// Verify a ticket credential and record the scan, if it follows the entry rules
+#[utoipa::path(
+    security(("scanner_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Scan recorded, with the ticket to check against the holder",
+            body = CheckInResponse
+        ),
+        (
+            status = 400,
+            description = "Credential invalid, ticket not valid today, or duplicate entry",
+            body = ApiError,
+            example = json!(
+                ApiError::FailedPrecondition(String::from("ticket has already been checked in"))
+            )
+        ),
+        (status = 401, description = "Missing or invalid scanner token", body = ApiError)
+    )
+)]
+#[post("/gate/check-in")]
+pub async fn check_in(
+    _auth: ScannerAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    body: web::Json<CheckInRequest>,
+) -> WebResult<impl Responder> {
+    let (check_in, ticket) = ticketing
+        .check_in(&body.credential, body.direction, &body.gate)
+        .await?;
+
+    Ok(web::Json(CheckInResponse { check_in, ticket }))
+}
+
+/// Config for scanners to verify tickets locally while offline
+#[utoipa::path(
+    security(("scanner_token" = [])),
+    responses(
+        (status = 200, description = "Scanner config", body = ScannerConfig),
+        (status = 401, description = "Missing or invalid scanner token", body = ApiError)
+    )
+)]
+#[get("/gate/config")]
+pub async fn get_scanner_config(
+    _auth: ScannerAuth,
+    ticketing: web::Data<dyn TicketingService>,
+) -> WebResult<impl Responder> {
+    Ok(web::Json(ticketing.get_scanner_config()))
+}
+
+/// Upload scans made while offline. Scans which broke the entry rules are recorded as conflicts.
+#[utoipa::path(
+    security(("scanner_token" = [])),
+    responses(
+        (
+            status = 200,
+            description = "Recorded scans, and scans which couldn't be recorded",
+            body = SyncCheckInsResponse
+        ),
+        (status = 401, description = "Missing or invalid scanner token", body = ApiError)
+    )
+)]
+#[post("/gate/sync")]
+pub async fn sync_check_ins(
+    _auth: ScannerAuth,
+    ticketing: web::Data<dyn TicketingService>,
+    body: web::Json<SyncCheckInsRequest>,
+) -> WebResult<impl Responder> {
+    let (check_ins, rejected) = ticketing.sync_check_ins(&body.check_ins).await?;
+
+    Ok(web::Json(SyncCheckInsResponse {
+        check_ins,
+        rejected,
+    }))
+}
@@ -0,0 +1,40 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use festival_tickets_core::health::{HealthCheck, HealthReport};
+
+pub(crate) fn configure(config: &mut web::ServiceConfig) {
+    config.service(healthz).service(readyz);
+}
+
+fn respond(healthy: bool, report: HealthReport) -> HttpResponse {
+    if healthy {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+/// Liveness check, fails once the background workers have stopped making progress
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Background workers are running", body = HealthReport),
+        (status = 503, description = "Background workers are stuck or stopped", body = HealthReport)
+    )
+)]
+#[get("/healthz")]
+pub async fn healthz(health: web::Data<HealthCheck>) -> impl Responder {
+    let report = health.check_live();
+    respond(report.workers, report)
+}
+
+/// Readiness check, fails while the database can't be reached or workers aren't running
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Ready to serve requests", body = HealthReport),
+        (status = 503, description = "Database or background workers unhealthy", body = HealthReport)
+    )
+)]
+#[get("/readyz")]
+pub async fn readyz(health: web::Data<HealthCheck>) -> impl Responder {
+    let report = health.check().await;
+    respond(report.is_ready(), report)
+}
@@ -0,0 +1,59 @@
+use std::future::{ready, Ready};
+use std::time::Instant;
+
+use actix_web::dev::{Payload, ServiceResponse};
+use actix_web::{get, web, FromRequest, HttpRequest, HttpResponse, Responder};
+use festival_tickets_core::{metrics, TicketingService};
+
+use super::admin::check_bearer_token;
+use super::error::ApiError;
+
+/// Bearer token for scraping metrics. Metrics are rejected if unset.
+#[derive(Clone)]
+pub struct MetricsToken(pub Option<String>);
+
+/// Extractor guarding metrics. Requires an `Authorization: Bearer <METRICS_TOKEN>` header.
+pub struct MetricsAuth;
+
+impl FromRequest for MetricsAuth {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = req.app_data::<web::Data<MetricsToken>>();
+        let token = token.and_then(|t| t.0.as_deref());
+        ready(check_bearer_token(req, token, "metrics").map(|_| MetricsAuth))
+    }
+}
+
+pub(crate) fn configure(config: &mut web::ServiceConfig) {
+    config.service(get_metrics);
+}
+
+/// Record the count and latency of a request, by route pattern
+pub(crate) fn record_request<B>(res: &ServiceResponse<B>, started: Instant) {
+    let route = res
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    metrics::record_request("rest", &route, res.status().as_str(), started.elapsed());
+}
+
+/// Metrics in the Prometheus text format
+#[utoipa::path(
+    security(("metrics_token" = [])),
+    responses(
+        (status = 200, description = "Prometheus metrics", content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid metrics token", body = ApiError)
+    )
+)]
+#[get("/metrics")]
+pub async fn get_metrics(
+    _auth: MetricsAuth,
+    ticketing: web::Data<dyn TicketingService>,
+) -> impl Responder {
+    let body = metrics::render(ticketing.as_ref()).await;
+    HttpResponse::Ok()
+        .content_type(metrics::CONTENT_TYPE)
+        .body(body)
+}
@@ -1,20 +1,34 @@
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use festival_tickets_core::config::CatalogueConfig;
+use festival_tickets_core::TicketingService;
 use uuid::Uuid;
 
-use crate::db;
+pub mod admin;
+mod cache;
 pub mod error;
+pub mod gate;
+pub mod health;
+pub mod metrics;
 pub mod types;
 
 use error::ApiError;
-use types::{AddTicketToBasketRequest, AddUserInfoRequest};
+use types::{AddTicketToBasketRequest, ApplyPromoCodeRequest, NewUser, TicketQrCodeQuery};
 
 //type WebResult<T> = actix_web::Result<T>;
 type WebResult<T> = Result<T, ApiError>;
 
-pub(super) fn configure(pool: web::Data<db::DbPool>) -> impl FnOnce(&mut web::ServiceConfig) {
+pub(super) fn configure(
+    ticketing: web::Data<dyn TicketingService>,
+    admin_token: web::Data<admin::AdminToken>,
+    scanner_token: web::Data<gate::ScannerToken>,
+    catalogue: web::Data<CatalogueConfig>,
+) -> impl FnOnce(&mut web::ServiceConfig) {
     |config: &mut web::ServiceConfig| {
         config
-            .app_data(pool)
+            .app_data(ticketing)
+            .app_data(admin_token)
+            .app_data(scanner_token)
+            .app_data(catalogue)
             .service(add_ticket_to_basket)
             .service(get_ticket_types)
             .service(get_ticket_durations)
@@ -22,7 +36,12 @@ pub(super) fn configure(pool: web::Data<db::DbPool>) -> impl FnOnce(&mut web::Se
             .service(get_order)
             .service(get_user)
             .service(add_user_info)
-            .service(stream_order_stats);
+            .service(apply_promo_code)
+            .service(get_tickets)
+            .service(get_ticket_qr_code)
+            .service(stream_order_stats)
+            .configure(admin::configure)
+            .configure(gate::configure);
     }
 }
 
@@ -46,46 +65,60 @@ pub(super) fn configure(pool: web::Data<db::DbPool>) -> impl FnOnce(&mut web::Se
 )]
 #[post("/tickets/add-to-basket")]
 pub async fn add_ticket_to_basket(
-    pool: web::Data<db::DbPool>,
+    ticketing: web::Data<dyn TicketingService>,
     req: web::Json<AddTicketToBasketRequest>,
 ) -> WebResult<impl Responder> {
-    let res = db::add_ticket_to_basket(&pool, &req.ticket_type_id, req.duration).await?;
+    let res = ticketing
+        .add_ticket_to_basket(
+            &req.ticket_type_id,
+            req.duration,
+            req.presale_code.as_deref(),
+        )
+        .await?;
     Ok(web::Json(res))
 }
 
-/// List possible ticket types
+/// List possible ticket types. Cacheable, with an `ETag`
 #[utoipa::path(
     responses(
         (
             status = 200,
             description = "List of possible ticket types",
             body = Vec<TicketType>
-        )
+        ),
+        (status = 304, description = "Ticket types match the `ETag` in `If-None-Match`")
     )
 )]
 #[get("/tickets/types")]
-pub async fn get_ticket_types(pool: web::Data<db::DbPool>) -> WebResult<impl Responder> {
-    let res = db::get_ticket_types(&pool).await?;
-    Ok(web::Json(res))
+pub async fn get_ticket_types(
+    req: HttpRequest,
+    ticketing: web::Data<dyn TicketingService>,
+    catalogue: web::Data<CatalogueConfig>,
+) -> WebResult<impl Responder> {
+    let res = ticketing.get_ticket_types().await?;
+    Ok(cache::cached_json(&req, &res, catalogue.ticket_types_ttl()))
 }
 
-/// List possible duration (days) selection for given ticket type
+/// List possible duration (days) selection for given ticket type. Cacheable, with an `ETag`
 #[utoipa::path(
     responses(
         (
             status = 200,
             description = "List of possible durations",
             body = Vec<i32>
-        )
+        ),
+        (status = 304, description = "Durations match the `ETag` in `If-None-Match`")
     )
 )]
 #[get("/tickets/durations/{ticket_type_id}")]
 pub async fn get_ticket_durations(
-    pool: web::Data<db::DbPool>,
+    req: HttpRequest,
+    ticketing: web::Data<dyn TicketingService>,
+    catalogue: web::Data<CatalogueConfig>,
     ticket_type_id: web::Path<String>,
 ) -> WebResult<impl Responder> {
-    let res = db::get_ticket_durations(&pool, &ticket_type_id).await?;
-    Ok(web::Json(res))
+    let res = ticketing.get_ticket_durations(&ticket_type_id).await?;
+    Ok(cache::cached_json(&req, &res, catalogue.availability_ttl()))
 }
 
 /// Purchase an order. Note: User info must be attached to order first
@@ -98,7 +131,7 @@ pub async fn get_ticket_durations(
         ),
         (
             status = 400,
-            description = "User info missing from order",
+            description = "User info missing from order, or customer purchase limit reached",
             body = ApiError,
             example = json!(
                 ApiError::FailedPrecondition(
@@ -110,10 +143,10 @@ pub async fn get_ticket_durations(
 )]
 #[post("/orders/{order_id}/purchase")]
 pub async fn purchase_order(
-    pool: web::Data<db::DbPool>,
+    ticketing: web::Data<dyn TicketingService>,
     order_id: web::Path<Uuid>,
 ) -> WebResult<impl Responder> {
-    let res = db::purchase_order(&pool, &order_id).await?;
+    let res = ticketing.purchase_order(&order_id).await?;
     Ok(web::Json(res))
 }
 
@@ -137,15 +170,11 @@ pub async fn purchase_order(
 )]
 #[get("/orders/{order_id}")]
 pub async fn get_order(
-    pool: web::Data<db::DbPool>,
+    ticketing: web::Data<dyn TicketingService>,
     order_id: web::Path<Uuid>,
 ) -> WebResult<impl Responder> {
-    let res = db::get_order(&pool, &order_id).await?;
-
-    match res {
-        Some(order) => Ok(web::Json(order)),
-        None => Err(ApiError::NotFound(format!("order {} not found", *order_id))),
-    }
+    let res = ticketing.get_order(&order_id).await?;
+    Ok(web::Json(res))
 }
 
 /// Retrieve a user by ID
@@ -168,10 +197,10 @@ pub async fn get_order(
 )]
 #[get("/users/{user_id}")]
 pub async fn get_user(
-    pool: web::Data<db::DbPool>,
+    ticketing: web::Data<dyn TicketingService>,
     user_id: web::Path<Uuid>,
 ) -> WebResult<impl Responder> {
-    let res = db::get_user(&pool, &user_id).await?;
+    let res = ticketing.get_user(&user_id).await?;
     Ok(web::Json(res))
 }
 
@@ -182,19 +211,112 @@ pub async fn get_user(
             status = 200,
             description = "Added user info to order",
             body = Order
+        ),
+        (
+            status = 400,
+            description = "Customer purchase limit reached, or email not on presale allow-list",
+            body = ApiError,
+            example = json!(
+                ApiError::FailedPrecondition(
+                    String::from("purchase limit of 4 tickets per customer reached")
+                )
+            )
         )
     )
 )]
 #[post("/orders/{order_id}/add-user-info")]
 pub async fn add_user_info(
-    pool: web::Data<db::DbPool>,
+    ticketing: web::Data<dyn TicketingService>,
+    order_id: web::Path<Uuid>,
+    body: web::Json<NewUser>,
+) -> WebResult<impl Responder> {
+    let res = ticketing.add_user_info(&order_id, &body).await?;
+    Ok(web::Json(res))
+}
+
+/// Apply a promo code to a reserved order
+#[utoipa::path(
+    responses(
+        (
+            status = 200,
+            description = "Promo code applied, discount subtracted from order price",
+            body = Order
+        ),
+        (
+            status = 400,
+            description = "Promo code invalid, expired, fully redeemed or not applicable to order",
+            body = ApiError,
+            example = json!(
+                ApiError::FailedPrecondition(String::from("promo code EARLYBIRD has expired"))
+            )
+        )
+    )
+)]
+#[post("/orders/{order_id}/apply-promo-code")]
+pub async fn apply_promo_code(
+    ticketing: web::Data<dyn TicketingService>,
     order_id: web::Path<Uuid>,
-    body: web::Json<AddUserInfoRequest>,
+    body: web::Json<ApplyPromoCodeRequest>,
 ) -> WebResult<impl Responder> {
-    let res = db::add_user_to_order(&pool, &order_id, &body).await?;
+    let res = ticketing
+        .apply_promo_code(&order_id, &body.promo_code)
+        .await?;
     Ok(web::Json(res))
 }
 
+/// Retrieve the signed tickets issued for an order. Empty until the order is purchased.
+#[utoipa::path(
+    responses(
+        (
+            status = 200,
+            description = "Tickets issued for order",
+            body = Vec<Ticket>
+        )
+    )
+)]
+#[get("/orders/{order_id}/tickets")]
+pub async fn get_tickets(
+    ticketing: web::Data<dyn TicketingService>,
+    order_id: web::Path<Uuid>,
+) -> WebResult<impl Responder> {
+    let res = ticketing.get_tickets(&order_id).await?;
+    Ok(web::Json(res))
+}
+
+/// Render the ticket credential for an order as a QR code
+#[utoipa::path(
+    params(TicketQrCodeQuery),
+    responses(
+        (
+            status = 200,
+            description = "QR code image",
+            content_type = ["image/png", "image/svg+xml"]
+        ),
+        (
+            status = 400,
+            description = "Order has not been purchased",
+            body = ApiError,
+            example = json!(
+                ApiError::FailedPrecondition(String::from("no ticket issued for order 1234"))
+            )
+        )
+    )
+)]
+#[get("/orders/{order_id}/tickets/qr")]
+pub async fn get_ticket_qr_code(
+    ticketing: web::Data<dyn TicketingService>,
+    order_id: web::Path<Uuid>,
+    query: web::Query<TicketQrCodeQuery>,
+) -> WebResult<impl Responder> {
+    let qr_code = ticketing
+        .get_ticket_qr_code(&order_id, query.format)
+        .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(qr_code.content_type)
+        .body(qr_code.image))
+}
+
 #[get("/orders/stats")]
 pub async fn stream_order_stats() -> impl Responder {
     // TODO: Complete this endpoint
@@ -1,46 +1,20 @@
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
-use uuid::Uuid;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize)]
-pub struct OrderStats {
-    pub duration_days: i32,
-    pub order_limit: i32,
-    pub order_count: i32,
-}
-
-#[derive(Serialize, ToSchema)]
-pub struct Order {
-    pub id: Uuid,
-    pub ticket_type_id: String,
-    pub user_id: Option<String>,
-    pub duration: i32,
-    pub price: f32,
-    pub reserved_until: chrono::DateTime<chrono::Utc>,
-    pub purchased_at: Option<chrono::DateTime<chrono::Utc>>,
-}
-
-#[derive(Serialize, ToSchema)]
-pub struct User {
-    pub id: Uuid,
-    pub name: String,
-    pub address: String,
-    pub email: String,
-    pub order_id: Uuid,
-}
-
-#[derive(Serialize, ToSchema)]
-pub struct TicketType {
-    pub id: String,
-    pub display: String,
-    pub sold_out: bool,
-}
+pub use festival_tickets_core::export::ExportFormat;
+pub use festival_tickets_core::model::{
+    CheckIn, Job, NewUser, OfflineCheckIn, Order, Presale, PresaleCode, PurchaseLimitOverride,
+    QrCodeFormat, ScanDirection, ScannerConfig, Ticket, TicketType, User, WebhookDelivery,
+    WebhookEndpoint,
+};
 
 #[derive(Deserialize, ToSchema)]
 pub struct AddTicketToBasketRequest {
     pub ticket_type_id: String,
     /// Duration in days
     pub duration: i32,
+    /// Required while a presale is running
+    pub presale_code: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -54,8 +28,107 @@ pub struct GetTicketTypesResponse {
 }
 
 #[derive(Deserialize, ToSchema)]
-pub struct AddUserInfoRequest {
-    pub name: String,
-    pub email: String,
-    pub address: String,
+pub struct ApplyPromoCodeRequest {
+    pub promo_code: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpsertPresaleRequest {
+    pub starts_at: chrono::DateTime<chrono::Utc>,
+    pub ends_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ImportPresaleCodesQuery {
+    /// Used for rows without a ticket_limit
+    pub default_ticket_limit: i32,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ImportCompTicketsQuery {
+    /// Used for rows without an allocation, i.e. crew
+    pub allocation: Option<String>,
+    /// Check the rows without saving anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetPurchaseLimitOverrideRequest {
+    pub ticket_limit: i32,
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateWebhookEndpointRequest {
+    pub url: String,
+    pub events: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreateWebhookEndpointResponse {
+    pub endpoint: WebhookEndpoint,
+    /// Deliveries have an `X-Festival-Signature: t=<unix timestamp>,v1=<signature>` header,
+    /// where signature is the hex HMAC-SHA256 of `<timestamp>.<body>` with this secret
+    pub secret: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct GetWebhookDeliveriesQuery {
+    /// Defaults to 100
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ExportOrdersQuery {
+    /// File format, defaults to csv
+    #[serde(default)]
+    pub format: ExportFormat,
+    pub ticket_type_id: Option<String>,
+    pub duration: Option<i32>,
+    /// Purchased at or after
+    pub purchased_from: Option<chrono::DateTime<chrono::Utc>>,
+    /// Purchased before
+    pub purchased_to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct GetJobsQuery {
+    /// pending, running, succeeded or dead
+    pub state: Option<String>,
+    pub kind: Option<String>,
+    /// Defaults to 100
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct TicketQrCodeQuery {
+    /// Image format, defaults to png
+    #[serde(default)]
+    pub format: QrCodeFormat,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CheckInRequest {
+    pub credential: String,
+    pub direction: ScanDirection,
+    pub gate: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CheckInResponse {
+    pub check_in: CheckIn,
+    pub ticket: Option<Ticket>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SyncCheckInsRequest {
+    pub check_ins: Vec<OfflineCheckIn>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SyncCheckInsResponse {
+    pub check_ins: Vec<CheckIn>,
+    /// Scans which couldn't be recorded, and why
+    pub rejected: Vec<String>,
 }
@@ -0,0 +1,218 @@
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Instant;
+
+use actix_web::dev::{Server, Service};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{middleware::Logger, web, App, HttpServer};
+use festival_tickets_core::config::{ApiTokens, Config};
+use festival_tickets_core::health::{HealthCheck, HealthReport};
+use festival_tickets_core::shutdown::CancellationToken;
+use festival_tickets_core::telemetry::{self, REQUEST_ID_HEADER};
+use festival_tickets_core::{db, TicketingService};
+use tracing::field::Empty;
+use tracing::Instrument;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_rapidoc::RapiDoc;
+use utoipa_redoc::{Redoc, Servable};
+use utoipa_swagger_ui::SwaggerUi;
+
+pub mod api;
+
+/// Default access log format, plus the request ID
+const LOG_FORMAT: &str = r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-request-id}o"#;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        api::add_ticket_to_basket,
+        api::get_ticket_types,
+        api::get_ticket_durations,
+        api::purchase_order,
+        api::get_order,
+        api::get_user,
+        api::add_user_info,
+        api::apply_promo_code,
+        api::get_tickets,
+        api::get_ticket_qr_code,
+        api::admin::upsert_presale,
+        api::admin::import_presale_codes,
+        api::admin::get_presale_codes,
+        api::admin::set_purchase_limit_override,
+        api::admin::delete_purchase_limit_override,
+        api::admin::get_jobs,
+        api::admin::retry_job,
+        api::admin::create_webhook_endpoint,
+        api::admin::get_webhook_endpoints,
+        api::admin::delete_webhook_endpoint,
+        api::admin::get_webhook_deliveries,
+        api::admin::test_webhook_endpoint,
+        api::admin::export_orders,
+        api::admin::import_comp_tickets,
+        api::gate::check_in,
+        api::gate::get_scanner_config,
+        api::gate::sync_check_ins,
+        api::health::healthz,
+        api::health::readyz,
+        api::metrics::get_metrics,
+    ),
+    components(
+        schemas(
+            api::types::Order,
+            api::error::ApiError,
+            api::types::AddTicketToBasketRequest,
+            api::types::TicketType,
+            api::types::User,
+            api::types::NewUser,
+            api::types::ApplyPromoCodeRequest,
+            api::types::Presale,
+            api::types::PresaleCode,
+            api::types::UpsertPresaleRequest,
+            db::presale::ImportReport,
+            db::comps::CompImportReport,
+            api::types::PurchaseLimitOverride,
+            api::types::SetPurchaseLimitOverrideRequest,
+            api::types::Job,
+            api::types::WebhookEndpoint,
+            api::types::WebhookDelivery,
+            api::types::CreateWebhookEndpointRequest,
+            api::types::CreateWebhookEndpointResponse,
+            api::types::ExportFormat,
+            api::types::Ticket,
+            api::types::QrCodeFormat,
+            api::types::ScanDirection,
+            api::types::CheckIn,
+            api::types::CheckInRequest,
+            api::types::CheckInResponse,
+            api::types::ScannerConfig,
+            api::types::OfflineCheckIn,
+            api::types::SyncCheckInsRequest,
+            api::types::SyncCheckInsResponse,
+            HealthReport,
+        )
+    ),
+    modifiers(&SecurityAddon),
+    tags(
+        (name = "festival-tickets", description = "Purchase festival tickets")
+    ),
+)]
+struct ApiDoc;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.as_mut().unwrap();
+        for token in ["admin_token", "scanner_token", "metrics_token"] {
+            components.add_security_scheme(
+                token,
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            )
+        }
+    }
+}
+
+/// Serve the REST API, its docs, health checks and metrics on `listener`. The admin and gate routes
+/// require the tokens in `tokens`. Stops accepting connections once `shutdown` is cancelled,
+/// giving in-flight requests until the shutdown timeout to finish
+pub fn server(
+    listener: TcpListener,
+    ticketing: Arc<dyn TicketingService>,
+    health: HealthCheck,
+    tokens: ApiTokens,
+    config: &Config,
+    shutdown: CancellationToken,
+) -> std::io::Result<Server> {
+    let openapi = ApiDoc::openapi();
+
+    let admin_token = web::Data::new(api::admin::AdminToken(tokens.admin));
+    let scanner_token = web::Data::new(api::gate::ScannerToken(tokens.scanner));
+    let metrics_token = web::Data::new(api::metrics::MetricsToken(tokens.metrics));
+
+    let ticketing = web::Data::from(ticketing);
+    let catalogue = web::Data::new(config.catalogue.clone());
+    let health = web::Data::new(health);
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .configure(api::configure(
+                ticketing.clone(),
+                admin_token.clone(),
+                scanner_token.clone(),
+                catalogue.clone(),
+            ))
+            .app_data(health.clone())
+            .configure(api::health::configure)
+            .app_data(metrics_token.clone())
+            .configure(api::metrics::configure)
+            // Setup OpenAPI routes.
+            // See: https://github.com/juhaku/utoipa/blob/master/examples/todo-actix/src/main.rs
+            .service(Redoc::with_url("/redoc", openapi.clone()))
+            .service(
+                SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", openapi.clone()),
+            )
+            .service(RapiDoc::new("/api-docs/openapi.json").path("/rapidoc"))
+            .wrap_fn(|req, srv| {
+                let started = Instant::now();
+                let res = srv.call(req);
+                async move {
+                    let res = res.await?;
+                    api::metrics::record_request(&res, started);
+                    Ok(res)
+                }
+            })
+            // Run each request in a span with its request ID, which is also returned. Failed
+            // requests are logged at `warn` for client errors, or `error` for server errors
+            .wrap_fn(|mut req, srv| {
+                let request_id = telemetry::request_id(
+                    req.headers()
+                        .get(REQUEST_ID_HEADER)
+                        .and_then(|id| id.to_str().ok()),
+                );
+                let span = tracing::info_span!(
+                    "request",
+                    request_id = %request_id,
+                    method = %req.method(),
+                    path = %req.path(),
+                    error = Empty,
+                    source = Empty
+                );
+                // Valid, as request IDs are visible ASCII
+                let request_id = HeaderValue::from_str(&request_id).unwrap();
+                req.headers_mut().insert(
+                    HeaderName::from_static(REQUEST_ID_HEADER),
+                    request_id.clone(),
+                );
+                let res = span.in_scope(|| srv.call(req));
+                async move {
+                    let mut res = res.await?;
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(REQUEST_ID_HEADER), request_id);
+                    // With the error `ApiError` recorded on the span
+                    let status = res.status().as_u16();
+                    if res.status().is_server_error() {
+                        tracing::error!(status, "failed");
+                    } else if res.status().is_client_error() {
+                        tracing::warn!(status, "failed");
+                    }
+                    Ok(res)
+                }
+                .instrument(span)
+            })
+            .wrap(Logger::new(LOG_FORMAT))
+    })
+    .listen(listener)?
+    .shutdown_timeout(config.server.shutdown_timeout_secs)
+    // Signals are handled by `shutdown`, along with the gRPC server and workers
+    .disable_signals()
+    .run();
+
+    let handle = server.handle();
+    tokio::spawn(async move {
+        shutdown.cancelled().await;
+        handle.stop(true).await;
+    });
+
+    Ok(server)
+}
@@ -1,73 +1,29 @@
-use actix_web::{middleware::Logger, web, App, HttpServer};
-
-use utoipa::OpenApi;
-use utoipa_rapidoc::RapiDoc;
-use utoipa_redoc::{Redoc, Servable};
-use utoipa_swagger_ui::SwaggerUi;
-
-pub mod api;
-pub mod db;
-pub mod env;
+use festival_tickets_core::app::App;
+use festival_tickets_core::telemetry;
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    // Setup Api
-
-    #[derive(OpenApi)]
-    #[openapi(
-        paths(
-            api::add_ticket_to_basket,
-            api::get_ticket_types,
-            api::get_ticket_durations,
-            api::purchase_order,
-            api::get_order,
-            api::get_user,
-            api::add_user_info,
-        ),
-        components(
-            schemas(
-                api::types::Order,
-                api::error::ApiError,
-                api::types::AddTicketToBasketRequest,
-                api::types::TicketType,
-                api::types::User,
-                api::types::AddUserInfoRequest,
-            )
-        ),
-        tags(
-            (name = "festival-tickets", description = "Purchase festival tickets")
-        ),
-    )]
-    struct ApiDoc;
-    let openapi = ApiDoc::openapi();
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Setup logging, level INFO unless RUST_LOG is set
+    let _telemetry = telemetry::init("festival-tickets-actix")?;
 
-    // Setup logging, level INFO
-    let env = env_logger::Env::default().default_filter_or("info");
-    env_logger::Builder::from_env(env).init();
-    let addr = ("0.0.0.0", 50051);
+    let Some(app) = App::init().await? else {
+        return Ok(());
+    };
+    let addr = app.config.server.rest_addr;
 
-    log::info!("connecting to db...");
-    let pool = db::connect_to_pool().await;
-    // Run database migrations
-    sqlx::migrate!("../migrations")
-        .run(&pool)
-        .await
-        .expect("failed to apply database migrations");
+    let listener = std::net::TcpListener::bind(addr)?;
+    println!("serving on {}", addr);
+    let res = festival_tickets_actix::server(
+        listener,
+        app.ticketing.clone(),
+        app.health.clone(),
+        app.tokens.clone(),
+        &app.config,
+        app.shutdown.clone(),
+    )?
+    .await;
 
-    println!("serving on {}:{}", addr.0, addr.1);
-    HttpServer::new(move || {
-        App::new()
-            .configure(api::configure(web::Data::new(pool.clone())))
-            // Setup OpenAPI routes.
-            // See: https://github.com/juhaku/utoipa/blob/master/examples/todo-actix/src/main.rs
-            .service(Redoc::with_url("/redoc", openapi.clone()))
-            .service(
-                SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", openapi.clone()),
-            )
-            .service(RapiDoc::new("/api-docs/openapi.json").path("/rapidoc"))
-            .wrap(Logger::default())
-    })
-    .bind(addr)?
-    .run()
-    .await
+    // Requests have drained, so the running job gets what's left until the same deadline
+    app.finish().await;
+    Ok(res?)
 }
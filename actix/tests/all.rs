@@ -1,10 +1,18 @@
+mod harness;
 use std::ops::Add;
 
-use festival_tickets_client::types::{AddTicketToBasketRequest, AddUserInfoRequest};
+use festival_tickets_client::types::{
+    AddTicketToBasketRequest, AddUserInfoRequest, ApiError, ApplyPromoCodeRequest, CheckInRequest,
+    CreateWebhookEndpointRequest, ExportFormat, OfflineCheckIn, QrCodeFormat, ScanDirection,
+    SetPurchaseLimitOverrideRequest, SyncCheckInsRequest, Ticket, UpsertPresaleRequest,
+};
+use harness::TestServer;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
 
-#[actix_web::test]
-async fn reserve_ticket() {
-    let client = festival_tickets_client::Client::new("http://localhost:50051");
+#[sqlx::test(migrations = "../migrations")]
+async fn reserve_ticket(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let client = server.client();
 
     let res = client.get_ticket_types().await.unwrap().into_inner();
     assert_eq!(res.len(), 4);
@@ -22,6 +30,7 @@ async fn reserve_ticket() {
         .add_ticket_to_basket(&AddTicketToBasketRequest {
             ticket_type_id: "chalet3".to_owned(),
             duration: 3,
+            presale_code: None,
         })
         .await
         .unwrap()
@@ -39,14 +48,16 @@ async fn reserve_ticket() {
     );
 }
 
-#[actix_web::test]
-async fn purchase_ticket() {
-    let client = festival_tickets_client::Client::new("http://localhost:50051");
+#[sqlx::test(migrations = "../migrations")]
+async fn purchase_ticket(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let client = server.client();
 
     let order = client
         .add_ticket_to_basket(&AddTicketToBasketRequest {
             ticket_type_id: "chalet3".to_owned(),
             duration: 3,
+            presale_code: None,
         })
         .await
         .unwrap()
@@ -74,11 +85,11 @@ async fn purchase_ticket() {
                 festival_tickets_client::types::ApiError::FailedPrecondition(p) => {
                     println!("error: {}", p);
                 }
-                _ => assert!(false, "expected res to be pre-condition err!"),
+                _ => panic!("expected res to be pre-condition err!"),
             },
-            _ => assert!(false, "expected res to be ApiError err!"),
+            _ => panic!("expected res to be ApiError err!"),
         },
-        _ => assert!(false, "expected res to be err!"),
+        _ => panic!("expected res to be err!"),
     }
 
     // Add user to order
@@ -104,4 +115,737 @@ async fn purchase_ticket() {
     // Purchase order
     let order = client.purchase_order(&order.id).await.unwrap().into_inner();
     assert!(order.purchased_at.is_some());
+
+    // Signed ticket issued on purchase
+    let tickets = client.get_tickets(&order.id).await.unwrap().into_inner();
+    assert_eq!(tickets.len(), 1);
+    assert_eq!(tickets[0].holder_name, "Oscar");
+    assert!(tickets[0].credential.starts_with("FT1."));
+
+    let res = client
+        .get_ticket_qr_code(&order.id, Some(QrCodeFormat::Png))
+        .await
+        .unwrap();
+    assert_eq!(res.headers()["content-type"], "image/png");
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn apply_promo_code(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let client = server.client();
+
+    let order = client
+        .add_ticket_to_basket(&AddTicketToBasketRequest {
+            ticket_type_id: "chalet3".to_owned(),
+            duration: 3,
+            presale_code: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(order.discount, 0.0);
+
+    // Seeded code restricted to 4-day hotel tickets
+    let res = client
+        .apply_promo_code(
+            &order.id,
+            &ApplyPromoCodeRequest {
+                promo_code: "TESTHOTEL".to_string(),
+            },
+        )
+        .await;
+    assert!(res.is_err());
+
+    let discounted = client
+        .apply_promo_code(
+            &order.id,
+            &ApplyPromoCodeRequest {
+                promo_code: "TEST10PCT".to_string(),
+            },
+        )
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(discounted.promo_code.as_deref(), Some("TEST10PCT"));
+    assert!(discounted.discount > 0.0);
+    assert_eq!(discounted.price, order.price - discounted.discount);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn import_presale_codes(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let suffix = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+    let presale_id = format!("test-presale-{}", suffix);
+
+    // Admin API requires the admin token
+    let client = server.client();
+    match client.get_presale_codes(&presale_id).await {
+        Err(festival_tickets_client::Error::ErrorResponse(e)) => {
+            assert!(matches!(e.into_inner(), ApiError::Unauthorized(_)))
+        }
+        _ => panic!("expected unauthorized error"),
+    }
+
+    let client = server.admin_client();
+
+    // Presale in the future, so it doesn't affect the other tests
+    let starts_at = chrono::Utc::now().add(chrono::Duration::days(365));
+    let presale = client
+        .upsert_presale(
+            &presale_id,
+            &UpsertPresaleRequest {
+                starts_at,
+                ends_at: starts_at.add(chrono::Duration::days(1)),
+            },
+        )
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(presale.id, presale_id);
+
+    let csv =
+        format!("code,email,ticket_limit\nCODE-{suffix},,4\n,fan-{suffix}@example.com,\n,,2\n");
+    let report = client
+        .import_presale_codes(&presale_id, 2, csv)
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(report.imported, 2);
+    assert_eq!(report.rejected.len(), 1);
+
+    let codes = client
+        .get_presale_codes(&presale_id)
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(codes.len(), 2);
+    assert_eq!(codes[0].code, format!("code-{}", suffix));
+    assert_eq!(codes[0].ticket_limit, 4);
+    assert_eq!(codes[1].email, Some(format!("fan-{}@example.com", suffix)));
+    assert!(codes.iter().all(|c| c.redeemed == 0 && c.purchased == 0));
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn purchase_limit_override(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let client = server.client();
+    let admin = server.admin_client();
+
+    let suffix = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+    let email = format!("limit-{}@example.com", suffix);
+
+    let limit_override = admin
+        .set_purchase_limit_override(
+            &email.to_uppercase(),
+            &SetPurchaseLimitOverrideRequest {
+                ticket_limit: 1,
+                note: Some("test".to_string()),
+            },
+        )
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(limit_override.email, email);
+
+    let mut order_ids = vec![];
+    for _ in 0..2 {
+        let order = client
+            .add_ticket_to_basket(&AddTicketToBasketRequest {
+                ticket_type_id: "chalet3".to_owned(),
+                duration: 3,
+                presale_code: None,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        order_ids.push(order.id);
+    }
+
+    let user_info = |order_id: &uuid::Uuid| AddUserInfoRequest {
+        address: format!("{} Limit Road", suffix),
+        // Sub-addressed variant of the same mailbox counts against the same limit
+        email: email.replace('@', &format!("+{}@", order_id)),
+        name: "Limit".to_string(),
+    };
+
+    client
+        .add_user_info(&order_ids[0], &user_info(&order_ids[0]))
+        .await
+        .unwrap();
+
+    match client
+        .add_user_info(&order_ids[1], &user_info(&order_ids[1]))
+        .await
+    {
+        Err(festival_tickets_client::Error::ErrorResponse(e)) => {
+            assert!(matches!(e.into_inner(), ApiError::FailedPrecondition(_)))
+        }
+        _ => panic!("expected purchase limit error"),
+    }
+
+    // Default limit applies again once the override is removed
+    admin.delete_purchase_limit_override(&email).await.unwrap();
+
+    client
+        .add_user_info(&order_ids[1], &user_info(&order_ids[1]))
+        .await
+        .unwrap();
+}
+
+/// Reserve, fill in and purchase a 3 day ticket for a new customer
+async fn purchase_new_ticket(server: &TestServer) -> Ticket {
+    let client = server.client();
+    let suffix = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+
+    let order = client
+        .add_ticket_to_basket(&AddTicketToBasketRequest {
+            ticket_type_id: "chalet3".to_owned(),
+            duration: 3,
+            presale_code: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    client
+        .add_user_info(
+            &order.id,
+            &AddUserInfoRequest {
+                address: format!("{} Gate Road", suffix),
+                email: format!("gate-{}@example.com", suffix),
+                name: "Gate".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+    client.purchase_order(&order.id).await.unwrap();
+
+    client
+        .get_tickets(&order.id)
+        .await
+        .unwrap()
+        .into_inner()
+        .pop()
+        .unwrap()
+}
+
+fn expect_error(
+    res: Result<impl std::fmt::Debug, festival_tickets_client::Error<ApiError>>,
+) -> ApiError {
+    match res {
+        Err(festival_tickets_client::Error::ErrorResponse(e)) => e.into_inner(),
+        res => panic!("expected error response, got {:?}", res),
+    }
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn check_in_ticket(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let client = server.scanner_client();
+    let ticket = purchase_new_ticket(&server).await;
+
+    let check_in = |direction: ScanDirection| CheckInRequest {
+        credential: ticket.credential.clone(),
+        direction,
+        gate: "north".to_string(),
+    };
+
+    // Gate API requires the scanner token
+    let res = server
+        .client()
+        .check_in(&check_in(ScanDirection::Entry))
+        .await;
+    assert!(matches!(expect_error(res), ApiError::Unauthorized(_)));
+
+    let config = client.get_scanner_config().await.unwrap().into_inner();
+    assert!(!config.verifying_key.is_empty());
+    assert_eq!(config.festival_utc_offset, "+00:00");
+
+    let today = chrono::Utc::now().date_naive();
+    let festival_day = (today - config.festival_start_date).num_days() + 1;
+
+    let res = client.check_in(&check_in(ScanDirection::Entry)).await;
+    if !(1..=3).contains(&festival_day) {
+        // Outside the festival, so the ticket isn't valid today
+        assert!(matches!(expect_error(res), ApiError::FailedPrecondition(_)));
+        return;
+    }
+
+    let res = res.unwrap().into_inner();
+    assert_eq!(res.check_in.festival_day, festival_day as i32);
+    assert_eq!(res.ticket.unwrap().holder_name, "Gate");
+
+    // Duplicate entry is rejected
+    let res = client.check_in(&check_in(ScanDirection::Entry)).await;
+    assert!(matches!(expect_error(res), ApiError::FailedPrecondition(_)));
+
+    client
+        .check_in(&check_in(ScanDirection::Exit))
+        .await
+        .unwrap();
+    client
+        .check_in(&check_in(ScanDirection::Entry))
+        .await
+        .unwrap();
+
+    // Forged credentials are rejected
+    let res = client
+        .check_in(&CheckInRequest {
+            credential: ticket.credential.replace("FT1.", "FT1.e30"),
+            ..check_in(ScanDirection::Entry)
+        })
+        .await;
+    assert!(matches!(expect_error(res), ApiError::FailedPrecondition(_)));
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn sync_offline_check_ins(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let client = server.scanner_client();
+    let ticket = purchase_new_ticket(&server).await;
+
+    let config = client.get_scanner_config().await.unwrap().into_inner();
+    let scanned_at = |day: u64, hour: u32| {
+        (config.festival_start_date + chrono::Days::new(day - 1))
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+            .and_utc()
+    };
+
+    let scan = |direction: ScanDirection, gate: &str, scanned_at| OfflineCheckIn {
+        client_ref: uuid::Uuid::new_v4(),
+        credential: ticket.credential.clone(),
+        direction,
+        gate: gate.to_string(),
+        scanned_at,
+    };
+    let check_ins = vec![
+        scan(ScanDirection::Entry, "north", scanned_at(2, 10)),
+        scan(ScanDirection::Exit, "north", scanned_at(2, 18)),
+        // 3 day ticket isn't valid on day 4
+        scan(ScanDirection::Entry, "north", scanned_at(4, 10)),
+        OfflineCheckIn {
+            credential: "FT1.forged.credential".to_string(),
+            ..scan(ScanDirection::Entry, "north", scanned_at(2, 10))
+        },
+    ];
+
+    let res = client
+        .sync_check_ins(&SyncCheckInsRequest {
+            check_ins: check_ins.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(res.check_ins.len(), 3);
+    assert_eq!(res.rejected.len(), 1);
+    assert!(res.check_ins.iter().all(|c| c.offline));
+    assert_eq!(res.check_ins[0].conflict, None);
+    assert_eq!(res.check_ins[1].conflict, None);
+    assert!(res.check_ins[2].conflict.is_some());
+
+    // Same ticket let in earlier at another gate, by a scanner which synced later
+    let late = client
+        .sync_check_ins(&SyncCheckInsRequest {
+            check_ins: vec![scan(ScanDirection::Entry, "south", scanned_at(2, 9))],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(late.check_ins[0].conflict.is_some());
+
+    // Syncing again doesn't record the scans twice, but shows the entry it duplicated
+    let again = client
+        .sync_check_ins(&SyncCheckInsRequest { check_ins })
+        .await
+        .unwrap()
+        .into_inner();
+    let ids = |check_ins: &[festival_tickets_client::types::CheckIn]| {
+        check_ins.iter().map(|c| c.id).collect::<Vec<_>>()
+    };
+    assert_eq!(ids(&again.check_ins), ids(&res.check_ins));
+    assert!(again.check_ins[0].conflict.is_some());
+    assert_eq!(again.check_ins[1].conflict, None);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn inspect_background_jobs(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let admin = server.admin_client();
+
+    // Scheduled by the job runner once it starts
+    let mut jobs = vec![];
+    for _ in 0..50 {
+        jobs = admin
+            .get_jobs(Some("expire_reservations"), None, None)
+            .await
+            .unwrap()
+            .into_inner();
+        if !jobs.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(!jobs.is_empty());
+    assert!(jobs
+        .iter()
+        .all(|j| j.kind == "expire_reservations" && j.state != "dead"));
+
+    let err = expect_error(admin.retry_job(-1).await);
+    assert!(matches!(err, ApiError::FailedPrecondition(_)));
+
+    let client = server.client();
+    let err = expect_error(client.get_jobs(None, None, None).await);
+    assert!(matches!(err, ApiError::Unauthorized(_)));
+}
+
+/// Accept webhook deliveries on a random local port, responding 200. Returns the url, and the
+/// headers and body of each delivery.
+fn webhook_receiver() -> (
+    String,
+    tokio::sync::mpsc::UnboundedReceiver<(String, String)>,
+) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                headers.push_str(&line.to_lowercase());
+            }
+            let length = headers
+                .lines()
+                .find_map(|l| l.strip_prefix("content-length: "))
+                .map(|l| l.trim().parse().unwrap())
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                .unwrap();
+            let _ = tx.send((headers, String::from_utf8(body).unwrap()));
+        }
+    });
+    (url, rx)
+}
+
+fn check_webhook_signature(secret: &str, headers: &str, body: &str) {
+    use hmac::Mac;
+
+    let signature = headers
+        .lines()
+        .find_map(|l| l.strip_prefix("x-festival-signature: "))
+        .expect("signature header")
+        .trim();
+    let (timestamp, signature) = signature
+        .strip_prefix("t=")
+        .and_then(|s| s.split_once(",v1="))
+        .unwrap();
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    mac.verify_slice(&hex::decode(signature).unwrap()).unwrap();
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn deliver_webhooks(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let admin = server.admin_client();
+    let (url, mut deliveries) = webhook_receiver();
+
+    let err = expect_error(
+        admin
+            .create_webhook_endpoint(&CreateWebhookEndpointRequest {
+                url: url.clone(),
+                events: vec!["order.shipped".to_string()],
+            })
+            .await,
+    );
+    assert!(matches!(err, ApiError::FailedPrecondition(_)));
+
+    let res = admin
+        .create_webhook_endpoint(&CreateWebhookEndpointRequest {
+            url,
+            events: vec!["order.purchased".to_string()],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    let delivery = admin
+        .test_webhook_endpoint(&res.endpoint.id)
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(delivery.event, "webhook.test");
+    assert_eq!(delivery.response_status, Some(200));
+    assert!(delivery.delivered_at.is_some());
+
+    let (headers, body) = deliveries.recv().await.unwrap();
+    assert!(headers.contains("x-festival-event: webhook.test"));
+    assert_eq!(body, delivery.payload);
+    check_webhook_signature(&res.secret, &headers, &body);
+
+    // Purchases are delivered in the background
+    let ticket = purchase_new_ticket(&server).await;
+    let (headers, body) = loop {
+        let (headers, body) =
+            tokio::time::timeout(std::time::Duration::from_secs(10), deliveries.recv())
+                .await
+                .expect("order.purchased delivery")
+                .unwrap();
+        if body.contains(&ticket.order_id.to_string()) {
+            break (headers, body);
+        }
+    };
+    assert!(headers.contains("x-festival-event: order.purchased"));
+    check_webhook_signature(&res.secret, &headers, &body);
+
+    let log = admin
+        .get_webhook_deliveries(&res.endpoint.id, None)
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(log.iter().any(|d| d.event == "order.purchased"));
+
+    admin
+        .delete_webhook_endpoint(&res.endpoint.id)
+        .await
+        .unwrap();
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn export_orders(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    use futures::TryStreamExt;
+
+    let admin = server.admin_client();
+    let started = chrono::Utc::now().add(chrono::Duration::seconds(-1));
+    let ticket = purchase_new_ticket(&server).await;
+
+    let export = |format, purchased_from: chrono::DateTime<chrono::Utc>| {
+        let admin = admin.clone();
+        async move {
+            let res = admin
+                .export_orders(
+                    Some(3),
+                    Some(format),
+                    Some(&purchased_from),
+                    None,
+                    Some("chalet3"),
+                )
+                .await
+                .unwrap();
+            let data: Vec<u8> = res
+                .into_inner()
+                .into_inner()
+                .map_ok(|chunk| chunk.to_vec())
+                .try_concat()
+                .await
+                .unwrap();
+            String::from_utf8(data).unwrap()
+        }
+    };
+
+    let csv = export(ExportFormat::Csv, started).await;
+    let mut lines = csv.lines();
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("order_id,ticket_type_id,"));
+    let row = lines
+        .find(|l| l.starts_with(&ticket.order_id.to_string()))
+        .expect("purchased order is exported");
+    assert!(row.contains(",chalet3,"));
+    assert!(row.contains("@example.com,"));
+
+    let ndjson = export(ExportFormat::Ndjson, started).await;
+    let rows: Vec<serde_json::Value> = ndjson
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let row = rows
+        .iter()
+        .find(|r| r["order_id"] == ticket.order_id.to_string().as_str())
+        .expect("purchased order is exported");
+    assert_eq!(row["duration"], 3);
+    assert_eq!(row["holder_name"], "Gate");
+
+    // Only the header, as nothing has been purchased since
+    let csv = export(
+        ExportFormat::Csv,
+        chrono::Utc::now().add(chrono::Duration::hours(1)),
+    )
+    .await;
+    assert_eq!(csv.lines().count(), 1);
+
+    let client = server.client();
+    let res = client.export_orders(None, None, None, None, None).await;
+    let err = expect_error(res.map(|res| res.status()));
+    assert!(matches!(err, ApiError::Unauthorized(_)));
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn import_comp_tickets(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let client = server.client();
+    let admin = server.admin_client();
+    let suffix = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+    let csv = format!(
+        "ticket_type_id,duration,name,email,address,allocation
+chalet3,3,Crew {suffix},crew-{suffix}@example.com,1 Crew Lane,
+chalet3,4,Artist {suffix},artist-{suffix}@example.com,2 Stage Road,artist
+nope,3,Nobody,nobody@example.com,3 Nowhere,
+chalet3,5,Nobody,nobody@example.com,3 Nowhere,
+"
+    );
+
+    let report = admin
+        .import_comp_tickets(Some("crew"), Some(true), csv.clone())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(report.imported, 2);
+    assert!(report.order_ids.is_empty());
+    assert_eq!(
+        report.rejected,
+        vec![
+            "line 4: unknown ticket type nope",
+            "line 5: invalid duration 5"
+        ]
+    );
+
+    let report = admin
+        .import_comp_tickets(Some("crew"), None, csv)
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(report.imported, 2);
+    assert_eq!(report.order_ids.len(), 2);
+    assert_eq!(report.rejected.len(), 2);
+
+    for order_id in report.order_ids {
+        let order = client.get_order(&order_id).await.unwrap().into_inner();
+        assert_eq!(order.price, 0.0);
+        assert!(order.purchased_at.is_some());
+
+        let tickets = client.get_tickets(&order_id).await.unwrap().into_inner();
+        assert_eq!(tickets.len(), 1);
+    }
+
+    let body = server.metrics().await;
+    assert!(body.contains(
+        r#"festival_orders_purchased_total{comp="true",duration="4",ticket_type="chalet3"}"#
+    ));
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn health_checks(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    // Liveness doesn't depend on the database
+    for (path, database) in [
+        ("healthz", serde_json::Value::Null),
+        ("readyz", true.into()),
+    ] {
+        let res = reqwest::get(server.url(&format!("/{}", path)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200, "{}", path);
+        let report: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(report["database"], database, "{}", path);
+        assert_eq!(report["workers"], true, "{}", path);
+    }
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn metrics(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let client = server.client();
+    client
+        .add_ticket_to_basket(&AddTicketToBasketRequest {
+            ticket_type_id: "chalet3".to_string(),
+            duration: 3,
+            presale_code: None,
+        })
+        .await
+        .unwrap();
+
+    // Only scrapers with the token can read metrics
+    let res = reqwest::get(server.url("/metrics")).await.unwrap();
+    assert_eq!(res.status(), 401);
+
+    let body = server.metrics().await;
+    assert!(body.contains(
+        r#"festival_requests_total{api="rest",route="/tickets/add-to-basket",status="200"}"#
+    ));
+    assert!(body.contains(r#"festival_orders_reserved_total{duration="3",ticket_type="chalet3"}"#));
+    assert!(body.contains(r#"festival_inventory_remaining{duration="3"}"#));
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn request_ids(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let client = reqwest::Client::new();
+    let res = client
+        .get(server.url("/tickets/types"))
+        .header("x-request-id", "test-request-1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.headers()["x-request-id"], "test-request-1");
+
+    // Generated if not sent, and returned with errors too
+    let res = client
+        .get(server.url("/orders/not-a-uuid"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+    assert_eq!(res.headers()["x-request-id"].len(), 36);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn catalogue_is_cacheable(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let client = reqwest::Client::new();
+    let res = client
+        .get(server.url("/tickets/types"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["cache-control"], "public, max-age=60");
+    let etag = res.headers()["etag"].clone();
+
+    let res = client
+        .get(server.url("/tickets/types"))
+        .header("if-none-match", etag.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 304);
+    assert_eq!(res.headers()["etag"], etag);
+
+    // Different body, different tag
+    let res = client
+        .get(server.url("/tickets/durations/chalet3"))
+        .header("if-none-match", etag.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_ne!(res.headers()["etag"], etag);
+    assert_eq!(res.headers()["cache-control"], "public, max-age=1");
 }
@@ -0,0 +1,142 @@
+//! Runs the REST server in-process for each test, on a random port, against the database
+//! `sqlx::test` created and migrated for it. Background jobs, order stats and caches are started
+//! by `App::start`, as in the server, and run until the `TestServer` is dropped.
+
+use std::net::TcpListener;
+
+use festival_tickets_client::Client;
+use festival_tickets_core::app::App;
+use festival_tickets_core::config::{ApiTokens, Config};
+use festival_tickets_core::db::DbPool;
+use festival_tickets_core::metrics;
+use festival_tickets_core::shutdown::CancellationToken;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::Executor;
+
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+pub const SCANNER_TOKEN: &str = "test-scanner-token";
+pub const METRICS_TOKEN: &str = "test-metrics-token";
+
+const SIGNING_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+/// Enough for the connections background tasks hold, and a few requests at once
+const MAX_CONNECTIONS: u32 = 10;
+
+pub struct TestServer {
+    pool: DbPool,
+    base_url: String,
+    shutdown: CancellationToken,
+}
+
+impl TestServer {
+    /// Seed the test database, then start the server. Requests are accepted once this returns
+    pub async fn start(pool_options: PgPoolOptions, options: PgConnectOptions) -> Self {
+        let pool = pool_options
+            .max_connections(MAX_CONNECTIONS)
+            .connect_with(options)
+            .await
+            .unwrap();
+        // As `db::connect_to_pool` would, for the pool metrics
+        metrics::observe_pool(&pool);
+        pool.execute(include_str!("../../../seed_db.sql"))
+            .await
+            .unwrap();
+
+        let mut config = Config::default();
+        config.reservations.max_tickets_per_customer = Some(4);
+        // So jobs queued by a request run promptly
+        config.workers.poll_interval_ms = 100;
+        // Workers drop connections as soon as the server stops, rather than waiting for clients
+        config.server.shutdown_timeout_secs = 0;
+        config.tickets.signing_key = SIGNING_KEY.to_string();
+        // Festival starts today, so tickets can be checked in
+        config.tickets.festival_start_date = Some(chrono::Utc::now().date_naive());
+
+        let tokens = ApiTokens {
+            admin: Some(ADMIN_TOKEN.to_string()),
+            scanner: Some(SCANNER_TOKEN.to_string()),
+            metrics: Some(METRICS_TOKEN.to_string()),
+        };
+        let app = App::start(
+            pool.clone(),
+            None,
+            None,
+            tokens,
+            config,
+            CancellationToken::new(),
+        )
+        .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = festival_tickets_actix::server(
+            listener,
+            app.ticketing.clone(),
+            app.health.clone(),
+            app.tokens.clone(),
+            &app.config,
+            app.shutdown.clone(),
+        )
+        .unwrap();
+        tokio::spawn(server);
+
+        Self {
+            pool,
+            base_url,
+            shutdown: app.shutdown.clone(),
+        }
+    }
+
+    /// Url of `path` on the server, for requests the client doesn't cover
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    pub fn client(&self) -> Client {
+        Client::new(&self.base_url)
+    }
+
+    /// Metrics, scraped with the metrics token
+    pub async fn metrics(&self) -> String {
+        let res = reqwest::Client::new()
+            .get(self.url("/metrics"))
+            .bearer_auth(METRICS_TOKEN)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        res.text().await.unwrap()
+    }
+
+    pub fn admin_client(&self) -> Client {
+        self.client_with_token(ADMIN_TOKEN)
+    }
+
+    pub fn scanner_client(&self) -> Client {
+        self.client_with_token(SCANNER_TOKEN)
+    }
+
+    /// Client sending `token` as a bearer token
+    fn client_with_token(&self, token: &str) -> Client {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+        Client::new_with_client(&self.base_url, client)
+    }
+}
+
+impl Drop for TestServer {
+    /// Stop the server and background tasks, and close the pool's connections once they're
+    /// returned, so `sqlx::test` can drop the test database
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+        let pool = self.pool.clone();
+        tokio::spawn(async move { pool.close().await });
+    }
+}
@@ -1,3 +1,9 @@
+#![allow(
+    clippy::needless_lifetimes,
+    clippy::to_string_trait_impl,
+    clippy::unnecessary_to_owned,
+    clippy::vec_init_then_push
+)]
 #[allow(unused_imports)]
 use progenitor_client::{encode_path, RequestBuilderExt};
 pub use progenitor_client::{ByteStream, Error, ResponseValue};
@@ -11,6 +17,9 @@ pub mod types {
     pub struct AddTicketToBasketRequest {
         ///Duration in days
         pub duration: i32,
+        ///Required while a presale is running
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub presale_code: Option<String>,
         pub ticket_type_id: String,
     }
 
@@ -38,6 +47,7 @@ pub mod types {
         DbExecutionError(String),
         FailedPrecondition(String),
         NotFound(String),
+        Unauthorized(String),
         Unknown,
     }
 
@@ -47,12 +57,196 @@ pub mod types {
         }
     }
 
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct ApplyPromoCodeRequest {
+        pub promo_code: String,
+    }
+
+    impl From<&ApplyPromoCodeRequest> for ApplyPromoCodeRequest {
+        fn from(value: &ApplyPromoCodeRequest) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct CheckIn {
+        ///Why an offline scan broke the entry rules, if it did
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub conflict: Option<String>,
+        ///entry or exit
+        pub direction: String,
+        ///Day of the festival, from 1
+        pub festival_day: i32,
+        pub gate: String,
+        pub id: i64,
+        ///Scanned offline and synced later
+        pub offline: bool,
+        pub order_id: uuid::Uuid,
+        pub scanned_at: chrono::DateTime<chrono::offset::Utc>,
+    }
+
+    impl From<&CheckIn> for CheckIn {
+        fn from(value: &CheckIn) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct CheckInRequest {
+        pub credential: String,
+        pub direction: ScanDirection,
+        pub gate: String,
+    }
+
+    impl From<&CheckInRequest> for CheckInRequest {
+        fn from(value: &CheckInRequest) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct CheckInResponse {
+        pub check_in: CheckIn,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ticket: Option<Ticket>,
+    }
+
+    impl From<&CheckInResponse> for CheckInResponse {
+        fn from(value: &CheckInResponse) -> Self {
+            value.clone()
+        }
+    }
+
+    ///Result of a comp ticket import
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct CompImportReport {
+        pub imported: i32,
+        ///Orders created. Empty for dry runs.
+        pub order_ids: Vec<uuid::Uuid>,
+        ///Rows which weren't imported, and why
+        pub rejected: Vec<String>,
+    }
+
+    impl From<&CompImportReport> for CompImportReport {
+        fn from(value: &CompImportReport) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct CreateWebhookEndpointRequest {
+        pub events: Vec<String>,
+        pub url: String,
+    }
+
+    impl From<&CreateWebhookEndpointRequest> for CreateWebhookEndpointRequest {
+        fn from(value: &CreateWebhookEndpointRequest) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct CreateWebhookEndpointResponse {
+        pub endpoint: WebhookEndpoint,
+        ///Deliveries have an `X-Festival-Signature: t=<unix
+        /// timestamp>,v1=<signature>` header, where signature is the hex
+        /// HMAC-SHA256 of `<timestamp>.<body>` with this secret
+        pub secret: String,
+    }
+
+    impl From<&CreateWebhookEndpointResponse> for CreateWebhookEndpointResponse {
+        fn from(value: &CreateWebhookEndpointResponse) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+    pub enum ExportFormat {
+        #[serde(rename = "csv")]
+        Csv,
+        #[serde(rename = "ndjson")]
+        Ndjson,
+    }
+
+    impl From<&ExportFormat> for ExportFormat {
+        fn from(value: &ExportFormat) -> Self {
+            *value
+        }
+    }
+
+    impl ToString for ExportFormat {
+        fn to_string(&self) -> String {
+            match *self {
+                Self::Csv => "csv".to_string(),
+                Self::Ndjson => "ndjson".to_string(),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct ImportReport {
+        pub imported: i32,
+        ///Rows which weren't imported, and why
+        pub rejected: Vec<String>,
+    }
+
+    impl From<&ImportReport> for ImportReport {
+        fn from(value: &ImportReport) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct Job {
+        pub attempts: i32,
+        pub created_at: chrono::DateTime<chrono::offset::Utc>,
+        pub id: i64,
+        ///i.e. send_email
+        pub kind: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub last_error: Option<String>,
+        pub max_attempts: i32,
+        ///JSON
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub payload: Option<String>,
+        pub run_after: chrono::DateTime<chrono::offset::Utc>,
+        ///pending, running, succeeded or dead
+        pub state: String,
+        pub updated_at: chrono::DateTime<chrono::offset::Utc>,
+    }
+
+    impl From<&Job> for Job {
+        fn from(value: &Job) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct OfflineCheckIn {
+        ///Generated by the scanner, so the same scan is only recorded once
+        pub client_ref: uuid::Uuid,
+        pub credential: String,
+        pub direction: ScanDirection,
+        pub gate: String,
+        pub scanned_at: chrono::DateTime<chrono::offset::Utc>,
+    }
+
+    impl From<&OfflineCheckIn> for OfflineCheckIn {
+        fn from(value: &OfflineCheckIn) -> Self {
+            value.clone()
+        }
+    }
+
     #[derive(Clone, Debug, Deserialize, Serialize)]
     pub struct Order {
+        ///Discount from applied promo code, already subtracted from price
+        pub discount: f64,
         pub duration: i32,
         pub id: uuid::Uuid,
         pub price: f64,
         #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub promo_code: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
         pub purchased_at: Option<chrono::DateTime<chrono::offset::Utc>>,
         pub reserved_until: chrono::DateTime<chrono::offset::Utc>,
         pub ticket_type_id: String,
@@ -66,6 +260,172 @@ pub mod types {
         }
     }
 
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct Presale {
+        pub ends_at: chrono::DateTime<chrono::offset::Utc>,
+        pub id: String,
+        pub starts_at: chrono::DateTime<chrono::offset::Utc>,
+    }
+
+    impl From<&Presale> for Presale {
+        fn from(value: &Presale) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct PresaleCode {
+        pub code: String,
+        ///Allow-listed email, which the order's user info must match
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub email: Option<String>,
+        pub presale_id: String,
+        pub purchased: i32,
+        ///Tickets reserved or purchased with this code
+        pub redeemed: i32,
+        pub ticket_limit: i32,
+    }
+
+    impl From<&PresaleCode> for PresaleCode {
+        fn from(value: &PresaleCode) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct PurchaseLimitOverride {
+        ///Normalised email
+        pub email: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub note: Option<String>,
+        pub ticket_limit: i32,
+    }
+
+    impl From<&PurchaseLimitOverride> for PurchaseLimitOverride {
+        fn from(value: &PurchaseLimitOverride) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+    pub enum QrCodeFormat {
+        #[serde(rename = "png")]
+        Png,
+        #[serde(rename = "svg")]
+        Svg,
+    }
+
+    impl From<&QrCodeFormat> for QrCodeFormat {
+        fn from(value: &QrCodeFormat) -> Self {
+            *value
+        }
+    }
+
+    impl ToString for QrCodeFormat {
+        fn to_string(&self) -> String {
+            match *self {
+                Self::Png => "png".to_string(),
+                Self::Svg => "svg".to_string(),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+    pub enum ScanDirection {
+        #[serde(rename = "entry")]
+        Entry,
+        #[serde(rename = "exit")]
+        Exit,
+    }
+
+    impl From<&ScanDirection> for ScanDirection {
+        fn from(value: &ScanDirection) -> Self {
+            *value
+        }
+    }
+
+    impl ToString for ScanDirection {
+        fn to_string(&self) -> String {
+            match *self {
+                Self::Entry => "entry".to_string(),
+                Self::Exit => "exit".to_string(),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct ScannerConfig {
+        ///Whether tickets may leave and re-enter on the same day
+        pub allow_reentry: bool,
+        ///Tickets are valid from this day, for their duration in days
+        pub festival_start_date: chrono::naive::NaiveDate,
+        ///Offset from UTC of the festival's local time, i.e. `+01:00`, which festival days follow
+        pub festival_utc_offset: String,
+        ///Base64 encoded Ed25519 public key, for verifying ticket credentials
+        pub verifying_key: String,
+    }
+
+    impl From<&ScannerConfig> for ScannerConfig {
+        fn from(value: &ScannerConfig) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct SetPurchaseLimitOverrideRequest {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub note: Option<String>,
+        pub ticket_limit: i32,
+    }
+
+    impl From<&SetPurchaseLimitOverrideRequest> for SetPurchaseLimitOverrideRequest {
+        fn from(value: &SetPurchaseLimitOverrideRequest) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct SyncCheckInsRequest {
+        pub check_ins: Vec<OfflineCheckIn>,
+    }
+
+    impl From<&SyncCheckInsRequest> for SyncCheckInsRequest {
+        fn from(value: &SyncCheckInsRequest) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct SyncCheckInsResponse {
+        pub check_ins: Vec<CheckIn>,
+        ///Scans which couldn't be recorded, and why
+        pub rejected: Vec<String>,
+    }
+
+    impl From<&SyncCheckInsResponse> for SyncCheckInsResponse {
+        fn from(value: &SyncCheckInsResponse) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct Ticket {
+        ///Signed credential presented at the gate,
+        /// `FT1.<payload>.<signature>`
+        pub credential: String,
+        pub duration: i32,
+        pub holder_name: String,
+        pub issued_at: chrono::DateTime<chrono::offset::Utc>,
+        pub order_id: uuid::Uuid,
+        pub ticket_type_id: String,
+    }
+
+    impl From<&Ticket> for Ticket {
+        fn from(value: &Ticket) -> Self {
+            value.clone()
+        }
+    }
+
     #[derive(Clone, Debug, Deserialize, Serialize)]
     pub struct TicketType {
         pub display: String,
@@ -79,6 +439,18 @@ pub mod types {
         }
     }
 
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct UpsertPresaleRequest {
+        pub ends_at: chrono::DateTime<chrono::offset::Utc>,
+        pub starts_at: chrono::DateTime<chrono::offset::Utc>,
+    }
+
+    impl From<&UpsertPresaleRequest> for UpsertPresaleRequest {
+        fn from(value: &UpsertPresaleRequest) -> Self {
+            value.clone()
+        }
+    }
+
     #[derive(Clone, Debug, Deserialize, Serialize)]
     pub struct User {
         pub address: String,
@@ -93,6 +465,47 @@ pub mod types {
             value.clone()
         }
     }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct WebhookDelivery {
+        pub attempts: i32,
+        pub created_at: chrono::DateTime<chrono::offset::Utc>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub delivered_at: Option<chrono::DateTime<chrono::offset::Utc>>,
+        pub endpoint_id: uuid::Uuid,
+        pub event: String,
+        ///Same for every endpoint receiving the event
+        pub event_id: uuid::Uuid,
+        pub id: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub last_error: Option<String>,
+        ///JSON body which was posted
+        pub payload: String,
+        ///Status of the last response, if there was one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub response_status: Option<i32>,
+    }
+
+    impl From<&WebhookDelivery> for WebhookDelivery {
+        fn from(value: &WebhookDelivery) -> Self {
+            value.clone()
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct WebhookEndpoint {
+        pub created_at: chrono::DateTime<chrono::offset::Utc>,
+        ///order.reserved, order.purchased, order.expired or order.refunded (not sent yet)
+        pub events: Vec<String>,
+        pub id: uuid::Uuid,
+        pub url: String,
+    }
+
+    impl From<&WebhookEndpoint> for WebhookEndpoint {
+        fn from(value: &WebhookEndpoint) -> Self {
+            value.clone()
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -158,84 +571,772 @@ impl Client {
 }
 
 impl Client {
-    ///Retrieve an order by ID
+    ///Bulk import complimentary tickets, i.e. for artists and crew, as
+    /// purchased zero-price orders
     ///
-    ///Retrieve an order by ID
+    ///Bulk import complimentary tickets, i.e. for artists and crew, as
+    /// purchased zero-price orders. The CSV body has header
+    /// `ticket_type_id,duration,name,email,address,allocation`.
     ///
-    ///Sends a `GET` request to `/orders/{order_id}`
-    pub async fn get_order<'a>(
+    ///Sends a `POST` request to `/admin/comp-tickets`
+    ///
+    ///Arguments:
+    /// - `allocation`: Used for rows without an allocation, i.e. crew
+    /// - `dry_run`: Check the rows without saving anything
+    /// - `body`
+    pub async fn import_comp_tickets<'a>(
         &'a self,
-        order_id: &'a uuid::Uuid,
-    ) -> Result<ResponseValue<types::Order>, Error<types::ApiError>> {
-        let url = format!(
-            "{}/orders/{}",
-            self.baseurl,
-            encode_path(&order_id.to_string()),
-        );
+        allocation: Option<&'a str>,
+        dry_run: Option<bool>,
+        body: String,
+    ) -> Result<ResponseValue<types::CompImportReport>, Error<types::ApiError>> {
+        let url = format!("{}/admin/comp-tickets", self.baseurl,);
+        let mut query = Vec::with_capacity(2usize);
+        if let Some(v) = &allocation {
+            query.push(("allocation", v.to_string()));
+        }
+        if let Some(v) = &dry_run {
+            query.push(("dry_run", v.to_string()));
+        }
         let request = self
             .client
-            .get(url)
+            .post(url)
             .header(
                 reqwest::header::ACCEPT,
                 reqwest::header::HeaderValue::from_static("application/json"),
             )
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                reqwest::header::HeaderValue::from_static("text/csv"),
+            )
+            .body(body)
+            .query(&query)
             .build()?;
         let result = self.client.execute(request).await;
         let response = result?;
         match response.status().as_u16() {
             200u16 => ResponseValue::from_response(response).await,
-            404u16 => Err(Error::ErrorResponse(
+            401u16 => Err(Error::ErrorResponse(
                 ResponseValue::from_response(response).await?,
             )),
             _ => Err(Error::UnexpectedResponse(response)),
         }
     }
 
-    ///Add user info to order
+    ///Purchased orders with their attendees, oldest purchase first
     ///
-    ///Add user info to order
+    ///Purchased orders with their attendees, oldest purchase first. The file
+    /// is streamed in chunks.
     ///
-    ///Sends a `POST` request to `/orders/{order_id}/add-user-info`
+    ///Sends a `GET` request to `/admin/exports/orders`
     ///
     ///Arguments:
-    /// - `order_id`
-    /// - `body`:
-    pub async fn add_user_info<'a>(
+    /// - `duration`
+    /// - `format`: File format, defaults to csv
+    /// - `purchased_from`: Purchased at or after
+    /// - `purchased_to`: Purchased before
+    /// - `ticket_type_id`
+    pub async fn export_orders<'a>(
         &'a self,
-        order_id: &'a uuid::Uuid,
-        body: &'a types::AddUserInfoRequest,
-    ) -> Result<ResponseValue<types::Order>, Error<()>> {
-        let url = format!(
-            "{}/orders/{}/add-user-info",
-            self.baseurl,
-            encode_path(&order_id.to_string()),
-        );
+        duration: Option<i32>,
+        format: Option<types::ExportFormat>,
+        purchased_from: Option<&'a chrono::DateTime<chrono::offset::Utc>>,
+        purchased_to: Option<&'a chrono::DateTime<chrono::offset::Utc>>,
+        ticket_type_id: Option<&'a str>,
+    ) -> Result<ResponseValue<ByteStream>, Error<types::ApiError>> {
+        let url = format!("{}/admin/exports/orders", self.baseurl,);
+        let mut query = Vec::with_capacity(5usize);
+        if let Some(v) = &duration {
+            query.push(("duration", v.to_string()));
+        }
+        if let Some(v) = &format {
+            query.push(("format", v.to_string()));
+        }
+        if let Some(v) = &purchased_from {
+            query.push(("purchased_from", v.to_rfc3339()));
+        }
+        if let Some(v) = &purchased_to {
+            query.push(("purchased_to", v.to_rfc3339()));
+        }
+        if let Some(v) = &ticket_type_id {
+            query.push(("ticket_type_id", v.to_string()));
+        }
+        let request = self.client.get(url).query(&query).build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => Ok(ResponseValue::stream(response)),
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///List background jobs, most recently updated first
+    ///
+    ///List background jobs, most recently updated first
+    ///
+    ///Sends a `GET` request to `/admin/jobs`
+    ///
+    ///Arguments:
+    /// - `kind`
+    /// - `limit`: Defaults to 100
+    /// - `state`: pending, running, succeeded or dead
+    pub async fn get_jobs<'a>(
+        &'a self,
+        kind: Option<&'a str>,
+        limit: Option<i64>,
+        state: Option<&'a str>,
+    ) -> Result<ResponseValue<Vec<types::Job>>, Error<types::ApiError>> {
+        let url = format!("{}/admin/jobs", self.baseurl,);
+        let mut query = Vec::with_capacity(3usize);
+        if let Some(v) = &kind {
+            query.push(("kind", v.to_string()));
+        }
+        if let Some(v) = &limit {
+            query.push(("limit", v.to_string()));
+        }
+        if let Some(v) = &state {
+            query.push(("state", v.to_string()));
+        }
         let request = self
             .client
-            .post(url)
+            .get(url)
             .header(
                 reqwest::header::ACCEPT,
                 reqwest::header::HeaderValue::from_static("application/json"),
             )
-            .json(&body)
+            .query(&query)
             .build()?;
         let result = self.client.execute(request).await;
         let response = result?;
         match response.status().as_u16() {
             200u16 => ResponseValue::from_response(response).await,
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
             _ => Err(Error::UnexpectedResponse(response)),
         }
     }
 
-    ///Purchase an order. Note: User info must be attached to order first
+    ///Run a dead or pending job now, with its attempts reset
     ///
-    ///Purchase an order. Note: User info must be attached to order first
+    ///Run a dead or pending job now, with its attempts reset
     ///
-    ///Sends a `POST` request to `/orders/{order_id}/purchase`
-    pub async fn purchase_order<'a>(
+    ///Sends a `POST` request to `/admin/jobs/{job_id}/retry`
+    pub async fn retry_job<'a>(
         &'a self,
-        order_id: &'a uuid::Uuid,
-    ) -> Result<ResponseValue<types::Order>, Error<types::ApiError>> {
+        job_id: i64,
+    ) -> Result<ResponseValue<types::Job>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/admin/jobs/{}/retry",
+            self.baseurl,
+            encode_path(&job_id.to_string()),
+        );
+        let request = self
+            .client
+            .post(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            400u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Bulk import presale codes from a CSV body, with header
+    /// `code,email,ticket_limit`
+    ///
+    ///Bulk import presale codes from a CSV body, with header
+    /// `code,email,ticket_limit`. Either code or email must be set; rows with
+    /// only an email are allow-list entries.
+    ///
+    ///Sends a `POST` request to `/admin/presales/{presale_id}/codes`
+    ///
+    ///Arguments:
+    /// - `presale_id`
+    /// - `default_ticket_limit`: Used for rows without a ticket_limit
+    /// - `body`
+    pub async fn import_presale_codes<'a>(
+        &'a self,
+        presale_id: &'a str,
+        default_ticket_limit: i32,
+        body: String,
+    ) -> Result<ResponseValue<types::ImportReport>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/admin/presales/{}/codes",
+            self.baseurl,
+            encode_path(&presale_id.to_string()),
+        );
+        let mut query = Vec::with_capacity(1usize);
+        query.push(("default_ticket_limit", default_ticket_limit.to_string()));
+        let request = self
+            .client
+            .post(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                reqwest::header::HeaderValue::from_static("text/csv"),
+            )
+            .body(body)
+            .query(&query)
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            400u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///List presale codes with their redemption status
+    ///
+    ///List presale codes with their redemption status
+    ///
+    ///Sends a `GET` request to `/admin/presales/{presale_id}/codes`
+    pub async fn get_presale_codes<'a>(
+        &'a self,
+        presale_id: &'a str,
+    ) -> Result<ResponseValue<Vec<types::PresaleCode>>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/admin/presales/{}/codes",
+            self.baseurl,
+            encode_path(&presale_id.to_string()),
+        );
+        let request = self
+            .client
+            .get(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Create or update a presale window
+    ///
+    ///Create or update a presale window
+    ///
+    ///Sends a `PUT` request to `/admin/presales/{presale_id}`
+    ///
+    ///Arguments:
+    /// - `presale_id`
+    /// - `body`:
+    pub async fn upsert_presale<'a>(
+        &'a self,
+        presale_id: &'a str,
+        body: &'a types::UpsertPresaleRequest,
+    ) -> Result<ResponseValue<types::Presale>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/admin/presales/{}",
+            self.baseurl,
+            encode_path(&presale_id.to_string()),
+        );
+        let request = self
+            .client
+            .put(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .json(&body)
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            400u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Override the per-customer ticket limit for an email, e.g. for group
+    /// bookers
+    ///
+    ///Override the per-customer ticket limit for an email, e.g. for group
+    /// bookers
+    ///
+    ///Sends a `PUT` request to `/admin/purchase-limits/{email}`
+    ///
+    ///Arguments:
+    /// - `email`
+    /// - `body`:
+    pub async fn set_purchase_limit_override<'a>(
+        &'a self,
+        email: &'a str,
+        body: &'a types::SetPurchaseLimitOverrideRequest,
+    ) -> Result<ResponseValue<types::PurchaseLimitOverride>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/admin/purchase-limits/{}",
+            self.baseurl,
+            encode_path(&email.to_string()),
+        );
+        let request = self
+            .client
+            .put(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .json(&body)
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Remove a purchase limit override, so the default limit applies again
+    ///
+    ///Remove a purchase limit override, so the default limit applies again
+    ///
+    ///Sends a `DELETE` request to `/admin/purchase-limits/{email}`
+    pub async fn delete_purchase_limit_override<'a>(
+        &'a self,
+        email: &'a str,
+    ) -> Result<ResponseValue<()>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/admin/purchase-limits/{}",
+            self.baseurl,
+            encode_path(&email.to_string()),
+        );
+        let request = self.client.delete(url).build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            204u16 => Ok(ResponseValue::empty(response)),
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Register an endpoint for order events. Deliveries are signed with the
+    /// returned secret
+    ///
+    ///Register an endpoint for order events. Deliveries are signed with the
+    /// returned secret.
+    ///
+    ///Sends a `POST` request to `/admin/webhooks`
+    pub async fn create_webhook_endpoint<'a>(
+        &'a self,
+        body: &'a types::CreateWebhookEndpointRequest,
+    ) -> Result<ResponseValue<types::CreateWebhookEndpointResponse>, Error<types::ApiError>> {
+        let url = format!("{}/admin/webhooks", self.baseurl,);
+        let request = self
+            .client
+            .post(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .json(&body)
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            400u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///List webhook endpoints
+    ///
+    ///List webhook endpoints
+    ///
+    ///Sends a `GET` request to `/admin/webhooks`
+    pub async fn get_webhook_endpoints<'a>(
+        &'a self,
+    ) -> Result<ResponseValue<Vec<types::WebhookEndpoint>>, Error<types::ApiError>> {
+        let url = format!("{}/admin/webhooks", self.baseurl,);
+        let request = self
+            .client
+            .get(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Remove a webhook endpoint, with its delivery log
+    ///
+    ///Remove a webhook endpoint, with its delivery log
+    ///
+    ///Sends a `DELETE` request to `/admin/webhooks/{endpoint_id}`
+    pub async fn delete_webhook_endpoint<'a>(
+        &'a self,
+        endpoint_id: &'a uuid::Uuid,
+    ) -> Result<ResponseValue<()>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/admin/webhooks/{}",
+            self.baseurl,
+            encode_path(&endpoint_id.to_string()),
+        );
+        let request = self.client.delete(url).build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            204u16 => Ok(ResponseValue::empty(response)),
+            400u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Delivery log of a webhook endpoint, newest first
+    ///
+    ///Delivery log of a webhook endpoint, newest first
+    ///
+    ///Sends a `GET` request to `/admin/webhooks/{endpoint_id}/deliveries`
+    ///
+    ///Arguments:
+    /// - `endpoint_id`
+    /// - `limit`: Defaults to 100
+    pub async fn get_webhook_deliveries<'a>(
+        &'a self,
+        endpoint_id: &'a uuid::Uuid,
+        limit: Option<i64>,
+    ) -> Result<ResponseValue<Vec<types::WebhookDelivery>>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/admin/webhooks/{}/deliveries",
+            self.baseurl,
+            encode_path(&endpoint_id.to_string()),
+        );
+        let mut query = Vec::with_capacity(1usize);
+        if let Some(v) = &limit {
+            query.push(("limit", v.to_string()));
+        }
+        let request = self
+            .client
+            .get(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .query(&query)
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Send a webhook.test event to an endpoint now, and return the outcome
+    ///
+    ///Send a webhook.test event to an endpoint now, and return the outcome
+    ///
+    ///Sends a `POST` request to `/admin/webhooks/{endpoint_id}/test`
+    pub async fn test_webhook_endpoint<'a>(
+        &'a self,
+        endpoint_id: &'a uuid::Uuid,
+    ) -> Result<ResponseValue<types::WebhookDelivery>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/admin/webhooks/{}/test",
+            self.baseurl,
+            encode_path(&endpoint_id.to_string()),
+        );
+        let request = self
+            .client
+            .post(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            400u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Verify a ticket credential and record the scan, if it follows the entry
+    /// rules
+    ///
+    ///Verify a ticket credential and record the scan, if it follows the entry
+    /// rules
+    ///
+    ///Sends a `POST` request to `/gate/check-in`
+    pub async fn check_in<'a>(
+        &'a self,
+        body: &'a types::CheckInRequest,
+    ) -> Result<ResponseValue<types::CheckInResponse>, Error<types::ApiError>> {
+        let url = format!("{}/gate/check-in", self.baseurl,);
+        let request = self
+            .client
+            .post(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .json(&body)
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            400u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Config for scanners to verify tickets locally while offline
+    ///
+    ///Config for scanners to verify tickets locally while offline
+    ///
+    ///Sends a `GET` request to `/gate/config`
+    pub async fn get_scanner_config<'a>(
+        &'a self,
+    ) -> Result<ResponseValue<types::ScannerConfig>, Error<types::ApiError>> {
+        let url = format!("{}/gate/config", self.baseurl,);
+        let request = self
+            .client
+            .get(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Upload scans made while offline
+    ///
+    ///Upload scans made while offline. Scans which broke the entry rules are
+    /// recorded as conflicts.
+    ///
+    ///Sends a `POST` request to `/gate/sync`
+    pub async fn sync_check_ins<'a>(
+        &'a self,
+        body: &'a types::SyncCheckInsRequest,
+    ) -> Result<ResponseValue<types::SyncCheckInsResponse>, Error<types::ApiError>> {
+        let url = format!("{}/gate/sync", self.baseurl,);
+        let request = self
+            .client
+            .post(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .json(&body)
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            401u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Retrieve an order by ID
+    ///
+    ///Retrieve an order by ID
+    ///
+    ///Sends a `GET` request to `/orders/{order_id}`
+    pub async fn get_order<'a>(
+        &'a self,
+        order_id: &'a uuid::Uuid,
+    ) -> Result<ResponseValue<types::Order>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/orders/{}",
+            self.baseurl,
+            encode_path(&order_id.to_string()),
+        );
+        let request = self
+            .client
+            .get(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            404u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Add user info to order
+    ///
+    ///Add user info to order
+    ///
+    ///Sends a `POST` request to `/orders/{order_id}/add-user-info`
+    ///
+    ///Arguments:
+    /// - `order_id`
+    /// - `body`:
+    pub async fn add_user_info<'a>(
+        &'a self,
+        order_id: &'a uuid::Uuid,
+        body: &'a types::AddUserInfoRequest,
+    ) -> Result<ResponseValue<types::Order>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/orders/{}/add-user-info",
+            self.baseurl,
+            encode_path(&order_id.to_string()),
+        );
+        let request = self
+            .client
+            .post(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .json(&body)
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            400u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Apply a promo code to a reserved order
+    ///
+    ///Apply a promo code to a reserved order
+    ///
+    ///Sends a `POST` request to `/orders/{order_id}/apply-promo-code`
+    ///
+    ///Arguments:
+    /// - `order_id`
+    /// - `body`:
+    pub async fn apply_promo_code<'a>(
+        &'a self,
+        order_id: &'a uuid::Uuid,
+        body: &'a types::ApplyPromoCodeRequest,
+    ) -> Result<ResponseValue<types::Order>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/orders/{}/apply-promo-code",
+            self.baseurl,
+            encode_path(&order_id.to_string()),
+        );
+        let request = self
+            .client
+            .post(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .json(&body)
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            400u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Purchase an order. Note: User info must be attached to order first
+    ///
+    ///Purchase an order. Note: User info must be attached to order first
+    ///
+    ///Sends a `POST` request to `/orders/{order_id}/purchase`
+    pub async fn purchase_order<'a>(
+        &'a self,
+        order_id: &'a uuid::Uuid,
+    ) -> Result<ResponseValue<types::Order>, Error<types::ApiError>> {
         let url = format!(
             "{}/orders/{}/purchase",
             self.baseurl,
@@ -260,6 +1361,72 @@ impl Client {
         }
     }
 
+    ///Retrieve the signed tickets issued for an order
+    ///
+    ///Retrieve the signed tickets issued for an order. Empty until the order
+    /// is purchased.
+    ///
+    ///Sends a `GET` request to `/orders/{order_id}/tickets`
+    pub async fn get_tickets<'a>(
+        &'a self,
+        order_id: &'a uuid::Uuid,
+    ) -> Result<ResponseValue<Vec<types::Ticket>>, Error<()>> {
+        let url = format!(
+            "{}/orders/{}/tickets",
+            self.baseurl,
+            encode_path(&order_id.to_string()),
+        );
+        let request = self
+            .client
+            .get(url)
+            .header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static("application/json"),
+            )
+            .build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => ResponseValue::from_response(response).await,
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    ///Render the ticket credential for an order as a QR code
+    ///
+    ///Render the ticket credential for an order as a QR code
+    ///
+    ///Sends a `GET` request to `/orders/{order_id}/tickets/qr`
+    ///
+    ///Arguments:
+    /// - `order_id`
+    /// - `format`: Image format, defaults to png
+    pub async fn get_ticket_qr_code<'a>(
+        &'a self,
+        order_id: &'a uuid::Uuid,
+        format: Option<types::QrCodeFormat>,
+    ) -> Result<ResponseValue<ByteStream>, Error<types::ApiError>> {
+        let url = format!(
+            "{}/orders/{}/tickets/qr",
+            self.baseurl,
+            encode_path(&order_id.to_string()),
+        );
+        let mut query = Vec::with_capacity(1usize);
+        if let Some(v) = &format {
+            query.push(("format", v.to_string()));
+        }
+        let request = self.client.get(url).query(&query).build()?;
+        let result = self.client.execute(request).await;
+        let response = result?;
+        match response.status().as_u16() {
+            200u16 => Ok(ResponseValue::stream(response)),
+            400u16 => Err(Error::ErrorResponse(
+                ResponseValue::from_response(response).await?,
+            )),
+            _ => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
     ///Add Ticket of type and duration in days to basket
     ///
     ///Add Ticket of type and duration in days to basket
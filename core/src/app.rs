@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use tokio::task::JoinHandle;
+
+use crate::config::{ApiTokens, Config, ConfigError};
+use crate::db::{self, DbPool};
+use crate::health::HealthCheck;
+use crate::jobs::JobRunner;
+use crate::mail::{self, Mailer};
+use crate::shutdown::{self, CancellationToken, Deadline};
+use crate::{cli, leader, Ticketing, TicketingService};
+
+/// What the servers share: the ticketing service, and the background tasks behind it. Started
+/// once per process, however many APIs it serves
+pub struct App {
+    pub config: Config,
+    pub ticketing: Arc<dyn TicketingService>,
+    pub health: HealthCheck,
+    pub tokens: ApiTokens,
+    /// Cancelled on ctrl-c or SIGTERM, or to stop the servers and background tasks
+    pub shutdown: CancellationToken,
+    /// What servers and the running job have to finish by once shutting down
+    pub deadline: Deadline,
+    workers: JoinHandle<()>,
+}
+
+impl App {
+    /// Load config, then connect to and migrate the database. Then run the command in the
+    /// process arguments, returning `None`, or start the background tasks to serve with
+    pub async fn init() -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let command = cli::Command::from_args(&args)?;
+
+        let config = Config::load()?;
+        log::info!("connecting to db...");
+        let pool = db::connect_to_pool(&config.database).await?;
+        sqlx::migrate!("../migrations").run(&pool).await?;
+
+        if let Some(command) = command {
+            command.run(&pool, &config).await?;
+            return Ok(None);
+        }
+
+        let mailer = mail::mailer_from_env()?;
+        if mailer.is_none() {
+            log::warn!("mailer url not set, emails are queued but not sent");
+        }
+        let replica = db::replica::connect_to_replica(&config.database)?;
+        let app = Self::start(
+            pool,
+            replica,
+            mailer,
+            ApiTokens::from_env(),
+            config,
+            shutdown::on_signal(),
+        )?;
+        Ok(Some(app))
+    }
+
+    /// Start the job runner, order stats leader and caches on a migrated `pool`, until
+    /// `shutdown` is cancelled
+    pub fn start(
+        pool: DbPool,
+        replica: Option<DbPool>,
+        mailer: Option<Arc<dyn Mailer>>,
+        tokens: ApiTokens,
+        config: Config,
+        shutdown: CancellationToken,
+    ) -> Result<Self, ConfigError> {
+        let ticketing = Ticketing::from_config(pool.clone(), replica, &config)?;
+
+        let runner = JobRunner::new(Arc::new(pool.clone()), mailer, config.workers.clone());
+        let health = HealthCheck::new(&pool, runner.heartbeat(), &config.workers);
+        let workers = tokio::spawn(runner.run(shutdown.clone()));
+        tokio::spawn(leader::publish_order_stats(
+            pool,
+            config.server.stats_interval(),
+            config.workers.leader_retry(),
+            shutdown.clone(),
+        ));
+        tokio::spawn(ticketing.watch_catalogue(shutdown.clone()));
+        tokio::spawn(ticketing.watch_replica(shutdown.clone()));
+
+        let deadline = Deadline::new(shutdown.clone(), config.server.shutdown_timeout());
+        Ok(Self {
+            config,
+            ticketing: Arc::new(ticketing),
+            health,
+            tokens,
+            shutdown,
+            deadline,
+            workers,
+        })
+    }
+
+    /// Once the servers have stopped, give the running job whatever's left until the shutdown
+    /// deadline to finish
+    pub async fn finish(self) {
+        self.shutdown.cancel();
+        if shutdown::drain(self.workers, &self.deadline)
+            .await
+            .is_none()
+        {
+            log::warn!(
+                "background job still running {:?} after shutting down",
+                self.deadline.timeout()
+            );
+        }
+    }
+}
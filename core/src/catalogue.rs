@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use tokio::sync::OnceCell;
+use tokio::time::sleep;
+
+use crate::config::CatalogueConfig;
+use crate::db::replica::ReadPool;
+use crate::db::{self, CatalogueChange, DbPool, DbResult};
+use crate::model::TicketType;
+use crate::shutdown::CancellationToken;
+
+/// How long to wait before listening for changes again, if the connection fails
+const RELISTEN_DELAY: Duration = Duration::from_secs(1);
+
+/// When a value started loading, and the value once loaded
+type Entry<V> = (Instant, Arc<OnceCell<V>>);
+
+/// Values loaded on demand, and kept for `ttl`. Concurrent misses for a key share one load
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<K, Entry<V>>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Cached value for `key`, or the result of `load`. Errors aren't cached
+    pub async fn get_or_try_load<E, F, Fut>(&self, key: K, load: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        let cell = {
+            let mut entries = self.entries.lock().unwrap();
+            let now = Instant::now();
+            let (_, cell) = entries
+                .entry(key)
+                .and_modify(|(loaded_at, cell)| {
+                    // Failed loads leave the cell empty, to be tried again
+                    if now.duration_since(*loaded_at) >= self.ttl && cell.initialized() {
+                        *loaded_at = now;
+                        *cell = Arc::new(OnceCell::new());
+                    }
+                })
+                .or_insert_with(|| (now, Arc::new(OnceCell::new())));
+            cell.clone()
+        };
+
+        cell.get_or_try_init(load).await.cloned()
+    }
+
+    pub fn invalidate_all(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Ticket types and the durations still available for each, cached between the database and
+/// the APIs
+pub struct Catalogue {
+    ticket_types: TtlCache<(), Vec<TicketType>>,
+    /// Not keyed by ticket type, as the durations left are the same for every type. Keying by the
+    /// requested id would keep a copy for every id anyone asks for
+    durations: TtlCache<(), Vec<i32>>,
+}
+
+impl Catalogue {
+    pub fn new(config: &CatalogueConfig) -> Self {
+        Self {
+            ticket_types: TtlCache::new(config.ticket_types_ttl()),
+            durations: TtlCache::new(config.availability_ttl()),
+        }
+    }
+
+    pub async fn ticket_types(&self, reads: &ReadPool) -> DbResult<Vec<TicketType>> {
+        self.ticket_types
+            .get_or_try_load((), || {
+                reads.read(
+                    None,
+                    |pool| async move { db::get_ticket_types(&pool).await },
+                )
+            })
+            .await
+    }
+
+    pub async fn ticket_durations(&self, reads: &ReadPool, type_id: &str) -> DbResult<Vec<i32>> {
+        self.durations
+            .get_or_try_load((), || {
+                reads.read(None, |pool| async move {
+                    db::get_ticket_durations(&pool, type_id).await
+                })
+            })
+            .await
+    }
+
+    /// Drop everything, i.e. when ticket types or limits change
+    pub fn invalidate(&self) {
+        self.ticket_types.invalidate_all();
+        self.invalidate_availability();
+    }
+
+    /// Drop available durations, i.e. when inventory changed outside of reservations
+    pub fn invalidate_availability(&self) {
+        self.durations.invalidate_all();
+    }
+}
+
+/// Invalidate `catalogue` when ticket types or limits change, or published order stats show a
+/// duration selling out or becoming available again, until `shutdown` is cancelled. Changes made
+/// by any instance, or directly in the database, are seen.
+pub async fn watch(pool: DbPool, catalogue: Arc<Catalogue>, shutdown: CancellationToken) {
+    // Durations with tickets left, as of the last order stats
+    let mut available: Option<Vec<i32>> = None;
+
+    while !shutdown.is_cancelled() {
+        match db::listen_catalogue_changes(&pool).await {
+            Ok(mut changes) => loop {
+                tokio::select! {
+                    () = shutdown.cancelled() => return,
+                    change = changes.next() => match change {
+                        Some(Ok(CatalogueChange::Catalogue)) => catalogue.invalidate(),
+                        Some(Ok(CatalogueChange::Inventory)) => catalogue.invalidate_availability(),
+                        Some(Ok(CatalogueChange::OrderStats(stats))) => {
+                            let now_available: Vec<i32> = stats
+                                .iter()
+                                .filter(|s| s.order_count < s.order_limit)
+                                .map(|s| s.duration_days)
+                                .collect();
+                            if available.as_ref() != Some(&now_available) {
+                                catalogue.invalidate_availability();
+                                available = Some(now_available);
+                            }
+                        }
+                        Some(Err(e)) => {
+                            log::error!("error listening for catalogue changes: {}", e);
+                            break;
+                        }
+                        None => break,
+                    }
+                }
+            },
+            Err(e) => log::error!("error listening for catalogue changes: {}", e),
+        }
+
+        // Changes may have been missed while not listening
+        catalogue.invalidate();
+        available = None;
+        tokio::select! {
+            () = sleep(RELISTEN_DELAY) => {}
+            () = shutdown.cancelled() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn load_once_until_expired_or_invalidated() {
+        let cache = TtlCache::new(Duration::from_millis(50));
+        let loads = AtomicUsize::new(0);
+        let load = || async { Ok::<_, ()>(loads.fetch_add(1, Ordering::SeqCst)) };
+
+        let (a, b) = tokio::join!(
+            cache.get_or_try_load("key", load),
+            cache.get_or_try_load("key", load)
+        );
+        assert_eq!(
+            (a, b),
+            (Ok(0), Ok(0)),
+            "concurrent misses should share a load"
+        );
+        assert_eq!(cache.get_or_try_load("key", load).await, Ok(0));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(cache.get_or_try_load("key", load).await, Ok(1));
+
+        cache.invalidate_all();
+        assert_eq!(cache.get_or_try_load("key", load).await, Ok(2));
+    }
+
+    #[tokio::test]
+    async fn errors_are_not_cached() {
+        let cache = TtlCache::new(Duration::from_secs(60));
+        assert_eq!(
+            cache.get_or_try_load(1, || async { Err("down") }).await,
+            Err::<i32, _>("down")
+        );
+        assert_eq!(
+            cache
+                .get_or_try_load(1, || async { Ok::<_, &str>(5) })
+                .await,
+            Ok(5)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use crate::config::Config;
+use crate::db::{self, DbPool};
+
+const USAGE: &str =
+    "usage: festival-tickets import-comps <file.csv> [--allocation <name>] [--dry-run]";
+
+/// Subcommands, run instead of the server
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    ImportComps(ImportCompsArgs),
+}
+
+impl Command {
+    /// Parse the process arguments. Returns `None` to run the server.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, String> {
+        match args.first().map(String::as_str) {
+            None => Ok(None),
+            Some("import-comps") => {
+                ImportCompsArgs::parse(&args[1..]).map(|args| Some(Command::ImportComps(args)))
+            }
+            Some(command) => Err(format!("unknown command {}", command)),
+        }
+        .map_err(|e| format!("{}\n{}", e, USAGE))
+    }
+
+    pub async fn run(
+        &self,
+        pool: &DbPool,
+        config: &Config,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Command::ImportComps(args) => import_comps(pool, config, args).await,
+        }
+    }
+}
+
+/// Arguments of the `import-comps` subcommand
+#[derive(Debug, PartialEq)]
+pub struct ImportCompsArgs {
+    pub path: String,
+    /// Used for rows without an allocation
+    pub allocation: Option<String>,
+    pub dry_run: bool,
+}
+
+impl ImportCompsArgs {
+    /// Parse the arguments following the subcommand
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut path = None;
+        let mut allocation = None;
+        let mut dry_run = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--dry-run" => dry_run = true,
+                "--allocation" => match args.next() {
+                    Some(value) => allocation = Some(value.clone()),
+                    None => return Err("--allocation needs a value".to_string()),
+                },
+                flag if flag.starts_with("--") => return Err(format!("unknown flag {}", flag)),
+                value if path.is_none() => path = Some(value.to_string()),
+                value => return Err(format!("unexpected argument {}", value)),
+            }
+        }
+
+        Ok(Self {
+            path: path.ok_or("missing csv file")?,
+            allocation,
+            dry_run,
+        })
+    }
+}
+
+/// Import comp tickets from a CSV file, and print the report
+async fn import_comps(
+    pool: &DbPool,
+    config: &Config,
+    args: &ImportCompsArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let signer = config.tickets.signer()?;
+    let csv = std::fs::read_to_string(&args.path)?;
+    let report = db::comps::import_comp_tickets(
+        pool,
+        &csv,
+        args.allocation.as_deref(),
+        args.dry_run,
+        &signer,
+    )
+    .await?;
+
+    if args.dry_run {
+        println!("dry run, {} tickets would be imported", report.imported);
+    } else {
+        println!("imported {} tickets", report.imported);
+        for order_id in &report.order_ids {
+            println!("  order {}", order_id);
+        }
+    }
+    if !report.rejected.is_empty() {
+        println!("rejected {} rows:", report.rejected.len());
+        for reason in &report.rejected {
+            println!("  {}", reason);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_import_comps_args() {
+        assert_eq!(
+            ImportCompsArgs::parse(&args(&["crew.csv", "--allocation", "crew", "--dry-run"])),
+            Ok(ImportCompsArgs {
+                path: "crew.csv".to_string(),
+                allocation: Some("crew".to_string()),
+                dry_run: true,
+            })
+        );
+        assert!(ImportCompsArgs::parse(&args(&[])).is_err());
+        assert!(ImportCompsArgs::parse(&args(&["crew.csv", "--allocation"])).is_err());
+        assert!(ImportCompsArgs::parse(&args(&["crew.csv", "--force"])).is_err());
+        assert!(ImportCompsArgs::parse(&args(&["crew.csv", "more.csv"])).is_err());
+
+        assert_eq!(Command::from_args(&args(&[])), Ok(None));
+        assert!(Command::from_args(&args(&["serve"])).is_err());
+    }
+}
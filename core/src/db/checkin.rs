@@ -0,0 +1,457 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use sqlx::types::Uuid;
+use sqlx::PgConnection;
+
+use super::error::DbError;
+use super::{DbPool, DbResult};
+use crate::model::{CheckIn, OfflineCheckIn, ScanDirection};
+use crate::tickets::TicketSigner;
+
+/// Rules for letting tickets through the gates
+#[derive(Clone, Copy, Debug)]
+pub struct CheckInPolicy {
+    /// First day of the festival. Tickets are valid from this day, for their duration.
+    pub festival_start: NaiveDate,
+    /// Offset from UTC of the festival's local time, which festival days follow
+    pub utc_offset: FixedOffset,
+    /// Whether ticket holders may leave and re-enter on the same day
+    pub allow_reentry: bool,
+}
+
+impl CheckInPolicy {
+    /// Day of the festival at `at`, from 1. Days follow local dates, at `utc_offset`.
+    pub fn festival_day(&self, at: DateTime<Utc>) -> i32 {
+        let date = at.with_timezone(&self.utc_offset).date_naive();
+        (date - self.festival_start).num_days() as i32 + 1
+    }
+
+    /// Check a scan follows the entry rules, given the earlier scans of the ticket on the same
+    /// day, in order. Returns why it doesn't, if it doesn't.
+    fn check_scan(
+        &self,
+        duration: i32,
+        festival_day: i32,
+        earlier: &[&str],
+        direction: &str,
+    ) -> Result<(), String> {
+        if !(1..=duration).contains(&festival_day) {
+            return Err(format!(
+                "{} day ticket isn't valid on day {} of the festival",
+                duration, festival_day
+            ));
+        }
+
+        let inside = earlier.last() == Some(&"entry");
+        match direction {
+            "entry" if inside => Err("ticket has already been checked in".to_string()),
+            "entry" if !self.allow_reentry && earlier.contains(&"entry") => {
+                Err("re-entry isn't allowed".to_string())
+            }
+            "exit" if !inside => Err("ticket hasn't been checked in".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a scan made at `position` among the other scans of the ticket on the same day, in
+    /// order, whether they were made before or after it.
+    ///
+    /// Returns why the scan breaks the entry rules if it does, and the other scans which followed
+    /// the rules without it but don't with it, by position, with why. A duplicate entry puts the
+    /// entry it duplicates in conflict too.
+    fn check_scan_among(
+        &self,
+        duration: i32,
+        festival_day: i32,
+        others: &[&str],
+        position: usize,
+        direction: &str,
+    ) -> (Result<(), String>, Vec<(usize, String)>) {
+        let with: Vec<&str> = others[..position]
+            .iter()
+            .chain([&direction])
+            .chain(&others[position..])
+            .copied()
+            .collect();
+        let replay = |scans: &[&str]| -> Vec<Result<(), String>> {
+            (0..scans.len())
+                .map(|i| self.check_scan(duration, festival_day, &scans[..i], scans[i]))
+                .collect()
+        };
+        let before = replay(others);
+        let after = replay(&with);
+
+        let mut allowed = after[position].clone();
+        let mut conflicting = vec![];
+        if let Err(e) = &allowed {
+            let duplicated = position
+                .checked_sub(1)
+                .filter(|&i| direction == "entry" && others[i] == "entry" && before[i].is_ok());
+            if let Some(i) = duplicated {
+                conflicting.push((i, e.clone()));
+            }
+        }
+        for i in position..others.len() {
+            if let (Ok(()), Err(e)) = (&before[i], &after[i + 1]) {
+                conflicting.push((i, e.clone()));
+                if allowed.is_ok() {
+                    allowed = Err(e.clone());
+                }
+            }
+        }
+
+        (allowed, conflicting)
+    }
+}
+
+fn direction_name(direction: ScanDirection) -> &'static str {
+    match direction {
+        ScanDirection::Entry => "entry",
+        ScanDirection::Exit => "exit",
+    }
+}
+
+/// A scan checked against the entry rules
+struct ScanCheck {
+    festival_day: i32,
+    /// Why the scan breaks the entry rules, if it does
+    allowed: Result<(), String>,
+    /// Recorded scans of the ticket which break the entry rules once the scan is recorded, by id,
+    /// with why
+    conflicting: Vec<(i64, String)>,
+}
+
+/// Lock the ticket for `order_id` and check a scan at `scanned_at` against the entry rules.
+///
+/// The scan is checked against every recorded scan of the ticket that day, including those
+/// which broke the entry rules, as they still happened. Scans are ordered by when they were
+/// made, not when they were recorded, so offline scans synced late are checked in place.
+async fn check_scan(
+    conn: &mut PgConnection,
+    policy: &CheckInPolicy,
+    order_id: &Uuid,
+    direction: &str,
+    scanned_at: DateTime<Utc>,
+) -> DbResult<ScanCheck> {
+    // Locking the ticket serialises scans of the same ticket at different gates
+    let duration = sqlx::query_scalar!(
+        r#"
+SELECT ord.duration_days::integer as "duration!"
+FROM tickets AS t
+JOIN orders AS ord ON ord.id = t.order_id
+WHERE t.order_id = $1
+FOR UPDATE OF t
+        "#,
+        order_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| {
+        DbError::FailedPrecondition(format!("no ticket issued for order {}", order_id))
+    })?;
+
+    let festival_day = policy.festival_day(scanned_at);
+    let others = sqlx::query!(
+        r#"
+SELECT id, direction, scanned_at <= $3 as "earlier!"
+FROM check_ins
+WHERE order_id = $1 AND festival_day = $2
+ORDER BY scanned_at, id
+        "#,
+        order_id,
+        festival_day,
+        scanned_at
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let position = others.iter().filter(|o| o.earlier).count();
+    let directions: Vec<&str> = others.iter().map(|o| o.direction.as_str()).collect();
+    let (allowed, conflicting) =
+        policy.check_scan_among(duration, festival_day, &directions, position, direction);
+
+    Ok(ScanCheck {
+        festival_day,
+        allowed,
+        conflicting: conflicting
+            .into_iter()
+            .map(|(i, e)| (others[i].id, e))
+            .collect(),
+    })
+}
+
+/// Record why scan `id` broke the entry rules, unless it's already known to
+async fn mark_conflict(conn: &mut PgConnection, id: i64, conflict: &str) -> DbResult<()> {
+    sqlx::query!(
+        "UPDATE check_ins SET conflict = coalesce(conflict, $2) WHERE id = $1",
+        id,
+        conflict
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn insert_check_in(
+    conn: &mut PgConnection,
+    order_id: &Uuid,
+    festival_day: i32,
+    direction: &str,
+    gate: &str,
+    scanned_at: DateTime<Utc>,
+    client_ref: Option<Uuid>,
+    conflict: Option<String>,
+) -> DbResult<CheckIn> {
+    let check_in = sqlx::query_as!(
+        CheckIn,
+        r#"
+INSERT INTO check_ins (order_id, festival_day, direction, gate, scanned_at, client_ref, conflict)
+VALUES ($1, $2, $3, $4, $5, $6, $7)
+RETURNING
+    id,
+    order_id,
+    festival_day,
+    direction,
+    gate,
+    scanned_at,
+    client_ref IS NOT NULL as "offline!",
+    conflict
+        "#,
+        order_id,
+        festival_day,
+        direction,
+        gate,
+        scanned_at,
+        client_ref,
+        conflict
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(check_in)
+}
+
+fn parse_order_id(oid: &str) -> DbResult<Uuid> {
+    Uuid::parse_str(oid)
+        .map_err(|e| DbError::FailedPrecondition(format!("invalid ticket credential: {}", e)))
+}
+
+/// Verify a ticket credential and record a scan of it. Scans which break the entry rules,
+/// i.e. duplicate entries, are rejected.
+pub async fn check_in(
+    pool: &DbPool,
+    policy: &CheckInPolicy,
+    signer: &TicketSigner,
+    credential: &str,
+    direction: ScanDirection,
+    gate: &str,
+) -> DbResult<CheckIn> {
+    let claims = signer
+        .verify(credential)
+        .map_err(|e| DbError::FailedPrecondition(e.to_string()))?;
+    let order_id = parse_order_id(&claims.oid)?;
+    let direction = direction_name(direction);
+    let now = Utc::now();
+
+    let mut tx = pool.begin().await?;
+
+    let scan = check_scan(&mut tx, policy, &order_id, direction, now).await?;
+    scan.allowed.map_err(DbError::FailedPrecondition)?;
+
+    let check_in = insert_check_in(
+        &mut tx,
+        &order_id,
+        scan.festival_day,
+        direction,
+        gate,
+        now,
+        None,
+        None,
+    )
+    .await?;
+    // Only if an offline scanner's clock ran ahead
+    for (id, conflict) in scan.conflicting {
+        mark_conflict(&mut tx, id, &conflict).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(check_in)
+}
+
+/// Record scans made by offline scanners.
+///
+/// Scans already happened, so those breaking the entry rules are still recorded, with the
+/// conflict. Recorded scans they conflict with are marked too, so both of a duplicate entry are,
+/// whichever order they were synced in. Scans which can't be verified are rejected. Scans
+/// already synced are returned as they were recorded.
+pub async fn sync_check_ins(
+    pool: &DbPool,
+    policy: &CheckInPolicy,
+    signer: &TicketSigner,
+    check_ins: &[OfflineCheckIn],
+) -> DbResult<(Vec<CheckIn>, Vec<String>)> {
+    let mut recorded = vec![];
+    let mut rejected = vec![];
+
+    // Check scans in the order they were made
+    let mut scans: Vec<_> = check_ins.iter().collect();
+    scans.sort_by_key(|scan| scan.scanned_at);
+
+    let mut tx = pool.begin().await?;
+
+    for scan in scans {
+        let mut reject = |reason: String| rejected.push(format!("{}: {}", scan.client_ref, reason));
+
+        let existing = sqlx::query_as!(
+            CheckIn,
+            r#"
+SELECT
+    id,
+    order_id,
+    festival_day,
+    direction,
+    gate,
+    scanned_at,
+    client_ref IS NOT NULL as "offline!",
+    conflict
+FROM check_ins
+WHERE client_ref = $1
+            "#,
+            scan.client_ref
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(existing) = existing {
+            recorded.push(existing);
+            continue;
+        }
+
+        let order_id = match signer
+            .verify(&scan.credential)
+            .map_err(|e| DbError::FailedPrecondition(e.to_string()))
+            .and_then(|claims| parse_order_id(&claims.oid))
+        {
+            Ok(order_id) => order_id,
+            Err(DbError::FailedPrecondition(e)) => {
+                reject(e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let direction = direction_name(scan.direction);
+
+        let checked = match check_scan(&mut tx, policy, &order_id, direction, scan.scanned_at).await
+        {
+            Ok(res) => res,
+            Err(DbError::FailedPrecondition(e)) => {
+                reject(e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        recorded.push(
+            insert_check_in(
+                &mut tx,
+                &order_id,
+                checked.festival_day,
+                direction,
+                &scan.gate,
+                scan.scanned_at,
+                Some(scan.client_ref),
+                checked.allowed.err(),
+            )
+            .await?,
+        );
+        for (id, conflict) in checked.conflicting {
+            mark_conflict(&mut tx, id, &conflict).await?;
+            // Scans recorded earlier in this sync are returned as they are now
+            if let Some(check_in) = recorded.iter_mut().find(|c| c.id == id) {
+                check_in.conflict.get_or_insert(conflict);
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok((recorded, rejected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> CheckInPolicy {
+        CheckInPolicy {
+            festival_start: NaiveDate::from_ymd_opt(2024, 7, 18).unwrap(),
+            utc_offset: FixedOffset::east_opt(0).unwrap(),
+            allow_reentry: true,
+        }
+    }
+
+    #[test]
+    fn check_festival_days() {
+        let mut policy = policy();
+        let at = |at: &str| at.parse().unwrap();
+        assert_eq!(policy.festival_day(at("2024-07-17T12:00:00Z")), 0);
+        assert_eq!(policy.festival_day(at("2024-07-18T12:00:00Z")), 1);
+        assert_eq!(policy.festival_day(at("2024-07-21T12:00:00Z")), 4);
+
+        // Days follow local dates, not UTC
+        policy.utc_offset = "+01:00".parse().unwrap();
+        assert_eq!(policy.festival_day(at("2024-07-17T23:30:00Z")), 1);
+        assert_eq!(policy.festival_day(at("2024-07-18T22:59:00Z")), 1);
+        assert_eq!(policy.festival_day(at("2024-07-18T23:00:00Z")), 2);
+    }
+
+    #[test]
+    fn check_entry_rules() {
+        let mut policy = policy();
+
+        // Valid days
+        assert!(policy.check_scan(3, 1, &[], "entry").is_ok());
+        assert!(policy.check_scan(3, 3, &[], "entry").is_ok());
+        assert!(policy.check_scan(3, 4, &[], "entry").is_err());
+        assert!(policy.check_scan(3, 0, &[], "entry").is_err());
+
+        // Duplicate entries, and exits without entering
+        assert!(policy.check_scan(3, 1, &["entry"], "entry").is_err());
+        assert!(policy.check_scan(3, 1, &[], "exit").is_err());
+        assert!(policy.check_scan(3, 1, &["entry"], "exit").is_ok());
+
+        // Re-entry
+        let left = ["entry", "exit"];
+        assert!(policy.check_scan(3, 1, &left, "entry").is_ok());
+        policy.allow_reentry = false;
+        assert!(policy.check_scan(3, 1, &left, "entry").is_err());
+        assert!(policy.check_scan(3, 1, &["exit"], "entry").is_ok());
+    }
+
+    #[test]
+    fn check_scans_made_out_of_order() {
+        let policy = policy();
+        let check = |others: &[&str], position, direction| {
+            let (allowed, conflicting) = policy.check_scan_among(3, 1, others, position, direction);
+            let conflicting: Vec<usize> = conflicting.into_iter().map(|(i, _)| i).collect();
+            (allowed.is_ok(), conflicting)
+        };
+
+        // In order
+        assert_eq!(check(&["entry"], 1, "exit"), (true, vec![]));
+        assert_eq!(check(&["entry", "exit"], 2, "entry"), (true, vec![]));
+
+        // Duplicate entries conflict whichever was made first
+        assert_eq!(check(&["entry"], 1, "entry"), (false, vec![0]));
+        assert_eq!(check(&["entry"], 0, "entry"), (false, vec![0]));
+        assert_eq!(check(&["entry", "exit"], 0, "entry"), (false, vec![0]));
+
+        // An exit made before the entry it follows
+        assert_eq!(check(&["entry", "exit"], 0, "exit"), (false, vec![]));
+
+        // Scans which already broke the rules aren't marked again
+        assert_eq!(check(&["entry", "entry"], 0, "entry"), (false, vec![0]));
+        assert_eq!(check(&["entry", "entry"], 2, "entry"), (false, vec![]));
+    }
+}
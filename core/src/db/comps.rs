@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use sqlx::types::Uuid;
+
+use super::{limits, outbox, tickets, webhooks, DbPool, DbResult};
+use crate::metrics;
+use crate::tickets::TicketSigner;
+
+/// Complimentary ticket parsed from an import CSV
+#[derive(Debug, PartialEq)]
+pub struct NewCompTicket {
+    /// Line of the CSV the ticket is on, for reporting
+    pub line: u64,
+    pub ticket_type_id: String,
+    pub duration: i32,
+    pub name: String,
+    pub email: String,
+    pub address: String,
+    pub allocation: String,
+}
+
+/// Result of a comp ticket import
+#[derive(Debug, Default, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CompImportReport {
+    pub imported: i32,
+    /// Rows which weren't imported, and why
+    pub rejected: Vec<String>,
+    /// Orders created. Empty for dry runs.
+    pub order_ids: Vec<Uuid>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct CsvRow {
+    ticket_type_id: Option<String>,
+    duration: Option<i32>,
+    name: Option<String>,
+    email: Option<String>,
+    address: Option<String>,
+    allocation: Option<String>,
+}
+
+/// Parse comp tickets from a CSV with header
+/// `ticket_type_id,duration,name,email,address,allocation`.
+///
+/// Rows without an allocation, i.e. artist or crew, use `default_allocation`.
+pub fn parse_comp_tickets_csv(
+    csv: &str,
+    default_allocation: Option<&str>,
+) -> (Vec<NewCompTicket>, Vec<String>) {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => return (vec![], vec![format!("invalid header: {}", e)]),
+    };
+
+    let mut tickets = vec![];
+    let mut rejected = vec![];
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                rejected.push(e.to_string());
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let mut reject = |reason: String| rejected.push(format!("line {}: {}", line, reason));
+
+        let row: CsvRow = match record.deserialize(Some(&headers)) {
+            Ok(row) => row,
+            Err(e) => {
+                reject(e.to_string());
+                continue;
+            }
+        };
+
+        let non_empty = |value: Option<String>| value.filter(|v| !v.is_empty());
+        let allocation = non_empty(row.allocation)
+            .or_else(|| default_allocation.map(|a| a.trim().to_string()))
+            .filter(|a| !a.is_empty());
+        let fields = (
+            non_empty(row.ticket_type_id),
+            row.duration,
+            non_empty(row.name),
+            non_empty(row.email),
+            non_empty(row.address),
+            allocation,
+        );
+        let (ticket_type_id, duration, name, email, address, allocation) = match fields {
+            (Some(t), Some(d), Some(n), Some(e), Some(a), Some(al)) => (t, d, n, e, a, al),
+            fields => {
+                let missing: Vec<&str> = [
+                    ("ticket_type_id", fields.0.is_none()),
+                    ("duration", fields.1.is_none()),
+                    ("name", fields.2.is_none()),
+                    ("email", fields.3.is_none()),
+                    ("address", fields.4.is_none()),
+                    ("allocation", fields.5.is_none()),
+                ]
+                .into_iter()
+                .filter_map(|(field, missing)| missing.then_some(field))
+                .collect();
+                reject(format!("missing {}", missing.join(", ")));
+                continue;
+            }
+        };
+
+        if !email.contains('@') {
+            reject(format!("invalid email {}", email));
+            continue;
+        }
+
+        tickets.push(NewCompTicket {
+            line,
+            ticket_type_id,
+            duration,
+            name,
+            email,
+            address,
+            allocation,
+        });
+    }
+
+    (tickets, rejected)
+}
+
+/// Bulk import comp tickets from CSV, as purchased zero-price orders with signed tickets.
+///
+/// The import is one transaction, and takes tickets from the same inventory as customer orders.
+/// Rows for unknown ticket types or durations, or beyond the remaining inventory, are reported as
+/// rejected. With `dry_run` the rows are checked but nothing is saved.
+pub async fn import_comp_tickets(
+    pool: &DbPool,
+    csv: &str,
+    default_allocation: Option<&str>,
+    dry_run: bool,
+    signer: &TicketSigner,
+) -> DbResult<CompImportReport> {
+    let (comps, rejected) = parse_comp_tickets_csv(csv, default_allocation);
+    let mut report = CompImportReport {
+        rejected,
+        ..Default::default()
+    };
+
+    let mut tx = pool.begin().await?;
+
+    let ticket_types: HashSet<String> = sqlx::query_scalar!("SELECT id FROM ticket_types")
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+
+    // Locked until the import is committed, so concurrent orders can't oversell
+    let mut remaining: HashMap<i32, i32> = sqlx::query!(
+        r#"
+SELECT duration_days, order_limit - coalesce(order_count, 0) as "remaining!"
+FROM order_stats
+ORDER BY duration_days
+FOR UPDATE
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.duration_days, row.remaining))
+    .collect();
+
+    let now = chrono::Utc::now();
+    let mut imported = vec![];
+    for comp in comps {
+        let mut reject = |reason: String| {
+            report
+                .rejected
+                .push(format!("line {}: {}", comp.line, reason))
+        };
+
+        if !ticket_types.contains(&comp.ticket_type_id) {
+            reject(format!("unknown ticket type {}", comp.ticket_type_id));
+            continue;
+        }
+        match remaining.get_mut(&comp.duration) {
+            None => {
+                reject(format!("invalid duration {}", comp.duration));
+                continue;
+            }
+            Some(remaining) if *remaining <= 0 => {
+                reject(format!("no {} day tickets remaining", comp.duration));
+                continue;
+            }
+            Some(remaining) => *remaining -= 1,
+        }
+
+        let user_id = sqlx::query_scalar!(
+            r#"
+INSERT INTO users (name, address, email, email_normalised, address_normalised)
+VALUES ($1, $2, $3, $4, $5)
+RETURNING id
+            "#,
+            comp.name,
+            comp.address,
+            comp.email,
+            limits::normalise_email(&comp.email),
+            limits::normalise_address(&comp.address)
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let order_id = sqlx::query_scalar!(
+            r#"
+INSERT INTO orders (
+    ticket_type, reserved_until, purchased_at, duration_days, user_id, base_price, comp_allocation
+)
+VALUES ($1, $2, $2, $3::integer, $4, 0, $5)
+RETURNING id
+            "#,
+            comp.ticket_type_id,
+            now,
+            comp.duration,
+            user_id,
+            comp.allocation
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tickets::issue_ticket(&mut tx, &order_id, signer).await?;
+        outbox::queue_purchase_receipt(&mut tx, &order_id).await?;
+        webhooks::queue_order_event(&mut tx, "order.purchased", &order_id).await?;
+
+        report.imported += 1;
+        report.order_ids.push(order_id);
+        imported.push((comp.ticket_type_id, comp.duration));
+    }
+
+    if dry_run {
+        tx.rollback().await?;
+        report.order_ids.clear();
+    } else {
+        // Comp tickets use up inventory
+        super::notify_inventory_changed(&mut tx).await?;
+        tx.commit().await?;
+        for (ticket_type_id, duration) in imported {
+            metrics::record_purchase(&ticket_type_id, duration, true);
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_comp_csv() {
+        let csv = "ticket_type_id,duration,name,email,address,allocation
+chalet3,3,Ada,ada@example.com,1 Main St,
+chalet3,4,Bo,bo@example.com,2 Main St,sponsor
+chalet3,,Cy,cy@example.com,3 Main St,
+chalet3,3,Di,not-an-email,4 Main St,
+chalet3,three,Ed,ed@example.com,5 Main St,
+";
+        let (tickets, rejected) = parse_comp_tickets_csv(csv, Some("crew"));
+
+        assert_eq!(
+            tickets,
+            vec![
+                NewCompTicket {
+                    line: 2,
+                    ticket_type_id: "chalet3".to_string(),
+                    duration: 3,
+                    name: "Ada".to_string(),
+                    email: "ada@example.com".to_string(),
+                    address: "1 Main St".to_string(),
+                    allocation: "crew".to_string(),
+                },
+                NewCompTicket {
+                    line: 3,
+                    ticket_type_id: "chalet3".to_string(),
+                    duration: 4,
+                    name: "Bo".to_string(),
+                    email: "bo@example.com".to_string(),
+                    address: "2 Main St".to_string(),
+                    allocation: "sponsor".to_string(),
+                },
+            ]
+        );
+        assert_eq!(rejected.len(), 3, "{:#?}", rejected);
+        assert_eq!(rejected[0], "line 4: missing duration");
+        assert!(rejected[1].starts_with("line 5: invalid email"));
+
+        let (tickets, rejected) = parse_comp_tickets_csv(csv, None);
+        assert_eq!(tickets.len(), 1);
+        assert_eq!(rejected[0], "line 2: missing allocation");
+    }
+}
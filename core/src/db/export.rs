@@ -0,0 +1,77 @@
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use serde::Serialize;
+use sqlx::types::Uuid;
+
+use super::error::DbError;
+use super::{DbPool, DbResult};
+
+/// Which purchased orders to export. Unset filters match every order.
+#[derive(Clone, Debug, Default)]
+pub struct ExportFilter {
+    pub ticket_type_id: Option<String>,
+    pub duration: Option<i32>,
+    /// Purchased at or after
+    pub purchased_from: Option<DateTime<Utc>>,
+    /// Purchased before
+    pub purchased_to: Option<DateTime<Utc>>,
+}
+
+/// A purchased order with its attendee. Fields are exported in this order.
+#[derive(Debug, Serialize)]
+pub struct ExportRow {
+    pub order_id: Uuid,
+    pub ticket_type_id: String,
+    pub ticket_type: String,
+    pub duration: i32,
+    pub price: f32,
+    pub promo_code: Option<String>,
+    pub purchased_at: DateTime<Utc>,
+    pub holder_name: String,
+    pub email: String,
+    pub address: String,
+    /// Comp ticket allocation, i.e. crew. Unset for orders placed by customers.
+    pub allocation: Option<String>,
+}
+
+/// Stream purchased orders matching `filter`, oldest purchase first. Rows are fetched as the
+/// stream is read, rather than loaded up front.
+pub fn export_orders<'a>(
+    pool: &'a DbPool,
+    filter: &'a ExportFilter,
+) -> BoxStream<'a, DbResult<ExportRow>> {
+    sqlx::query_as!(
+        ExportRow,
+        r#"
+SELECT
+    ord.id as "order_id!",
+    ord.ticket_type as "ticket_type_id!",
+    coalesce(tt.display, tt.id) as "ticket_type!",
+    ord.duration_days::integer as "duration!",
+    (ord.base_price - ord.discount)::real as "price!",
+    ord.promo_code,
+    ord.purchased_at as "purchased_at!",
+    users.name as holder_name,
+    users.email,
+    users.address,
+    ord.comp_allocation as allocation
+FROM orders AS ord
+JOIN ticket_types AS tt ON tt.id = ord.ticket_type
+JOIN users ON users.id = ord.user_id
+WHERE ord.purchased_at IS NOT NULL
+    AND ($1::text IS NULL OR ord.ticket_type = $1)
+    AND ($2::integer IS NULL OR ord.duration_days = $2)
+    AND ($3::timestamptz IS NULL OR ord.purchased_at >= $3)
+    AND ($4::timestamptz IS NULL OR ord.purchased_at < $4)
+ORDER BY ord.purchased_at, ord.id
+        "#,
+        filter.ticket_type_id,
+        filter.duration,
+        filter.purchased_from,
+        filter.purchased_to
+    )
+    .fetch(pool)
+    .map_err(DbError::from)
+    .boxed()
+}
@@ -0,0 +1,266 @@
+use sqlx::types::Uuid;
+use sqlx::PgConnection;
+
+use super::error::DbError;
+use super::{outbox, DbPool, DbResult};
+use crate::jobs::Job;
+use crate::model;
+
+/// Succeeded jobs are kept this long, for inspection
+const SUCCEEDED_JOB_RETENTION_DAYS: i32 = 7;
+
+/// Failed jobs are retried after at most this long
+const MAX_BACKOFF_SECS: i32 = 60 * 60;
+
+/// The backoff stops doubling after this many failures, by when it's past `MAX_BACKOFF_SECS`.
+/// Recurring jobs never go dead, so their attempts grow for as long as they keep failing
+const MAX_BACKOFF_DOUBLINGS: i32 = 10;
+
+/// A job claimed by a worker
+#[derive(Debug)]
+pub struct ClaimedJob {
+    pub id: i64,
+    pub kind: String,
+    pub payload: Option<String>,
+    /// Including this one
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub repeat_secs: Option<i32>,
+}
+
+/// Queue a job. Queue jobs in the same transaction as the change they're for, so they run if
+/// and only if the change is committed.
+pub async fn enqueue_job(conn: &mut PgConnection, job: &Job) -> DbResult<i64> {
+    let id = sqlx::query_scalar!(
+        "INSERT INTO jobs (kind, payload) VALUES ($1, $2::text::jsonb) RETURNING id",
+        job.kind(),
+        job.payload()
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(id)
+}
+
+/// Schedule a job to run every `every_secs` seconds, unless it's already scheduled
+pub async fn schedule_recurring_job(pool: &DbPool, job: &Job, every_secs: i32) -> DbResult<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO jobs (kind, payload, dedupe_key, repeat_secs)
+VALUES ($1, $2::text::jsonb, $1, $3)
+ON CONFLICT (dedupe_key) WHERE state IN ('pending', 'running')
+DO UPDATE SET repeat_secs = EXCLUDED.repeat_secs
+        "#,
+        job.kind(),
+        job.payload(),
+        every_secs
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Claim the next due job of one of `kinds` for `worker`, locking it for `lock_secs`. Jobs whose
+/// lock expired are claimed again.
+pub async fn claim_job(
+    pool: &DbPool,
+    worker: Uuid,
+    kinds: &[&str],
+    lock_secs: i32,
+) -> DbResult<Option<ClaimedJob>> {
+    let kinds: Vec<String> = kinds.iter().map(|k| k.to_string()).collect();
+    let job = sqlx::query_as!(
+        ClaimedJob,
+        r#"
+UPDATE jobs
+SET
+    state = 'running',
+    attempts = attempts + 1,
+    locked_until = now() + make_interval(secs => $2::integer),
+    locked_by = $3,
+    updated_at = now()
+WHERE id = (
+    SELECT id
+    FROM jobs
+    WHERE kind = ANY($1)
+        AND run_after <= now()
+        AND (state = 'pending' OR (state = 'running' AND locked_until < now()))
+    ORDER BY run_after
+    LIMIT 1
+    FOR UPDATE SKIP LOCKED
+)
+RETURNING id, kind, payload::text, attempts, max_attempts, repeat_secs
+        "#,
+        &kinds,
+        lock_secs,
+        worker
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(job)
+}
+
+/// Mark a job as succeeded, or schedule its next run if it's recurring. Returns false, leaving
+/// the job as it is, if `worker`'s lock on it expired, as it may have been claimed again
+pub async fn complete_job(pool: &DbPool, id: i64, worker: Uuid) -> DbResult<bool> {
+    let res = sqlx::query!(
+        r#"
+UPDATE jobs
+SET
+    state = CASE WHEN repeat_secs IS NULL THEN 'succeeded' ELSE 'pending' END,
+    run_after = now() + make_interval(secs => coalesce(repeat_secs, 0)),
+    attempts = CASE WHEN repeat_secs IS NULL THEN attempts ELSE 0 END,
+    locked_until = NULL,
+    locked_by = NULL,
+    last_error = NULL,
+    updated_at = now()
+WHERE id = $1 AND locked_by = $2 AND locked_until > now()
+        "#,
+        id,
+        worker
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() == 1)
+}
+
+/// Retry a failed job with exponential backoff, or move it to the dead state once it's out of
+/// attempts, or straight away unless `retry` is set. Recurring jobs are retried until they
+/// succeed, at least as often as they repeat. Returns false, as `complete_job` does, if
+/// `worker`'s lock on it expired
+pub async fn fail_job(
+    pool: &DbPool,
+    id: i64,
+    worker: Uuid,
+    error: &str,
+    retry: bool,
+) -> DbResult<bool> {
+    let res = sqlx::query!(
+        r#"
+UPDATE jobs
+SET
+    state = CASE
+        WHEN repeat_secs IS NULL AND (attempts >= max_attempts OR NOT $6) THEN 'dead'
+        ELSE 'pending'
+    END,
+    run_after = now() + make_interval(secs => least(
+        5 * power(2, least(attempts - 1, $4::integer)),
+        $5::integer,
+        coalesce(repeat_secs, $5::integer)
+    )),
+    locked_until = NULL,
+    locked_by = NULL,
+    last_error = $3,
+    updated_at = now()
+WHERE id = $1 AND locked_by = $2 AND locked_until > now()
+        "#,
+        id,
+        worker,
+        error,
+        MAX_BACKOFF_DOUBLINGS,
+        MAX_BACKOFF_SECS,
+        retry
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(res.rows_affected() == 1)
+}
+
+pub async fn prune_jobs(pool: &DbPool) -> DbResult<()> {
+    sqlx::query!(
+        r#"
+DELETE FROM jobs
+WHERE state = 'succeeded' AND updated_at < now() - make_interval(days => $1)
+        "#,
+        SUCCEEDED_JOB_RETENTION_DAYS
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// List jobs, most recently updated first
+pub async fn get_jobs(
+    pool: &DbPool,
+    state: Option<&str>,
+    kind: Option<&str>,
+    limit: i64,
+) -> DbResult<Vec<model::Job>> {
+    let jobs = sqlx::query_as!(
+        model::Job,
+        r#"
+SELECT
+    id,
+    kind,
+    payload::text,
+    state,
+    attempts,
+    max_attempts,
+    last_error,
+    run_after,
+    created_at,
+    updated_at
+FROM jobs
+WHERE ($1::text IS NULL OR state = $1) AND ($2::text IS NULL OR kind = $2)
+ORDER BY updated_at DESC, id DESC
+LIMIT $3
+        "#,
+        state,
+        kind,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(jobs)
+}
+
+/// Run a dead or pending job now, with its attempts reset. An email whose sending was interrupted
+/// is sent again, as an admin has chosen to risk a duplicate
+pub async fn retry_job(pool: &DbPool, id: i64) -> DbResult<model::Job> {
+    let mut tx = pool.begin().await?;
+    let job = sqlx::query_as!(
+        model::Job,
+        r#"
+UPDATE jobs
+SET state = 'pending', run_after = now(), attempts = 0, updated_at = now()
+WHERE id = $1 AND state IN ('pending', 'dead')
+RETURNING
+    id,
+    kind,
+    payload::text,
+    state,
+    attempts,
+    max_attempts,
+    last_error,
+    run_after,
+    created_at,
+    updated_at
+        "#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if let Some(job) = job {
+        if let Ok(Job::SendEmail { outbox_id }) = Job::from_parts(&job.kind, job.payload.as_deref())
+        {
+            outbox::release_email(&mut tx, outbox_id).await?;
+        }
+        tx.commit().await?;
+        return Ok(job);
+    }
+
+    let state = sqlx::query_scalar!("SELECT state FROM jobs WHERE id = $1", id)
+        .fetch_optional(pool)
+        .await?;
+    Err(DbError::FailedPrecondition(match state {
+        Some(state) => format!("job {} is {}", id, state),
+        None => format!("job {} not found", id),
+    }))
+}
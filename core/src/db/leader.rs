@@ -0,0 +1,39 @@
+use sqlx::PgConnection;
+
+use super::DbResult;
+
+/// Advisory lock class for leader locks. Classes 1 and 2 are customer locks, taken by purchase
+/// limit checks
+const LOCK_CLASS: i32 = 3;
+
+/// Take the session lock `lock_id` on `conn`, if no other session holds it. Held until it's
+/// unlocked or the connection closes
+pub async fn try_lock_leader(conn: &mut PgConnection, lock_id: i32) -> DbResult<bool> {
+    let locked = sqlx::query_scalar!(
+        r#"SELECT pg_try_advisory_lock($1, $2) as "locked!""#,
+        LOCK_CLASS,
+        lock_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(locked)
+}
+
+pub async fn unlock_leader(conn: &mut PgConnection, lock_id: i32) -> DbResult<()> {
+    sqlx::query_scalar!(
+        r#"SELECT pg_advisory_unlock($1, $2) as "unlocked!""#,
+        LOCK_CLASS,
+        lock_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Check the connection holding a lock is still open, and so the lock is still held
+pub async fn check_leader(conn: &mut PgConnection) -> DbResult<()> {
+    sqlx::query("SELECT 1").execute(conn).await?;
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use sqlx::types::Uuid;
+use sqlx::PgConnection;
+
+use super::error::DbError;
+use super::{DbPool, DbResult};
+use crate::model::PurchaseLimitOverride;
+
+/// Default number of tickets each customer may hold. `None` is unlimited.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PurchaseLimit(pub Option<i32>);
+
+/// Normalise an email so variations of the same mailbox count as one customer
+pub fn normalise_email(email: &str) -> String {
+    let email = email.trim().to_lowercase();
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return email;
+    };
+
+    // Sub-addresses (`name+tag@`) deliver to the same mailbox
+    let local = local.split('+').next().unwrap_or_default();
+
+    // Gmail ignores dots in the local part
+    match domain {
+        "gmail.com" | "googlemail.com" => format!("{}@gmail.com", local.replace('.', "")),
+        _ => format!("{}@{}", local, domain),
+    }
+}
+
+/// Normalise an address to lower-case words, ignoring punctuation and spacing
+pub fn normalise_address(address: &str) -> String {
+    address
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Users normalised in each batch by `normalise_users`
+const NORMALISE_BATCH_SIZE: i64 = 1000;
+
+/// Normalise the email and address of every user, in batches, as new users are. Returns how
+/// many users changed
+pub async fn normalise_users(pool: &DbPool) -> DbResult<u64> {
+    let mut updated = 0;
+    let mut after = Uuid::nil();
+    loop {
+        let users = sqlx::query!(
+            "SELECT id, email, address FROM users WHERE id > $1 ORDER BY id LIMIT $2",
+            after,
+            NORMALISE_BATCH_SIZE
+        )
+        .fetch_all(pool)
+        .await?;
+        let Some(last) = users.last() else {
+            return Ok(updated);
+        };
+        after = last.id;
+
+        let ids: Vec<Uuid> = users.iter().map(|u| u.id).collect();
+        let emails: Vec<String> = users.iter().map(|u| normalise_email(&u.email)).collect();
+        let addresses: Vec<String> = users
+            .iter()
+            .map(|u| normalise_address(&u.address))
+            .collect();
+        updated += sqlx::query!(
+            r#"
+UPDATE users
+SET email_normalised = t.email, address_normalised = t.address
+FROM UNNEST($1::uuid[], $2::text[], $3::text[]) AS t(id, email, address)
+WHERE users.id = t.id
+    AND (users.email_normalised <> t.email OR users.address_normalised <> t.address)
+            "#,
+            &ids,
+            &emails,
+            &addresses
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+    }
+}
+
+/// Check the customer can hold another ticket, with order `order_id`.
+///
+/// Tickets are counted across all orders sharing the normalised email or address, except comp
+/// tickets. A blank normalised email or address is nobody's, so isn't shared. The limit is the
+/// highest override for the email or for anyone at the address, or else `default_limit`, so a
+/// group booker's override covers the household they share it with. Customers aren't
+/// identified by session, as neither API has one: orders are anonymous until user info is added,
+/// and a client can start a new session as easily as a new order. When `purchased_only` is set
+/// only purchased orders are counted, otherwise live reservations are counted too.
+///
+/// Takes transaction-scoped advisory locks on the customer, so concurrent checks for the same
+/// customer are serialised until the calling transaction ends.
+pub(super) async fn check_purchase_limit(
+    conn: &mut PgConnection,
+    order_id: &Uuid,
+    email_normalised: &str,
+    address_normalised: &str,
+    default_limit: PurchaseLimit,
+    purchased_only: bool,
+) -> DbResult<()> {
+    // Always lock email then address, so customers sharing an address can't deadlock
+    for (key, value) in [(1, email_normalised), (2, address_normalised)] {
+        if !value.is_empty() {
+            sqlx::query("SELECT pg_advisory_xact_lock($1, hashtext($2))")
+                .bind(key)
+                .bind(value)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+
+    // Tickets are shared with the address, so an override for anyone at it is shared too
+    let override_limit = sqlx::query_scalar!(
+        r#"
+SELECT max(ticket_limit)
+FROM purchase_limit_overrides
+WHERE email_normalised = $1
+    OR (
+        $2 <> ''
+        AND email_normalised IN (SELECT email_normalised FROM users WHERE address_normalised = $2)
+    )
+        "#,
+        email_normalised,
+        address_normalised
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let Some(limit) = override_limit.or(default_limit.0) else {
+        return Ok(());
+    };
+
+    let held = sqlx::query_scalar!(
+        r#"
+SELECT COUNT(*) as "count!"
+FROM orders AS ord
+JOIN users ON users.id = ord.user_id
+WHERE ord.id <> $1
+    AND ord.comp_allocation IS NULL
+    AND (
+        ($2 <> '' AND users.email_normalised = $2)
+        OR ($3 <> '' AND users.address_normalised = $3)
+    )
+    AND (ord.purchased_at IS NOT NULL OR (NOT $4 AND ord.reserved_until > now()))
+        "#,
+        order_id,
+        email_normalised,
+        address_normalised,
+        purchased_only
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if held + 1 > limit as i64 {
+        return Err(DbError::FailedPrecondition(format!(
+            "purchase limit of {} tickets per customer reached",
+            limit
+        )));
+    }
+
+    Ok(())
+}
+
+pub async fn set_purchase_limit_override(
+    pool: &DbPool,
+    email: &str,
+    ticket_limit: i32,
+    note: Option<&str>,
+) -> DbResult<PurchaseLimitOverride> {
+    if ticket_limit < 0 {
+        return Err(DbError::FailedPrecondition(format!(
+            "ticket limit must not be negative, got {}",
+            ticket_limit
+        )));
+    }
+
+    let limit_override = sqlx::query_as!(
+        PurchaseLimitOverride,
+        r#"
+INSERT INTO purchase_limit_overrides (email_normalised, ticket_limit, note)
+VALUES ($1, $2, $3)
+ON CONFLICT (email_normalised) DO UPDATE
+SET ticket_limit = EXCLUDED.ticket_limit, note = EXCLUDED.note, updated_at = now()
+RETURNING email_normalised as email, ticket_limit, note
+        "#,
+        normalise_email(email),
+        ticket_limit,
+        note
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(limit_override)
+}
+
+pub async fn delete_purchase_limit_override(pool: &DbPool, email: &str) -> DbResult<()> {
+    sqlx::query!(
+        "DELETE FROM purchase_limit_overrides WHERE email_normalised = $1",
+        normalise_email(email)
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_normalisation() {
+        assert_eq!(
+            normalise_email(" Oscar+Tickets@Example.com "),
+            "oscar@example.com"
+        );
+        assert_eq!(
+            normalise_email("o.s.car+1@googlemail.com"),
+            "oscar@gmail.com"
+        );
+        assert_eq!(normalise_email("o.scar@example.com"), "o.scar@example.com");
+        assert_eq!(
+            normalise_address("22 Oscar St.,  Dorset, UK"),
+            normalise_address("22 oscar st dorset uk")
+        );
+    }
+}
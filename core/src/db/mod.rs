@@ -0,0 +1,586 @@
+use std::ops::Add;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::model::{NewUser, Order, OrderStats, TicketType, User};
+
+use crate::config::DatabaseConfig;
+use crate::metrics;
+use crate::tickets::TicketSigner;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use sqlx::postgres::{PgConnectOptions, PgListener, PgPoolOptions, Postgres};
+use sqlx::types::Uuid;
+use sqlx::{Connection, PgConnection, Row};
+use tokio::time::sleep;
+
+pub type DbPool = sqlx::Pool<Postgres>;
+
+pub mod checkin;
+pub mod comps;
+pub mod error;
+pub mod export;
+pub mod jobs;
+pub mod leader;
+pub mod limits;
+pub mod outbox;
+pub mod presale;
+pub mod promo;
+pub mod replica;
+pub mod tickets;
+pub mod webhooks;
+use error::DbError;
+use limits::PurchaseLimit;
+
+pub type DbResult<T> = Result<T, DbError>;
+
+/// Longest wait between attempts to connect at startup
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Connect to the database, retrying with backoff while it's unreachable, i.e. still starting
+pub async fn connect_to_pool(config: &DatabaseConfig) -> Result<DbPool, sqlx::Error> {
+    let options = connect_options(&config.url, config)?;
+    let pool_options = pool_options(config);
+
+    // Connect once before creating the pool, as the pool only reports timing out
+    let mut backoff = config.connect_backoff();
+    let mut attempt = 1;
+    loop {
+        match PgConnection::connect_with(&options).await {
+            Ok(conn) => {
+                let _ = conn.close().await;
+                break;
+            }
+            Err(e) if attempt < config.connect_attempts => {
+                log::warn!(
+                    "failed to connect to db (attempt {} of {}), retrying in {:?}: {}",
+                    attempt,
+                    config.connect_attempts,
+                    backoff,
+                    e
+                );
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let pool = pool_options.connect_with(options).await?;
+    metrics::observe_pool(&pool);
+    Ok(pool)
+}
+
+fn connect_options(url: &str, config: &DatabaseConfig) -> Result<PgConnectOptions, sqlx::Error> {
+    let options = PgConnectOptions::from_str(url)?;
+    Ok(match config.statement_timeout() {
+        Some(timeout) => options.options([("statement_timeout", timeout.as_millis().to_string())]),
+        None => options,
+    })
+}
+
+fn pool_options(config: &DatabaseConfig) -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(config.acquire_timeout())
+        .idle_timeout(config.idle_timeout())
+}
+
+/// Check the database answers queries
+pub async fn ping(pool: &DbPool) -> DbResult<()> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
+pub async fn get_ticket_types(pool: &DbPool) -> DbResult<Vec<TicketType>> {
+    let mut rows = sqlx::query("SELECT * FROM ticket_types").fetch(pool);
+
+    let mut ticket_types = Vec::new();
+    while let Some(row) = rows.try_next().await? {
+        ticket_types.push(TicketType {
+            id: row.try_get::<String, _>("id")?,
+            display: row.try_get::<String, _>("display")?,
+            sold_out: false,
+        });
+    }
+
+    Ok(ticket_types)
+}
+
+pub async fn get_ticket_durations(pool: &DbPool, _type_id: &str) -> DbResult<Vec<i32>> {
+    let rows = sqlx::query!("SELECT * FROM order_stats")
+        .fetch_all(pool)
+        .await?;
+
+    let mut durations = vec![];
+    for row in rows {
+        if row.order_limit > row.order_count.unwrap_or(0) {
+            durations.push(row.duration_days);
+        }
+    }
+
+    Ok(durations)
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn add_ticket_to_basket(
+    pool: &DbPool,
+    type_id: &str,
+    duration: i32,
+    presale_code: Option<&str>,
+    reserve_for: chrono::Duration,
+) -> DbResult<Order> {
+    let now = chrono::Utc::now();
+    let mut tx = pool.begin().await?;
+
+    // Inserting the order counts it, so lock the count before the presale code, in the same order
+    // as `remove_expired_orders` returns tickets to both
+    sqlx::query!(
+        "SELECT duration_days FROM order_stats WHERE duration_days = $1 FOR UPDATE",
+        duration
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let presale = presale::redeem_presale_code(&mut tx, presale_code, now).await?;
+    let (presale_id, presale_code, presale_email) = match presale {
+        Some(presale) => (Some(presale.presale_id), presale.code, presale.email),
+        None => (None, None, None),
+    };
+
+    let order = sqlx::query_as!(
+        Order,
+        r#"
+WITH ord as (
+        INSERT INTO orders (
+            ticket_type, reserved_until, duration_days, presale_id, presale_code, presale_email
+        )
+        VALUES ( $1, $2, $3::integer, $4, $5, $6 )
+        RETURNING *
+    )
+SELECT 
+    ord.id as "id!",
+    tt.id as "ticket_type_id!",
+    ord.user_id::text as "user_id",
+    ord.duration_days::integer as "duration!",
+    (ord.base_price - ord.discount)::real as "price!",
+    ord.reserved_until as "reserved_until!",
+    ord.purchased_at as purchased_at,
+    ord.promo_code,
+    ord.discount as "discount!"
+FROM ticket_types as tt
+JOIN ord ON tt.id = ord.ticket_type
+        "#,
+        type_id,
+        now.add(reserve_for),
+        duration as i32,
+        presale_id,
+        presale_code,
+        presale_email
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    webhooks::queue_order_event(&mut tx, "order.reserved", &order.id).await?;
+
+    tx.commit().await?;
+
+    Ok(order)
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn purchase_order(
+    pool: &DbPool,
+    order_id: &Uuid,
+    purchase_limit: PurchaseLimit,
+    signer: &TicketSigner,
+) -> DbResult<Order> {
+    let mut tx = pool.begin().await?;
+
+    let precond = sqlx::query!(
+        r#"
+SELECT
+    users.email_normalised as "email_normalised?",
+    users.address_normalised as "address_normalised?"
+FROM orders AS ord
+LEFT JOIN users ON users.id = ord.user_id
+WHERE ord.id = $1
+FOR UPDATE OF ord
+        "#,
+        order_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let (Some(email_normalised), Some(address_normalised)) =
+        (precond.email_normalised, precond.address_normalised)
+    else {
+        return Err(DbError::FailedPrecondition(format!(
+            "user info missing from order {}",
+            order_id
+        )));
+    };
+
+    limits::check_purchase_limit(
+        &mut tx,
+        order_id,
+        &email_normalised,
+        &address_normalised,
+        purchase_limit,
+        true,
+    )
+    .await?;
+    promo::check_per_customer_limit(&mut tx, order_id, &email_normalised, true).await?;
+
+    let order = sqlx::query_as!(
+        Order,
+        r#"
+with ord as (
+    UPDATE orders
+    SET purchased_at = $1
+    WHERE id = $2 AND purchased_at IS NULL
+    RETURNING *
+    )
+SELECT
+    ord.id as "id!",
+    tt.id as "ticket_type_id!",
+    ord.user_id::text as "user_id",
+    ord.duration_days::integer as "duration!",
+    (ord.base_price - ord.discount)::real as "price!",
+    ord.reserved_until as "reserved_until!",
+    ord.purchased_at as purchased_at,
+    ord.promo_code,
+    ord.discount as "discount!"
+FROM ticket_types as tt
+JOIN ord ON tt.id = ord.ticket_type
+        "#,
+        chrono::Utc::now(),
+        order_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tickets::issue_ticket(&mut tx, order_id, signer).await?;
+    outbox::queue_purchase_receipt(&mut tx, order_id).await?;
+    webhooks::queue_order_event(&mut tx, "order.purchased", order_id).await?;
+
+    tx.commit().await?;
+
+    Ok(order)
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn get_order(pool: &DbPool, order_id: &Uuid) -> DbResult<Option<Order>> {
+    let order = sqlx::query_as!(
+        Order,
+        r#"
+SELECT
+    ord.id as "id!",
+    tt.id as "ticket_type_id!",
+    ord.user_id::text as "user_id",
+    ord.duration_days::integer as "duration!",
+    (ord.base_price - ord.discount)::real as "price!",
+    ord.reserved_until as "reserved_until!",
+    ord.purchased_at as purchased_at,
+    ord.promo_code,
+    ord.discount as "discount!"
+FROM orders as ord
+JOIN ticket_types as tt ON tt.id = ord.ticket_type
+WHERE ord.id = $1
+        "#,
+        order_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(order)
+}
+
+pub async fn get_user(pool: &DbPool, user_id: &Uuid) -> DbResult<Option<User>> {
+    let user = sqlx::query_as!(
+        User,
+        r#"
+SELECT
+    users.id as "id!",
+    users.name as "name!",
+    users.address as "address!",
+    users.email as "email!",
+    ord.id as "order_id!"
+FROM users
+JOIN orders AS ord ON ord.user_id = users.id
+WHERE users.id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user)
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn add_user_to_order(
+    pool: &DbPool,
+    order_id: &Uuid,
+    user_info: &NewUser,
+    purchase_limit: PurchaseLimit,
+) -> DbResult<Order> {
+    let email_normalised = limits::normalise_email(&user_info.email);
+    let address_normalised = limits::normalise_address(&user_info.address);
+    // Customers are told apart by these, so every blank one would count as the same customer
+    if email_normalised.is_empty() || address_normalised.is_empty() {
+        return Err(DbError::FailedPrecondition(
+            "email and address must not be blank".to_string(),
+        ));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    // A user already attached is replaced, so the new one is checked the same way, until the
+    // order is purchased
+    let purchased_at = sqlx::query_scalar!(
+        "SELECT purchased_at FROM orders WHERE id = $1 FOR UPDATE",
+        order_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| DbError::FailedPrecondition(format!("order {} not found", order_id)))?;
+    if purchased_at.is_some() {
+        return Err(DbError::FailedPrecondition(format!(
+            "order {} has already been purchased",
+            order_id
+        )));
+    }
+
+    presale::check_presale_email(&mut tx, order_id, &user_info.email).await?;
+    limits::check_purchase_limit(
+        &mut tx,
+        order_id,
+        &email_normalised,
+        &address_normalised,
+        purchase_limit,
+        false,
+    )
+    .await?;
+    promo::check_per_customer_limit(&mut tx, order_id, &email_normalised, false).await?;
+
+    let user = sqlx::query!(
+        r#"
+INSERT INTO users (name, address, email, email_normalised, address_normalised)
+VALUES ($1, $2, $3, $4, $5)
+RETURNING *
+        "#,
+        user_info.name,
+        user_info.address,
+        user_info.email,
+        email_normalised,
+        address_normalised
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let _update = sqlx::query!(
+        r#"
+UPDATE orders
+SET user_id = $2
+WHERE id = $1
+RETURNING *
+        "#,
+        order_id,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    outbox::queue_reservation_confirmation(&mut tx, order_id).await?;
+
+    let order = sqlx::query_as!(
+        Order,
+        r#"
+SELECT 
+    ord.id as "id!",
+    tt.id as "ticket_type_id!",
+    ord.user_id::text as "user_id",
+    ord.duration_days::integer as "duration!",
+    (ord.base_price - ord.discount)::real as "price!",
+    ord.reserved_until as "reserved_until!",
+    ord.purchased_at as purchased_at,
+    ord.promo_code,
+    ord.discount as "discount!"
+FROM orders as ord
+JOIN ticket_types as tt ON tt.id = ord.ticket_type
+WHERE ord.id = $1
+        "#,
+        order_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(order)
+}
+
+pub async fn get_order_stats(pool: &DbPool) -> DbResult<Vec<OrderStats>> {
+    let order_stats = sqlx::query_as!(
+        OrderStats,
+        r#"
+SELECT
+    duration_days::integer as "duration_days!",
+    order_limit::integer as "order_limit!",
+    order_count::integer as "order_count!"
+FROM order_stats"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(order_stats)
+}
+
+/// Channel order stats are published to, by the instance leading `leader::Singleton::OrderStats`
+const ORDER_STATS_CHANNEL: &str = "order_stats";
+
+/// Publish the current order stats to every instance listening with `listen_order_stats`
+pub async fn publish_order_stats(pool: &DbPool) -> DbResult<()> {
+    sqlx::query(
+        r#"
+SELECT pg_notify($1, coalesce(json_agg(json_build_object(
+    'duration_days', duration_days::integer,
+    'order_limit', order_limit::integer,
+    'order_count', coalesce(order_count, 0)::integer
+)), '[]')::text)
+FROM order_stats"#,
+    )
+    .bind(ORDER_STATS_CHANNEL)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Order stats as they're published. Holds a connection from the pool while listening, and
+/// reconnects if it's lost
+pub async fn listen_order_stats(
+    pool: &DbPool,
+) -> DbResult<BoxStream<'static, DbResult<Vec<OrderStats>>>> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(ORDER_STATS_CHANNEL).await?;
+
+    let stats = listener.into_stream().map(|notification| {
+        let notification = notification?;
+        serde_json::from_str(notification.payload())
+            .map_err(|e| DbError::from(sqlx::Error::Decode(Box::new(e))))
+    });
+    Ok(stats.boxed())
+}
+
+/// Channel notified by triggers when ticket types or order limits change, with the table changed
+const CATALOGUE_CHANNEL: &str = "catalogue";
+
+/// Payload on `CATALOGUE_CHANNEL` when inventory was taken other than by reservations
+const INVENTORY_CHANGED: &str = "orders";
+
+/// Tell every instance to drop cached availability once the transaction on `conn` commits, as
+/// they do when order limits change
+async fn notify_inventory_changed(conn: &mut PgConnection) -> DbResult<()> {
+    sqlx::query!(
+        "SELECT pg_notify($1, $2)",
+        CATALOGUE_CHANNEL,
+        INVENTORY_CHANGED
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Change affecting the catalogue, from `listen_catalogue_changes`
+#[derive(Debug)]
+pub enum CatalogueChange {
+    /// Ticket types or order limits changed
+    Catalogue,
+    /// Inventory was taken other than by reservations, i.e. by importing comps
+    Inventory,
+    /// Order stats were published
+    OrderStats(Vec<OrderStats>),
+}
+
+/// Catalogue changes and order stats as they're published. Holds a connection from the pool
+/// while listening, and reconnects if it's lost
+pub async fn listen_catalogue_changes(
+    pool: &DbPool,
+) -> DbResult<BoxStream<'static, DbResult<CatalogueChange>>> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener
+        .listen_all([CATALOGUE_CHANNEL, ORDER_STATS_CHANNEL])
+        .await?;
+
+    let changes = listener.into_stream().map(|notification| {
+        let notification = notification?;
+        if notification.channel() == CATALOGUE_CHANNEL {
+            if notification.payload() == INVENTORY_CHANGED {
+                return Ok(CatalogueChange::Inventory);
+            }
+            return Ok(CatalogueChange::Catalogue);
+        }
+        serde_json::from_str(notification.payload())
+            .map(CatalogueChange::OrderStats)
+            .map_err(|e| DbError::from(sqlx::Error::Decode(Box::new(e))))
+    });
+    Ok(changes.boxed())
+}
+
+/// Delete reservations which weren't purchased in time. Returns the ticket type and duration of
+/// each one
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn remove_expired_orders(pool: &DbPool) -> DbResult<Vec<(String, i32)>> {
+    let mut tx = pool.begin().await?;
+
+    let expired = sqlx::query!(
+        r#"
+SELECT id, ticket_type, duration_days::integer as "duration!"
+FROM orders
+WHERE reserved_until < $1 AND purchased_at IS NULL
+FOR UPDATE SKIP LOCKED
+        "#,
+        chrono::Utc::now()
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let order_ids: Vec<Uuid> = expired.iter().map(|o| o.id).collect();
+    for order_id in &order_ids {
+        webhooks::queue_order_event(&mut tx, "order.expired", order_id).await?;
+    }
+
+    // Deleting returns each order to its duration's count, then its presale and promo codes. Lock
+    // the counts in order first, so concurrent runs expiring several durations can't deadlock
+    // each other, or reservations, which lock the count before the presale code too
+    let mut durations: Vec<i32> = expired.iter().map(|o| o.duration).collect();
+    durations.sort_unstable();
+    durations.dedup();
+    sqlx::query!(
+        r#"
+SELECT duration_days
+FROM order_stats
+WHERE duration_days = ANY($1)
+ORDER BY duration_days
+FOR UPDATE
+        "#,
+        &durations
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM orders WHERE id = ANY($1)", &order_ids)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(expired
+        .into_iter()
+        .map(|o| (o.ticket_type, o.duration))
+        .collect())
+}
@@ -0,0 +1,205 @@
+use sqlx::types::Uuid;
+use sqlx::PgConnection;
+
+use super::{jobs, DbPool, DbResult};
+use crate::jobs::Job;
+use crate::mail::templates::{self, OrderDetails};
+use crate::mail::Email;
+
+/// How long before a reservation expires the holder is warned
+const EXPIRY_WARNING_MINUTES: i64 = 3;
+
+/// Queue an email, unless one was already queued for `dedupe_key`, with a job to send it.
+///
+/// Queue emails in the same transaction as the change they're about, so they're sent if and
+/// only if the change is committed.
+pub(super) async fn queue_email(
+    conn: &mut PgConnection,
+    dedupe_key: &str,
+    template: &str,
+    email: &Email,
+) -> DbResult<()> {
+    let outbox_id = sqlx::query_scalar!(
+        r#"
+INSERT INTO email_outbox (dedupe_key, template, recipient, subject, body)
+VALUES ($1, $2, $3, $4, $5)
+ON CONFLICT (dedupe_key) DO NOTHING
+RETURNING id
+        "#,
+        dedupe_key,
+        template,
+        email.to,
+        email.subject,
+        email.body
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if let Some(outbox_id) = outbox_id {
+        jobs::enqueue_job(conn, &Job::SendEmail { outbox_id }).await?;
+    }
+
+    Ok(())
+}
+
+async fn order_details(conn: &mut PgConnection, order_id: &Uuid) -> DbResult<OrderDetails> {
+    let details = sqlx::query_as!(
+        OrderDetails,
+        r#"
+SELECT
+    ord.id::text as "order_id!",
+    users.name as "holder_name!",
+    users.email as "email!",
+    tt.display as "ticket_type!",
+    ord.duration_days::integer as "duration!",
+    (ord.base_price - ord.discount)::real as "price!",
+    ord.reserved_until as "reserved_until!"
+FROM orders AS ord
+JOIN users ON users.id = ord.user_id
+JOIN ticket_types AS tt ON tt.id = ord.ticket_type
+WHERE ord.id = $1
+        "#,
+        order_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(details)
+}
+
+/// Queue the reservation confirmation for an order with user info
+pub(super) async fn queue_reservation_confirmation(
+    conn: &mut PgConnection,
+    order_id: &Uuid,
+) -> DbResult<()> {
+    let order = order_details(conn, order_id).await?;
+    queue_email(
+        conn,
+        &format!("reservation:{}", order_id),
+        "reservation_confirmation",
+        &templates::reservation_confirmation(&order),
+    )
+    .await
+}
+
+/// Queue the receipt for a purchased order, with its ticket
+pub(super) async fn queue_purchase_receipt(
+    conn: &mut PgConnection,
+    order_id: &Uuid,
+) -> DbResult<()> {
+    let order = order_details(conn, order_id).await?;
+    let credential = sqlx::query_scalar!(
+        "SELECT credential FROM tickets WHERE order_id = $1",
+        order_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    queue_email(
+        conn,
+        &format!("receipt:{}", order_id),
+        "purchase_receipt",
+        &templates::purchase_receipt(&order, &credential),
+    )
+    .await
+}
+
+/// Queue warnings for reserved orders which are about to expire
+pub async fn queue_expiry_warnings(pool: &DbPool) -> DbResult<()> {
+    let mut tx = pool.begin().await?;
+
+    let order_ids = sqlx::query_scalar!(
+        r#"
+SELECT ord.id
+FROM orders AS ord
+WHERE ord.purchased_at IS NULL
+    AND ord.user_id IS NOT NULL
+    AND ord.reserved_until > now()
+    AND ord.reserved_until < now() + make_interval(mins => $1::integer)
+    AND NOT EXISTS (
+        SELECT 1 FROM email_outbox WHERE dedupe_key = 'expiry-warning:' || ord.id::text
+    )
+        "#,
+        EXPIRY_WARNING_MINUTES as i32
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for order_id in order_ids {
+        let order = order_details(&mut tx, &order_id).await?;
+        queue_email(
+            &mut tx,
+            &format!("expiry-warning:{}", order_id),
+            "expiry_warning",
+            &templates::expiry_warning(&order),
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// An email from the outbox, as found by `claim_email`
+#[derive(Debug, PartialEq)]
+pub enum EmailClaim {
+    /// Claimed for sending. Send it, then `mark_email_sent`, or `release_email` if sending failed
+    Claimed(Email),
+    Sent,
+    /// An earlier attempt was sending it when it stopped, so it may have been sent
+    Interrupted,
+}
+
+/// Claim an email from the outbox before sending it, so a retry can tell if the worker died, or
+/// lost the database, after handing it to the mailer. Such an email isn't sent again
+/// automatically, as it may have gone out, but its job is dead-lettered, and `jobs::retry_job`
+/// resends it. Emails are never silently dropped, and only duplicated when an admin chooses to.
+pub async fn claim_email(pool: &DbPool, id: i64) -> DbResult<EmailClaim> {
+    let claimed = sqlx::query!(
+        r#"
+UPDATE email_outbox AS e
+SET sending_at = now()
+FROM (SELECT id, sending_at FROM email_outbox WHERE id = $1 AND sent_at IS NULL FOR UPDATE) AS prev
+WHERE e.id = prev.id
+RETURNING
+    e.recipient as "to",
+    e.subject,
+    e.body,
+    prev.sending_at as "previously_sending_at"
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(match claimed {
+        None => EmailClaim::Sent,
+        Some(c) if c.previously_sending_at.is_some() => EmailClaim::Interrupted,
+        Some(c) => EmailClaim::Claimed(Email {
+            to: c.to,
+            subject: c.subject,
+            body: c.body,
+        }),
+    })
+}
+
+/// Give up a claim on an email the mailer failed to send, so it's sent by the next attempt
+pub async fn release_email(conn: &mut PgConnection, id: i64) -> DbResult<()> {
+    sqlx::query!(
+        "UPDATE email_outbox SET sending_at = NULL WHERE id = $1 AND sent_at IS NULL",
+        id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn mark_email_sent(pool: &DbPool, id: i64) -> DbResult<()> {
+    sqlx::query!("UPDATE email_outbox SET sent_at = now() WHERE id = $1", id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,481 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use sqlx::PgConnection;
+
+use super::error::DbError;
+use super::{DbPool, DbResult};
+use crate::model::{Presale, PresaleCode};
+use serde::Serialize;
+
+/// Presale code or allow-listed email parsed from an import CSV
+#[derive(Debug, PartialEq)]
+pub struct NewPresaleCode {
+    /// `None` for allow-list entries, which are redeemed with the email instead
+    pub code: Option<String>,
+    pub email: Option<String>,
+    pub ticket_limit: i32,
+}
+
+/// What an order was reserved with during a presale: one of its codes, or an allow-listed email
+#[derive(Debug)]
+pub(super) struct Redemption {
+    pub presale_id: String,
+    pub code: Option<String>,
+    pub email: Option<String>,
+}
+
+/// Result of a presale code import
+#[derive(Debug, Default, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ImportReport {
+    pub imported: i32,
+    /// Rows which weren't imported, and why
+    pub rejected: Vec<String>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct CsvRow {
+    code: Option<String>,
+    email: Option<String>,
+    ticket_limit: Option<i32>,
+}
+
+/// Presale codes are case-insensitive, and stored lower-case
+fn normalise_code(code: &str) -> String {
+    code.trim().to_lowercase()
+}
+
+/// Parse presale codes from a CSV with header `code,email,ticket_limit`.
+///
+/// Rows with only an email are allow-list entries, which customers redeem by entering the email as
+/// the code. Rows with both can only be used by that email.
+pub fn parse_presale_codes_csv(
+    csv: &str,
+    default_ticket_limit: i32,
+) -> (Vec<NewPresaleCode>, Vec<String>) {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(csv.as_bytes());
+
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => return (vec![], vec![format!("invalid header: {}", e)]),
+    };
+
+    let mut codes = vec![];
+    let mut rejected = vec![];
+    let mut seen_codes = HashSet::new();
+    let mut seen_emails = HashSet::new();
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                rejected.push(e.to_string());
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let mut reject = |reason: String| rejected.push(format!("line {}: {}", line, reason));
+
+        let row: CsvRow = match record.deserialize(Some(&headers)) {
+            Ok(row) => row,
+            Err(e) => {
+                reject(e.to_string());
+                continue;
+            }
+        };
+
+        let email = row
+            .email
+            .map(|e| e.to_lowercase())
+            .filter(|e| !e.is_empty());
+        if let Some(email) = &email {
+            if !email.contains('@') {
+                reject(format!("invalid email {}", email));
+                continue;
+            }
+        }
+
+        let code = row
+            .code
+            .map(|c| normalise_code(&c))
+            .filter(|c| !c.is_empty());
+        if code.is_none() && email.is_none() {
+            reject("missing code and email".to_string());
+            continue;
+        }
+
+        let ticket_limit = row.ticket_limit.unwrap_or(default_ticket_limit);
+        if ticket_limit <= 0 {
+            reject(format!(
+                "ticket_limit must be positive, got {}",
+                ticket_limit
+            ));
+            continue;
+        }
+
+        match (&code, &email) {
+            (Some(code), _) if !seen_codes.insert(code.clone()) => {
+                reject(format!("duplicate code {}", code));
+                continue;
+            }
+            (None, Some(email)) if !seen_emails.insert(email.clone()) => {
+                reject(format!("duplicate email {}", email));
+                continue;
+            }
+            _ => {}
+        }
+
+        codes.push(NewPresaleCode {
+            code,
+            email,
+            ticket_limit,
+        });
+    }
+
+    (codes, rejected)
+}
+
+/// Redeem a presale code, or an allow-listed email entered as the code, against a new order, if a
+/// presale is running.
+///
+/// Returns what to record against the order, or `None` outside of presales.
+pub(super) async fn redeem_presale_code(
+    conn: &mut PgConnection,
+    code: Option<&str>,
+    now: DateTime<Utc>,
+) -> DbResult<Option<Redemption>> {
+    let presale_running = sqlx::query_scalar!(
+        r#"
+SELECT EXISTS(SELECT 1 FROM presales WHERE starts_at <= $1 AND $1 < ends_at) as "running!"
+        "#,
+        now
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if !presale_running {
+        return Ok(None);
+    }
+
+    let code = code.map(normalise_code).ok_or_else(|| {
+        DbError::FailedPrecondition("a presale code is required during the presale".to_string())
+    })?;
+
+    // Conditional updates lock the code or allow-list row, so the ticket limit can't be exceeded.
+    // If presales overlap, the earliest to start with the code left is used
+    let presale_id = sqlx::query_scalar!(
+        r#"
+UPDATE presale_codes
+SET redeemed = redeemed + 1
+WHERE (presale_id, code) = (
+        SELECT pc.presale_id, pc.code
+        FROM presale_codes AS pc
+        JOIN presales AS p ON p.id = pc.presale_id
+        WHERE pc.code = $1 AND pc.redeemed < pc.ticket_limit AND p.starts_at <= $2 AND $2 < p.ends_at
+        ORDER BY p.starts_at
+        LIMIT 1
+        FOR UPDATE OF pc
+    )
+    AND redeemed < ticket_limit
+RETURNING presale_id
+        "#,
+        code,
+        now
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(presale_id) = presale_id {
+        return Ok(Some(Redemption {
+            presale_id,
+            code: Some(code),
+            email: None,
+        }));
+    }
+
+    let presale_id = sqlx::query_scalar!(
+        r#"
+UPDATE presale_allow_list
+SET redeemed = redeemed + 1
+WHERE (presale_id, email) = (
+        SELECT al.presale_id, al.email
+        FROM presale_allow_list AS al
+        JOIN presales AS p ON p.id = al.presale_id
+        WHERE al.email = $1 AND al.redeemed < al.ticket_limit AND p.starts_at <= $2 AND $2 < p.ends_at
+        ORDER BY p.starts_at
+        LIMIT 1
+        FOR UPDATE OF al
+    )
+    AND redeemed < ticket_limit
+RETURNING presale_id
+        "#,
+        code,
+        now
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match presale_id {
+        Some(presale_id) => Ok(Some(Redemption {
+            presale_id,
+            code: None,
+            email: Some(code),
+        })),
+        None => Err(DbError::FailedPrecondition(format!(
+            "presale code {} is not valid or has no tickets remaining",
+            code
+        ))),
+    }
+}
+
+/// Check the order's user email matches the allow-listed email it was reserved with, or the email
+/// of its presale code, if any
+pub(super) async fn check_presale_email(
+    conn: &mut PgConnection,
+    order_id: &Uuid,
+    email: &str,
+) -> DbResult<()> {
+    let allowed_email = sqlx::query_scalar!(
+        r#"
+SELECT coalesce(ord.presale_email, pc.email)
+FROM orders AS ord
+LEFT JOIN presale_codes AS pc ON pc.presale_id = ord.presale_id AND pc.code = ord.presale_code
+WHERE ord.id = $1
+        "#,
+        order_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .flatten();
+
+    match allowed_email {
+        Some(allowed) if !allowed.eq_ignore_ascii_case(email.trim()) => {
+            Err(DbError::FailedPrecondition(format!(
+                "email for order {} doesn't match the presale allow-list",
+                order_id
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub async fn upsert_presale(
+    pool: &DbPool,
+    id: &str,
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+) -> DbResult<Presale> {
+    if starts_at >= ends_at {
+        return Err(DbError::FailedPrecondition(format!(
+            "presale {} must start before it ends",
+            id
+        )));
+    }
+
+    let presale = sqlx::query_as!(
+        Presale,
+        r#"
+INSERT INTO presales (id, starts_at, ends_at)
+VALUES ($1, $2, $3)
+ON CONFLICT (id) DO UPDATE
+SET starts_at = EXCLUDED.starts_at, ends_at = EXCLUDED.ends_at
+RETURNING
+    id,
+    starts_at,
+    ends_at
+        "#,
+        id,
+        starts_at,
+        ends_at
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(presale)
+}
+
+/// Bulk import presale codes and allow-listed emails from CSV. Existing codes and emails are left
+/// untouched and reported as rejected.
+pub async fn import_presale_codes(
+    pool: &DbPool,
+    presale_id: &str,
+    csv: &str,
+    default_ticket_limit: i32,
+) -> DbResult<ImportReport> {
+    let (codes, mut rejected) = parse_presale_codes_csv(csv, default_ticket_limit);
+
+    let mut tx = pool.begin().await?;
+
+    let presale_exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM presales WHERE id = $1) as "exists!""#,
+        presale_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !presale_exists {
+        return Err(DbError::FailedPrecondition(format!(
+            "presale {} not found",
+            presale_id
+        )));
+    }
+
+    let (mut code_col, mut email_col, mut limit_col) = (vec![], vec![], vec![]);
+    let (mut allowed_col, mut allowed_limit_col) = (vec![], vec![]);
+    for new in codes {
+        match (new.code, new.email) {
+            (Some(code), email) => {
+                code_col.push(code);
+                email_col.push(email);
+                limit_col.push(new.ticket_limit);
+            }
+            (None, Some(email)) => {
+                allowed_col.push(email);
+                allowed_limit_col.push(new.ticket_limit);
+            }
+            (None, None) => {}
+        }
+    }
+
+    let inserted: HashSet<String> = sqlx::query_scalar!(
+        r#"
+INSERT INTO presale_codes (code, presale_id, email, ticket_limit)
+SELECT code, $1, email, ticket_limit
+FROM UNNEST($2::varchar[], $3::text[], $4::integer[]) AS t(code, email, ticket_limit)
+ON CONFLICT (presale_id, code) DO NOTHING
+RETURNING code
+        "#,
+        presale_id,
+        &code_col,
+        &email_col as &[Option<String>],
+        &limit_col
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .collect();
+
+    let allowed: HashSet<String> = sqlx::query_scalar!(
+        r#"
+INSERT INTO presale_allow_list (presale_id, email, ticket_limit)
+SELECT $1, email, ticket_limit
+FROM UNNEST($2::text[], $3::integer[]) AS t(email, ticket_limit)
+ON CONFLICT (presale_id, email) DO NOTHING
+RETURNING email
+        "#,
+        presale_id,
+        &allowed_col,
+        &allowed_limit_col
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .collect();
+
+    tx.commit().await?;
+
+    rejected.extend(
+        code_col
+            .iter()
+            .filter(|c| !inserted.contains(*c))
+            .map(|c| format!("code {} already exists", c)),
+    );
+    rejected.extend(
+        allowed_col
+            .iter()
+            .filter(|e| !allowed.contains(*e))
+            .map(|e| format!("email {} is already allow-listed", e)),
+    );
+
+    Ok(ImportReport {
+        imported: (inserted.len() + allowed.len()) as i32,
+        rejected,
+    })
+}
+
+/// A presale's codes and allow-listed emails. Allow-list entries are listed with the email as
+/// the code, as customers enter it
+pub async fn get_presale_codes(pool: &DbPool, presale_id: &str) -> DbResult<Vec<PresaleCode>> {
+    let codes = sqlx::query_as!(
+        PresaleCode,
+        r#"
+SELECT
+    pc.code as "code!",
+    pc.presale_id as "presale_id!",
+    pc.email,
+    pc.ticket_limit as "ticket_limit!",
+    pc.redeemed as "redeemed!",
+    (
+        SELECT COUNT(*)
+        FROM orders AS ord
+        WHERE ord.presale_id = pc.presale_id
+            AND ord.presale_code = pc.code
+            AND ord.purchased_at IS NOT NULL
+    )::integer as "purchased!"
+FROM presale_codes AS pc
+WHERE pc.presale_id = $1
+UNION ALL
+SELECT
+    al.email,
+    al.presale_id,
+    al.email,
+    al.ticket_limit,
+    al.redeemed,
+    (
+        SELECT COUNT(*)
+        FROM orders AS ord
+        WHERE ord.presale_id = al.presale_id
+            AND ord.presale_email = al.email
+            AND ord.purchased_at IS NOT NULL
+    )::integer
+FROM presale_allow_list AS al
+WHERE al.presale_id = $1
+ORDER BY 1
+        "#,
+        presale_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_presale_csv() {
+        let csv = "code,email,ticket_limit
+EARLY-1,,4
+,Fan@Example.com,
+early-1,,2
+,,3
+,not-an-email,
+BAD,,0
+";
+        let (codes, rejected) = parse_presale_codes_csv(csv, 2);
+
+        assert_eq!(
+            codes,
+            vec![
+                NewPresaleCode {
+                    code: Some("early-1".to_string()),
+                    email: None,
+                    ticket_limit: 4,
+                },
+                NewPresaleCode {
+                    code: None,
+                    email: Some("fan@example.com".to_string()),
+                    ticket_limit: 2,
+                },
+            ]
+        );
+        assert_eq!(rejected.len(), 4, "{:#?}", rejected);
+        assert!(rejected[0].starts_with("line 4: duplicate code"));
+    }
+}
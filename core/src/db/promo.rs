@@ -0,0 +1,340 @@
+use chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use sqlx::PgConnection;
+
+use super::error::DbError;
+use super::{DbPool, DbResult};
+use crate::model::Order;
+
+/// How a promo code's `discount_value` is taken off the price. Stored as text, constrained to
+/// these values
+#[derive(Clone, Copy, Debug, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum DiscountKind {
+    /// Percentage of the price
+    Percentage,
+    /// Fixed amount
+    Fixed,
+}
+
+/// Promo code, as stored in the `promo_codes` table
+#[derive(Debug)]
+pub struct PromoCode {
+    pub code: String,
+    pub discount_kind: DiscountKind,
+    pub discount_value: f32,
+    pub ticket_types: Option<Vec<String>>,
+    pub durations: Option<Vec<i32>>,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub per_customer_limit: Option<i32>,
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl PromoCode {
+    /// Check the code can be used at `now` for the given ticket type and duration
+    fn check_applicable(
+        &self,
+        now: DateTime<Utc>,
+        ticket_type: &str,
+        duration: i32,
+    ) -> DbResult<()> {
+        let fail = |reason: &str| {
+            Err(DbError::FailedPrecondition(format!(
+                "promo code {} {}",
+                self.code, reason
+            )))
+        };
+
+        if self.valid_from.is_some_and(|from| now < from) {
+            return fail("is not valid yet");
+        }
+        if self.valid_until.is_some_and(|until| now >= until) {
+            return fail("has expired");
+        }
+        if let Some(types) = &self.ticket_types {
+            if !types.iter().any(|t| t == ticket_type) {
+                return fail(&format!("is not valid for ticket type {}", ticket_type));
+            }
+        }
+        if let Some(durations) = &self.durations {
+            if !durations.contains(&duration) {
+                return fail(&format!("is not valid for {} day tickets", duration));
+            }
+        }
+        if self.max_uses.is_some_and(|max| self.use_count >= max) {
+            return fail("has been fully redeemed");
+        }
+
+        Ok(())
+    }
+
+    /// Discount given by the code on `base_price`. Never more than the price itself.
+    fn discount_for(&self, base_price: f32) -> f32 {
+        let discount = match self.discount_kind {
+            DiscountKind::Percentage => base_price * self.discount_value / 100.0,
+            DiscountKind::Fixed => self.discount_value,
+        };
+        discount.clamp(0.0, base_price)
+    }
+}
+
+/// Apply a promo code to a reserved order, while its reservation lasts.
+///
+/// The promo code row is locked for the duration of the transaction, so concurrent
+/// redemptions are serialised and `max_uses` can't be exceeded.
+pub async fn apply_promo_code(pool: &DbPool, order_id: &Uuid, code: &str) -> DbResult<Order> {
+    let mut tx = pool.begin().await?;
+
+    let order = sqlx::query!(
+        r#"
+SELECT
+    ord.ticket_type,
+    ord.duration_days::integer as "duration!",
+    ord.base_price,
+    ord.promo_code,
+    ord.purchased_at,
+    ord.reserved_until > now() as "reserved!",
+    users.email_normalised as "email_normalised?"
+FROM orders AS ord
+LEFT JOIN users ON users.id = ord.user_id
+WHERE ord.id = $1
+FOR UPDATE OF ord
+        "#,
+        order_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| DbError::FailedPrecondition(format!("order {} not found", order_id)))?;
+
+    if order.purchased_at.is_some() {
+        return Err(DbError::FailedPrecondition(format!(
+            "order {} has already been purchased",
+            order_id
+        )));
+    }
+    // Lapsed but not yet removed by expiry, so a use would be spent on an order about to go
+    if !order.reserved {
+        return Err(DbError::FailedPrecondition(format!(
+            "reservation expired for order {}",
+            order_id
+        )));
+    }
+    if let Some(applied) = order.promo_code {
+        return Err(DbError::FailedPrecondition(format!(
+            "promo code {} already applied to order {}",
+            applied, order_id
+        )));
+    }
+
+    let promo = sqlx::query_as!(
+        PromoCode,
+        r#"
+SELECT
+    code,
+    discount_kind as "discount_kind: DiscountKind",
+    discount_value,
+    ticket_types,
+    durations,
+    max_uses,
+    use_count,
+    per_customer_limit,
+    valid_from,
+    valid_until
+FROM promo_codes
+WHERE code = $1
+FOR UPDATE
+        "#,
+        code
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| DbError::FailedPrecondition(format!("promo code {} is not valid", code)))?;
+
+    promo.check_applicable(chrono::Utc::now(), &order.ticket_type, order.duration)?;
+
+    if let Some(limit) = promo.per_customer_limit {
+        let email_normalised = order.email_normalised.ok_or_else(|| {
+            DbError::FailedPrecondition(format!(
+                "user info must be added to order {} before applying promo code {}",
+                order_id, code
+            ))
+        })?;
+        check_uses_by_customer(&mut tx, code, limit, order_id, &email_normalised, false).await?;
+    }
+
+    sqlx::query!(
+        "UPDATE promo_codes SET use_count = use_count + 1 WHERE code = $1",
+        code
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let order = sqlx::query_as!(
+        Order,
+        r#"
+with ord as (
+    UPDATE orders
+    SET promo_code = $2, discount = $3
+    WHERE id = $1
+    RETURNING *
+    )
+SELECT
+    ord.id as "id!",
+    tt.id as "ticket_type_id!",
+    ord.user_id::text as "user_id",
+    ord.duration_days::integer as "duration!",
+    (ord.base_price - ord.discount)::real as "price!",
+    ord.reserved_until as "reserved_until!",
+    ord.purchased_at as purchased_at,
+    ord.promo_code,
+    ord.discount as "discount!"
+FROM ticket_types as tt
+JOIN ord ON tt.id = ord.ticket_type
+        "#,
+        order_id,
+        code,
+        promo.discount_for(order.base_price)
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(order)
+}
+
+/// Check the customer of order `order_id` can still use its promo code, if it has one with a
+/// per-customer limit.
+///
+/// Checked again whenever the order's user changes, and when it's purchased, as the code may
+/// have been applied for a different customer. Callers serialise checks for the same customer
+/// with `limits::check_purchase_limit`.
+pub(super) async fn check_per_customer_limit(
+    conn: &mut PgConnection,
+    order_id: &Uuid,
+    email_normalised: &str,
+    purchased_only: bool,
+) -> DbResult<()> {
+    let promo = sqlx::query!(
+        r#"
+SELECT pc.code, pc.per_customer_limit as "limit!"
+FROM orders AS ord
+JOIN promo_codes AS pc ON pc.code = ord.promo_code
+WHERE ord.id = $1 AND pc.per_customer_limit IS NOT NULL
+        "#,
+        order_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match promo {
+        Some(promo) => {
+            check_uses_by_customer(
+                conn,
+                &promo.code,
+                promo.limit,
+                order_id,
+                email_normalised,
+                purchased_only,
+            )
+            .await
+        }
+        None => Ok(()),
+    }
+}
+
+/// Check the customer's other orders with `code` leave room for one more. When `purchased_only`
+/// is set only purchased orders are counted, otherwise live reservations are counted too.
+async fn check_uses_by_customer(
+    conn: &mut PgConnection,
+    code: &str,
+    limit: i32,
+    order_id: &Uuid,
+    email_normalised: &str,
+    purchased_only: bool,
+) -> DbResult<()> {
+    let used = sqlx::query_scalar!(
+        r#"
+SELECT COUNT(*) as "count!"
+FROM orders AS ord
+JOIN users ON users.id = ord.user_id
+WHERE ord.promo_code = $1
+    AND ord.id <> $2
+    AND users.email_normalised = $3
+    AND (ord.purchased_at IS NOT NULL OR (NOT $4 AND ord.reserved_until > now()))
+        "#,
+        code,
+        order_id,
+        email_normalised,
+        purchased_only
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if used >= limit as i64 {
+        return Err(DbError::FailedPrecondition(format!(
+            "promo code {} has already been used {} times by this customer",
+            code, used
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn promo(kind: DiscountKind, value: f32) -> PromoCode {
+        PromoCode {
+            code: "TEST".to_string(),
+            discount_kind: kind,
+            discount_value: value,
+            ticket_types: None,
+            durations: None,
+            max_uses: None,
+            use_count: 0,
+            per_customer_limit: None,
+            valid_from: None,
+            valid_until: None,
+        }
+    }
+
+    #[test]
+    fn discount_is_capped_at_price() {
+        assert_eq!(
+            promo(DiscountKind::Percentage, 25.0).discount_for(44.0),
+            11.0
+        );
+        assert_eq!(promo(DiscountKind::Fixed, 10.0).discount_for(44.0), 10.0);
+        assert_eq!(promo(DiscountKind::Fixed, 50.0).discount_for(44.0), 44.0);
+        assert_eq!(
+            promo(DiscountKind::Percentage, 150.0).discount_for(44.0),
+            44.0
+        );
+    }
+
+    #[test]
+    fn check_promo_code_restrictions() {
+        let now = chrono::Utc::now();
+        let mut p = promo(DiscountKind::Fixed, 5.0);
+        p.ticket_types = Some(vec!["chalet3".to_string()]);
+        p.durations = Some(vec![4]);
+        assert!(p.check_applicable(now, "chalet3", 4).is_ok());
+        assert!(p.check_applicable(now, "hotel2", 4).is_err());
+        assert!(p.check_applicable(now, "chalet3", 3).is_err());
+
+        p.max_uses = Some(1);
+        p.use_count = 1;
+        assert!(p.check_applicable(now, "chalet3", 4).is_err());
+
+        let mut p = promo(DiscountKind::Fixed, 5.0);
+        p.valid_until = Some(now);
+        assert!(p.check_applicable(now, "chalet3", 4).is_err());
+        p.valid_until = None;
+        p.valid_from = Some(now + chrono::Duration::minutes(1));
+        assert!(p.check_applicable(now, "chalet3", 4).is_err());
+    }
+}
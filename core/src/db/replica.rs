@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use sqlx::types::Uuid;
+use tokio::time::{sleep, timeout};
+
+use super::error::DbError;
+use super::{connect_options, pool_options, DbPool, DbResult};
+use crate::config::DatabaseConfig;
+use crate::metrics;
+use crate::shutdown::CancellationToken;
+
+/// How often the replica's lag is checked, and how long a check may take
+const LAG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Create a pool for `database.replica_url`, if it's set. Connects lazily, so a replica which is
+/// down at startup is only read from once a lag check reaches it.
+pub fn connect_to_replica(config: &DatabaseConfig) -> Result<Option<DbPool>, sqlx::Error> {
+    if config.replica_url.is_empty() {
+        return Ok(None);
+    }
+    let options = connect_options(&config.replica_url, config)?;
+    Ok(Some(pool_options(config).connect_lazy_with(options)))
+}
+
+/// Sends read-only queries to the replica while it's caught up, and to the primary otherwise.
+/// Rows written through this instance's `Ticketing` are read from the primary until the replica
+/// has replayed them. Writes are only tracked in memory, so rows written by other instances, or by
+/// jobs, may be read up to `max_lag` out of date.
+pub struct ReadPool {
+    primary: DbPool,
+    replica: Option<Replica>,
+}
+
+struct Replica {
+    pool: DbPool,
+    /// Lag tolerated before reads go to the primary
+    max_lag: Duration,
+    /// Everything committed on the primary before this had been replayed, as of the last lag
+    /// check. `None` while the replica is unreachable or lagging more than `max_lag`
+    replayed_until: Mutex<Option<Instant>>,
+    /// When rows were last written, by id, until the replica must have replayed them
+    writes: Mutex<HashMap<Uuid, Instant>>,
+}
+
+impl ReadPool {
+    pub fn new(primary: DbPool, replica: Option<DbPool>, max_lag: Duration) -> Self {
+        Self {
+            primary,
+            replica: replica.map(|pool| Replica {
+                pool,
+                max_lag,
+                replayed_until: Mutex::new(None),
+                writes: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Record that the order or user `id` was just written on the primary, so it's read from
+    /// there until the replica has replayed the write
+    pub fn wrote(&self, id: &Uuid) {
+        if let Some(replica) = &self.replica {
+            replica.writes.lock().unwrap().insert(*id, Instant::now());
+        }
+    }
+
+    /// Run `query` on the replica if it's caught up, and has replayed any write of `id`.
+    /// Falls back to the primary if the replica can't be reached.
+    pub async fn read<T, F, Fut>(&self, id: Option<&Uuid>, query: F) -> DbResult<T>
+    where
+        F: Fn(DbPool) -> Fut,
+        Fut: Future<Output = DbResult<T>>,
+    {
+        if let Some(replica) = self.replica_for(id) {
+            match query(replica.pool.clone()).await {
+                Err(DbError::ExecutionError(e)) if is_unreachable(&e) => {
+                    log::warn!("failed to read from replica, reading from primary: {}", e);
+                    *replica.replayed_until.lock().unwrap() = None;
+                }
+                res => {
+                    metrics::record_db_read("replica");
+                    return res;
+                }
+            }
+        }
+        metrics::record_db_read("primary");
+        query(self.primary.clone()).await
+    }
+
+    fn replica_for(&self, id: Option<&Uuid>) -> Option<&Replica> {
+        let replica = self.replica.as_ref()?;
+        let replayed_until = (*replica.replayed_until.lock().unwrap())?;
+        if replayed_until.elapsed() > replica.stale_after() {
+            // Lag checks have stopped answering
+            return None;
+        }
+        match id.and_then(|id| replica.writes.lock().unwrap().get(id).copied()) {
+            Some(written) if written > replayed_until => None,
+            _ => Some(replica),
+        }
+    }
+
+    /// Check the replica's lag every second until `shutdown` is cancelled, forgetting writes it
+    /// must have replayed. Returns straight away without a replica.
+    pub async fn watch_replica(&self, shutdown: CancellationToken) {
+        let Some(replica) = &self.replica else {
+            return;
+        };
+        // Only changes are logged, and failing from the start
+        let mut caught_up = true;
+        loop {
+            let started = Instant::now();
+            let replayed_until = match timeout(LAG_CHECK_INTERVAL, replica_lag(&replica.pool)).await
+            {
+                Ok(Ok(lag)) => {
+                    metrics::set_replica_lag(lag);
+                    Duration::try_from_secs_f64(lag.max(0.0))
+                        .ok()
+                        .filter(|lag| *lag <= replica.max_lag)
+                        .and_then(|lag| started.checked_sub(lag))
+                }
+                Ok(Err(e)) => {
+                    log::debug!("failed to check replica lag: {}", e);
+                    None
+                }
+                Err(_) => {
+                    log::debug!("replica lag check timed out");
+                    None
+                }
+            };
+            *replica.replayed_until.lock().unwrap() = replayed_until;
+            match (caught_up, replayed_until.is_some()) {
+                (true, false) => log::warn!("replica lagging or unreachable, reading from primary"),
+                (false, true) => log::info!("replica caught up, reading from replica"),
+                _ => {}
+            }
+            caught_up = replayed_until.is_some();
+
+            let stale_after = replica.stale_after();
+            replica
+                .writes
+                .lock()
+                .unwrap()
+                .retain(|_, written| written.elapsed() <= stale_after);
+
+            tokio::select! {
+                () = shutdown.cancelled() => return,
+                () = sleep(LAG_CHECK_INTERVAL) => {}
+            }
+        }
+    }
+}
+
+impl Replica {
+    /// Age at which `replayed_until` is too old to route by, as lag checks should have updated
+    /// it. Writes older than this have been replayed by any replica still read from.
+    fn stale_after(&self) -> Duration {
+        self.max_lag + LAG_CHECK_INTERVAL * 2
+    }
+}
+
+/// Seconds the replica is behind the primary. 0 when it has replayed everything it received,
+/// or isn't a standby
+async fn replica_lag(pool: &DbPool) -> DbResult<f64> {
+    let lag = sqlx::query_scalar!(
+        r#"
+SELECT CASE
+    WHEN NOT pg_is_in_recovery() OR pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() THEN 0
+    ELSE coalesce(
+        extract(epoch FROM now() - pg_last_xact_replay_timestamp())::float8,
+        'Infinity'
+    )
+END as "lag!"
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(lag)
+}
+
+/// Errors from the replica being down or restarting, rather than from the query
+fn is_unreachable(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::Protocol(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        // Shutting down or starting up, or a query cancelled by a conflict with recovery
+        sqlx::Error::Database(e) => matches!(
+            e.code().as_deref(),
+            Some("57P01" | "57P02" | "57P03" | "40001")
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgPoolOptions;
+
+    fn lazy_pool() -> DbPool {
+        PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn read_your_writes_from_primary() {
+        let reads = ReadPool::new(lazy_pool(), Some(lazy_pool()), Duration::from_secs(1));
+        let replica = reads.replica.as_ref().unwrap();
+        let order_id = Uuid::new_v4();
+        let other_id = Uuid::new_v4();
+
+        // Not checked yet
+        assert!(reads.replica_for(None).is_none());
+
+        *replica.replayed_until.lock().unwrap() =
+            Instant::now().checked_sub(Duration::from_millis(10));
+        assert!(reads.replica_for(None).is_some());
+        assert!(reads.replica_for(Some(&order_id)).is_some());
+
+        reads.wrote(&order_id);
+        assert!(reads.replica_for(Some(&order_id)).is_none());
+        assert!(reads.replica_for(Some(&other_id)).is_some());
+
+        *replica.replayed_until.lock().unwrap() = Some(Instant::now());
+        assert!(reads.replica_for(Some(&order_id)).is_some());
+
+        // Lag checks stopped
+        *replica.replayed_until.lock().unwrap() =
+            Instant::now().checked_sub(replica.stale_after() + Duration::from_secs(1));
+        assert!(reads.replica_for(None).is_none());
+    }
+
+    #[tokio::test]
+    async fn primary_without_replica() {
+        let reads = ReadPool::new(lazy_pool(), None, Duration::from_secs(1));
+        reads.wrote(&Uuid::new_v4());
+        assert!(reads.replica_for(None).is_none());
+    }
+}
@@ -0,0 +1,82 @@
+use sqlx::types::Uuid;
+use sqlx::PgConnection;
+
+use super::error::DbError;
+use super::{DbPool, DbResult};
+use crate::model::Ticket;
+use crate::tickets::{TicketClaims, TicketSigner};
+
+/// Issue a signed ticket for a purchased order
+pub(super) async fn issue_ticket(
+    conn: &mut PgConnection,
+    order_id: &Uuid,
+    signer: &TicketSigner,
+) -> DbResult<()> {
+    let order = sqlx::query!(
+        r#"
+SELECT
+    ticket_type,
+    duration_days::integer as "duration!",
+    purchased_at as "purchased_at!"
+FROM orders
+WHERE id = $1 AND purchased_at IS NOT NULL
+        "#,
+        order_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let credential = signer.sign(&TicketClaims {
+        oid: order_id.to_string(),
+        tt: order.ticket_type,
+        d: order.duration,
+        iat: order.purchased_at.timestamp(),
+    });
+
+    sqlx::query!(
+        "INSERT INTO tickets (order_id, credential, issued_at) VALUES ($1, $2, $3)",
+        order_id,
+        credential,
+        order.purchased_at
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Get the tickets issued for an order. Empty until the order is purchased.
+pub async fn get_tickets(pool: &DbPool, order_id: &Uuid) -> DbResult<Vec<Ticket>> {
+    let tickets = sqlx::query_as!(
+        Ticket,
+        r#"
+SELECT
+    t.order_id,
+    ord.ticket_type as ticket_type_id,
+    ord.duration_days::integer as "duration!",
+    users.name as holder_name,
+    t.credential,
+    t.issued_at
+FROM tickets AS t
+JOIN orders AS ord ON ord.id = t.order_id
+JOIN users ON users.id = ord.user_id
+WHERE t.order_id = $1
+        "#,
+        order_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tickets)
+}
+
+/// Get the credential of the ticket issued for an order
+pub async fn get_ticket_credential(pool: &DbPool, order_id: &Uuid) -> DbResult<String> {
+    sqlx::query_scalar!(
+        "SELECT credential FROM tickets WHERE order_id = $1",
+        order_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| DbError::FailedPrecondition(format!("no ticket issued for order {}", order_id)))
+}
@@ -0,0 +1,292 @@
+use sqlx::types::Uuid;
+use sqlx::PgConnection;
+
+use super::error::DbError;
+use super::{jobs, DbPool, DbResult};
+use crate::jobs::Job;
+use crate::model;
+use crate::webhooks;
+
+/// A delivery which hasn't succeeded yet, with where to send it
+#[derive(Debug)]
+pub struct PendingDelivery {
+    pub url: String,
+    pub secret: String,
+    pub event_id: Uuid,
+    pub event: String,
+    pub payload: String,
+}
+
+/// Register an endpoint for `events`. Returns the endpoint, and the secret deliveries to it are
+/// signed with.
+pub async fn create_webhook_endpoint(
+    pool: &DbPool,
+    url: &str,
+    events: &[String],
+) -> DbResult<(model::WebhookEndpoint, String)> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if ["http", "https"].contains(&parsed.scheme()) => (),
+        _ => {
+            return Err(DbError::FailedPrecondition(format!(
+                "invalid webhook url {}",
+                url
+            )))
+        }
+    }
+    if events.is_empty() {
+        return Err(DbError::FailedPrecondition(
+            "webhook must subscribe to at least one event".to_string(),
+        ));
+    }
+    for event in events {
+        webhooks::check_event(event).map_err(DbError::FailedPrecondition)?;
+    }
+
+    let secret = webhooks::generate_secret();
+    let endpoint = sqlx::query_as!(
+        model::WebhookEndpoint,
+        r#"
+INSERT INTO webhook_endpoints (url, secret, events)
+VALUES ($1, $2, $3)
+RETURNING
+    id,
+    url,
+    events,
+    created_at
+        "#,
+        url,
+        secret,
+        events
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((endpoint, secret))
+}
+
+pub async fn get_webhook_endpoints(pool: &DbPool) -> DbResult<Vec<model::WebhookEndpoint>> {
+    let endpoints = sqlx::query_as!(
+        model::WebhookEndpoint,
+        r#"
+SELECT
+    id,
+    url,
+    events,
+    created_at
+FROM webhook_endpoints
+ORDER BY created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(endpoints)
+}
+
+/// Remove an endpoint, with its delivery log
+pub async fn delete_webhook_endpoint(pool: &DbPool, endpoint_id: &Uuid) -> DbResult<()> {
+    let res = sqlx::query!("DELETE FROM webhook_endpoints WHERE id = $1", endpoint_id)
+        .execute(pool)
+        .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(DbError::FailedPrecondition(format!(
+            "webhook endpoint {} not found",
+            endpoint_id
+        )));
+    }
+
+    Ok(())
+}
+
+/// Delivery log of an endpoint, newest first
+pub async fn get_webhook_deliveries(
+    pool: &DbPool,
+    endpoint_id: &Uuid,
+    limit: i64,
+) -> DbResult<Vec<model::WebhookDelivery>> {
+    let deliveries = sqlx::query_as!(
+        model::WebhookDelivery,
+        r#"
+SELECT
+    id,
+    endpoint_id,
+    event_id,
+    event,
+    payload,
+    attempts,
+    response_status,
+    last_error,
+    created_at,
+    delivered_at
+FROM webhook_deliveries
+WHERE endpoint_id = $1
+ORDER BY created_at DESC, id DESC
+LIMIT $2
+        "#,
+        endpoint_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(deliveries)
+}
+
+pub async fn get_webhook_delivery(pool: &DbPool, id: i64) -> DbResult<model::WebhookDelivery> {
+    let delivery = sqlx::query_as!(
+        model::WebhookDelivery,
+        r#"
+SELECT
+    id,
+    endpoint_id,
+    event_id,
+    event,
+    payload,
+    attempts,
+    response_status,
+    last_error,
+    created_at,
+    delivered_at
+FROM webhook_deliveries
+WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(delivery)
+}
+
+/// Queue an order event for the endpoints subscribed to it. Call before the order is deleted,
+/// in the same transaction as the change.
+pub(super) async fn queue_order_event(
+    conn: &mut PgConnection,
+    event: &str,
+    order_id: &Uuid,
+) -> DbResult<()> {
+    let delivery_ids = sqlx::query_scalar!(
+        r#"
+WITH ev AS MATERIALIZED (SELECT gen_random_uuid() AS id, now() AS created_at)
+INSERT INTO webhook_deliveries (endpoint_id, event_id, event, payload)
+SELECT
+    endpoint.id,
+    ev.id,
+    $1::text,
+    jsonb_build_object(
+        'id', ev.id,
+        'type', $1::text,
+        'created_at', ev.created_at,
+        'data', jsonb_build_object(
+            'id', ord.id,
+            'ticket_type_id', ord.ticket_type,
+            'duration', ord.duration_days,
+            'price', ord.base_price - ord.discount,
+            'discount', ord.discount,
+            'promo_code', ord.promo_code,
+            'reserved_until', ord.reserved_until,
+            'purchased_at', ord.purchased_at,
+            'customer', CASE WHEN users.id IS NULL THEN NULL ELSE jsonb_build_object(
+                'id', users.id,
+                'name', users.name,
+                'email', users.email,
+                'address', users.address
+            ) END
+        )
+    )::text
+FROM webhook_endpoints AS endpoint
+CROSS JOIN ev
+JOIN orders AS ord ON ord.id = $2
+LEFT JOIN users ON users.id = ord.user_id
+WHERE $1::text = ANY(endpoint.events)
+RETURNING id
+        "#,
+        event,
+        order_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for delivery_id in delivery_ids {
+        jobs::enqueue_job(conn, &Job::DeliverWebhook { delivery_id }).await?;
+    }
+
+    Ok(())
+}
+
+/// Queue a test event for an endpoint. It isn't retried; send it with
+/// `WebhookSender::deliver`.
+pub async fn queue_test_event(pool: &DbPool, endpoint_id: &Uuid) -> DbResult<i64> {
+    sqlx::query_scalar!(
+        r#"
+WITH ev AS MATERIALIZED (SELECT gen_random_uuid() AS id, now() AS created_at)
+INSERT INTO webhook_deliveries (endpoint_id, event_id, event, payload)
+SELECT
+    endpoint.id,
+    ev.id,
+    $2::text,
+    jsonb_build_object(
+        'id', ev.id,
+        'type', $2::text,
+        'created_at', ev.created_at,
+        'data', jsonb_build_object('endpoint_id', endpoint.id)
+    )::text
+FROM webhook_endpoints AS endpoint
+CROSS JOIN ev
+WHERE endpoint.id = $1
+RETURNING id
+        "#,
+        endpoint_id,
+        webhooks::TEST_EVENT
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| {
+        DbError::FailedPrecondition(format!("webhook endpoint {} not found", endpoint_id))
+    })
+}
+
+pub async fn get_pending_delivery(pool: &DbPool, id: i64) -> DbResult<Option<PendingDelivery>> {
+    let delivery = sqlx::query_as!(
+        PendingDelivery,
+        r#"
+SELECT endpoint.url, endpoint.secret, delivery.event_id, delivery.event, delivery.payload
+FROM webhook_deliveries AS delivery
+JOIN webhook_endpoints AS endpoint ON endpoint.id = delivery.endpoint_id
+WHERE delivery.id = $1 AND delivery.delivered_at IS NULL
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(delivery)
+}
+
+/// Record the outcome of a delivery attempt. It's delivered if there's no error.
+pub async fn record_delivery_attempt(
+    pool: &DbPool,
+    id: i64,
+    response_status: Option<i32>,
+    error: Option<&str>,
+) -> DbResult<()> {
+    sqlx::query!(
+        r#"
+UPDATE webhook_deliveries
+SET
+    attempts = attempts + 1,
+    response_status = $2,
+    last_error = $3,
+    delivered_at = CASE WHEN $3::text IS NULL THEN now() END
+WHERE id = $1
+        "#,
+        id,
+        response_status,
+        error
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -9,6 +9,42 @@ pub enum CfgError {
 #[derive(strum_macros::Display)]
 pub enum Cfg {
     DatabaseUrl,
+    AdminToken,
+    MaxTicketsPerCustomer,
+    TicketSigningKey,
+    ScannerToken,
+    MetricsToken,
+    FestivalStartDate,
+    FestivalUtcOffset,
+    AllowReentry,
+    MailerUrl,
+    MailFrom,
+    GrpcAddr,
+    RestAddr,
+    CorsAllowedOrigins,
+    LogFormat,
+    OtelExporterOtlpEndpoint,
+    ConfigFile,
+    StatsIntervalMs,
+    StatsBuffer,
+    ShutdownTimeoutSecs,
+    DbMaxConnections,
+    DbMinConnections,
+    DbAcquireTimeoutMs,
+    DbIdleTimeoutSecs,
+    DbStatementTimeoutMs,
+    DbConnectAttempts,
+    DbConnectBackoffMs,
+    DatabaseReplicaUrl,
+    DbReplicaMaxLagMs,
+    ReservationMinutes,
+    TicketTypesTtlSecs,
+    AvailabilityTtlMs,
+    JobPollIntervalMs,
+    JobLockSecs,
+    ExpireIntervalSecs,
+    PruneIntervalSecs,
+    LeaderRetrySecs,
 }
 
 /// Convert CamelCase to snake_case
@@ -43,12 +79,16 @@ fn to_snake_case(in_str: &str) -> String {
 }
 
 impl Cfg {
-    pub fn load(&self) -> Result<String, CfgError> {
-        // Load env-vars to match attributes of Cfg enum
+    /// Name of the env-var
+    pub fn key(&self) -> String {
         // We're using strum to convert the variant names to string, then converting to SNAKE_CASE
         // to get the env-var name.
+        to_snake_case(&self.to_string())
+    }
 
-        let key: String = to_snake_case(&self.to_string());
+    pub fn load(&self) -> Result<String, CfgError> {
+        // Load env-vars to match attributes of Cfg enum
+        let key = self.key();
         dotenv::var(&key).map_err(|e| CfgError::LoadFailed(key, e.to_string()))
     }
 }
@@ -0,0 +1,85 @@
+use thiserror::Error;
+
+use crate::db::error::DbError;
+use crate::tickets::TicketError;
+
+/// Seconds clients are asked to wait before retrying, when the database is too busy
+pub const RETRY_AFTER_SECS: u64 = 1;
+
+/// Postgres error code for a statement cancelled by `statement_timeout`
+const QUERY_CANCELED: &str = "57014";
+
+/// Error code raised by `update_order_stats`, run by the `check_order_limit` trigger on order
+/// insert, when a duration has sold out
+const SOLD_OUT: &str = "FT001";
+
+/// Errors from `TicketingService`, mapped to a status by each API
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("failed precondition: {0}")]
+    FailedPrecondition(String),
+    /// Every ticket for the duration has been reserved
+    #[error("sold out: {0}")]
+    SoldOut(String),
+    #[error("database error")]
+    Db(sqlx::Error),
+    /// The database is too busy, and the request can be retried after `RETRY_AFTER_SECS`
+    #[error("unavailable: {0}")]
+    Unavailable(String),
+    #[error("ticket error: {0}")]
+    Ticket(#[from] TicketError),
+    #[error("unknown service error")]
+    Unknown,
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(value: sqlx::Error) -> Self {
+        match &value {
+            sqlx::Error::PoolTimedOut => {
+                Error::Unavailable("no database connection available".to_string())
+            }
+            sqlx::Error::Database(e) if e.code().as_deref() == Some(QUERY_CANCELED) => {
+                Error::Unavailable("database statement timed out".to_string())
+            }
+            sqlx::Error::Database(e) if e.code().as_deref() == Some(SOLD_OUT) => {
+                Error::SoldOut(e.message().to_string())
+            }
+            _ => Error::Db(value),
+        }
+    }
+}
+
+impl From<DbError> for Error {
+    fn from(value: DbError) -> Self {
+        match value {
+            DbError::ExecutionError(e) => Error::from(e),
+            DbError::FailedPrecondition(e) => Error::FailedPrecondition(e),
+            DbError::Unknown => Error::Unknown,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn busy_database_is_unavailable() {
+        assert!(matches!(
+            Error::from(sqlx::Error::PoolTimedOut),
+            Error::Unavailable(_)
+        ));
+        assert!(matches!(
+            Error::from(DbError::ExecutionError(sqlx::Error::PoolTimedOut)),
+            Error::Unavailable(_)
+        ));
+        assert!(matches!(
+            Error::from(sqlx::Error::RowNotFound),
+            Error::Db(_)
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+use async_stream::try_stream;
+use futures::{Stream, TryStreamExt};
+
+use crate::db::error::DbError;
+use crate::db::export::{ExportFilter, ExportRow};
+use crate::db::{self, DbPool, DbResult};
+
+/// Rows are sent in chunks of about this many bytes
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// CSV header, in the order `ExportRow` fields are serialized
+const CSV_HEADER: [&str; 11] = [
+    "order_id",
+    "ticket_type_id",
+    "ticket_type",
+    "duration",
+    "price",
+    "promo_code",
+    "purchased_at",
+    "holder_name",
+    "email",
+    "address",
+    "allocation",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    /// Start of the export, before any rows
+    fn header(&self) -> Vec<u8> {
+        match self {
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                writer
+                    .write_record(CSV_HEADER)
+                    .expect("writing to a Vec can't fail");
+                writer.into_inner().expect("writing to a Vec can't fail")
+            }
+            ExportFormat::Ndjson => Vec::new(),
+        }
+    }
+
+    /// Append a row to `buf`
+    fn write_row(&self, buf: &mut Vec<u8>, row: &ExportRow) -> DbResult<()> {
+        let res = match self {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(&mut *buf);
+                writer
+                    .serialize(row)
+                    .and_then(|()| writer.flush().map_err(csv::Error::from))
+                    .map_err(|e| e.to_string())
+            }
+            ExportFormat::Ndjson => serde_json::to_writer(&mut *buf, row)
+                .map(|()| buf.push(b'\n'))
+                .map_err(|e| e.to_string()),
+        };
+
+        res.map_err(|e| {
+            log::error!("error writing export row {}: {}", row.order_id, e);
+            DbError::Unknown
+        })
+    }
+}
+
+/// Export purchased orders matching `filter`, in chunks. Rows are read from the database as the
+/// chunks are consumed, so exports of any size use about `CHUNK_SIZE` of memory.
+pub fn export_orders(
+    pool: DbPool,
+    filter: ExportFilter,
+    format: ExportFormat,
+) -> impl Stream<Item = DbResult<Vec<u8>>> + Send + 'static {
+    try_stream! {
+        let mut chunk = format.header();
+        let mut rows = db::export::export_orders(&pool, &filter);
+        while let Some(row) = rows.try_next().await? {
+            format.write_row(&mut chunk, &row)?;
+            if chunk.len() >= CHUNK_SIZE {
+                yield std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK_SIZE));
+            }
+        }
+        if !chunk.is_empty() {
+            yield chunk;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use sqlx::types::Uuid;
+
+    fn row() -> ExportRow {
+        ExportRow {
+            order_id: Uuid::nil(),
+            ticket_type_id: "weekend".to_string(),
+            ticket_type: "Weekend, \"camping\"".to_string(),
+            duration: 3,
+            price: 39.5,
+            promo_code: None,
+            purchased_at: chrono::Utc.with_ymd_and_hms(2024, 3, 16, 12, 0, 0).unwrap(),
+            holder_name: "Ada".to_string(),
+            email: "ada@example.com".to_string(),
+            address: "1 Main St".to_string(),
+            allocation: None,
+        }
+    }
+
+    #[test]
+    fn write_csv_rows() {
+        let format = ExportFormat::Csv;
+        let mut buf = format.header();
+        format.write_row(&mut buf, &row()).unwrap();
+
+        // The header must match the serialized field order
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(row()).unwrap();
+        assert_eq!(buf, writer.into_inner().unwrap());
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap().lines().nth(1).unwrap(),
+            "00000000-0000-0000-0000-000000000000,weekend,\"Weekend, \"\"camping\"\"\",3,39.5,,2024-03-16T12:00:00Z,Ada,ada@example.com,1 Main St,"
+        );
+    }
+
+    #[test]
+    fn write_ndjson_rows() {
+        let format = ExportFormat::Ndjson;
+        let mut buf = format.header();
+        format.write_row(&mut buf, &row()).unwrap();
+        format.write_row(&mut buf, &row()).unwrap();
+
+        let lines: Vec<_> = String::from_utf8(buf)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(lines.len(), 2);
+        let value: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(value["ticket_type_id"], "weekend");
+        assert_eq!(value["promo_code"], serde_json::Value::Null);
+        assert_eq!(value["purchased_at"], "2024-03-16T12:00:00Z");
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::postgres::PgPoolOptions;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+use crate::config::WorkerConfig;
+use crate::db::{self, DbPool};
+
+/// How long the database has to answer a health check
+const DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Last time a background worker made progress, shared between the worker and health checks
+#[derive(Clone, Debug)]
+pub struct Heartbeat(Arc<AtomicI64>);
+
+impl Heartbeat {
+    /// Starts as if the worker just beat, so it's alive while starting up
+    pub fn new() -> Self {
+        Self(Arc::new(AtomicI64::new(Utc::now().timestamp())))
+    }
+
+    pub fn beat(&self) {
+        self.0.store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    /// Whether the worker beat in the last `secs` seconds
+    pub fn beat_within(&self, secs: i64) -> bool {
+        Utc::now().timestamp() - self.0.load(Ordering::Relaxed) <= secs
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HealthReport {
+    /// Database answered a query. Not checked for liveness
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<bool>,
+    /// Background workers are claiming and running jobs
+    pub workers: bool,
+}
+
+impl HealthReport {
+    /// Whether requests can be served
+    pub fn is_ready(&self) -> bool {
+        self.database != Some(false) && self.workers
+    }
+}
+
+/// Checks the database and background workers, for health endpoints
+#[derive(Clone)]
+pub struct HealthCheck {
+    /// One connection of its own, so checks aren't queued behind requests when the main pool is
+    /// busy, and a busy instance isn't reported as down
+    ping_pool: DbPool,
+    /// Held while checking. Checks made meanwhile report the last result, rather than waiting
+    checking: Arc<Mutex<()>>,
+    /// Result of the last check
+    database: Arc<AtomicBool>,
+    workers: Heartbeat,
+    job_lock_secs: i32,
+}
+
+impl HealthCheck {
+    /// Pings the database on a connection of its own, using `pool`'s connect options
+    pub fn new(pool: &DbPool, workers: Heartbeat, config: &WorkerConfig) -> Self {
+        let ping_pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_lazy_with(pool.connect_options().as_ref().clone());
+        Self {
+            ping_pool,
+            checking: Arc::new(Mutex::new(())),
+            database: Arc::new(AtomicBool::new(true)),
+            workers,
+            job_lock_secs: config.job_lock_secs,
+        }
+    }
+
+    /// Workers are assumed stuck once they've been running a job for longer than its lock,
+    /// as other workers will claim the job again
+    pub fn workers_alive(&self) -> bool {
+        self.workers.beat_within(self.job_lock_secs.into())
+    }
+
+    /// Liveness, which only depends on this instance, so doesn't check the database
+    pub fn check_live(&self) -> HealthReport {
+        HealthReport {
+            database: None,
+            workers: self.workers_alive(),
+        }
+    }
+
+    /// Readiness, checking the database answers too
+    pub async fn check(&self) -> HealthReport {
+        HealthReport {
+            database: Some(self.check_database().await),
+            workers: self.workers_alive(),
+        }
+    }
+
+    async fn check_database(&self) -> bool {
+        let Ok(_checking) = self.checking.try_lock() else {
+            return self.database.load(Ordering::Relaxed);
+        };
+
+        let database = match timeout(DB_TIMEOUT, db::ping(&self.ping_pool)).await {
+            Ok(Ok(())) => true,
+            Ok(Err(e)) => {
+                log::warn!("health check failed to query db: {}", e);
+                false
+            }
+            Err(_) => {
+                log::warn!("health check timed out querying db");
+                false
+            }
+        };
+        self.database.store(database, Ordering::Relaxed);
+        database
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat_goes_stale() {
+        let heartbeat = Heartbeat::new();
+        assert!(heartbeat.beat_within(0));
+
+        heartbeat
+            .0
+            .store(Utc::now().timestamp() - 60, Ordering::Relaxed);
+        assert!(heartbeat.beat_within(60));
+        assert!(!heartbeat.beat_within(30));
+
+        heartbeat.beat();
+        assert!(heartbeat.beat_within(0));
+    }
+}
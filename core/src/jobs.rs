@@ -0,0 +1,279 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use thiserror::Error;
+use tokio::time::sleep;
+use tracing::Instrument;
+
+use crate::config::WorkerConfig;
+use crate::db::outbox::EmailClaim;
+use crate::db::{self, error::DbError, DbPool};
+use crate::health::Heartbeat;
+use crate::mail::{MailError, Mailer};
+use crate::metrics;
+use crate::shutdown::CancellationToken;
+use crate::webhooks::{WebhookError, WebhookSender};
+
+#[derive(Error, Debug)]
+pub enum JobError {
+    #[error("invalid job payload: {0}")]
+    InvalidPayload(String),
+    #[error("no mailer configured")]
+    NoMailer,
+    /// Sending was interrupted, so the email may have gone out. Dead-lettered for an admin to
+    /// retry, which resends it
+    #[error("email {0} may have been sent by an interrupted attempt, retry the job to resend it")]
+    EmailInterrupted(i64),
+    #[error(transparent)]
+    Db(#[from] DbError),
+    #[error(transparent)]
+    Mail(#[from] MailError),
+    #[error(transparent)]
+    Webhook(#[from] WebhookError),
+}
+
+impl JobError {
+    /// Whether running the job again could succeed, or it should go straight to the dead state
+    fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            JobError::InvalidPayload(_) | JobError::EmailInterrupted(_)
+        )
+    }
+}
+
+/// Work done in the background. Stored in the jobs table as the kind, and the payload as JSON.
+///
+/// There's no payment reconciliation job yet: purchases are marked paid by `purchase_order`, with
+/// no payment provider to reconcile them against. See the notes in the README.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "payload", rename_all = "snake_case")]
+pub enum Job {
+    /// Warn holders of reservations which are about to expire, and release expired ones
+    ExpireReservations,
+    /// Send an email from the outbox
+    SendEmail { outbox_id: i64 },
+    /// Post an event to a webhook endpoint
+    DeliverWebhook { delivery_id: i64 },
+    /// Delete old succeeded jobs
+    PruneJobs,
+    /// Normalise the details of every user, for purchase limits. Queued once, by a migration
+    NormaliseUsers,
+}
+
+impl Job {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Job::ExpireReservations => "expire_reservations",
+            Job::SendEmail { .. } => "send_email",
+            Job::DeliverWebhook { .. } => "deliver_webhook",
+            Job::PruneJobs => "prune_jobs",
+            Job::NormaliseUsers => "normalise_users",
+        }
+    }
+
+    /// Payload as JSON, if the job has one
+    pub fn payload(&self) -> Option<String> {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(mut job)) => job.remove("payload").map(|p| p.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn from_parts(kind: &str, payload: Option<&str>) -> Result<Self, JobError> {
+        let mut job = serde_json::json!({ "kind": kind });
+        if let Some(payload) = payload {
+            job["payload"] = serde_json::from_str(payload)
+                .map_err(|e| JobError::InvalidPayload(e.to_string()))?;
+        }
+
+        serde_json::from_value(job).map_err(|e| JobError::InvalidPayload(e.to_string()))
+    }
+}
+
+/// Claims and runs jobs. Any number of runners can share the jobs table.
+pub struct JobRunner {
+    /// Identifies this runner's locks on the jobs it claims
+    id: Uuid,
+    pool: Arc<DbPool>,
+    mailer: Option<Arc<dyn Mailer>>,
+    webhook_sender: WebhookSender,
+    heartbeat: Heartbeat,
+    config: WorkerConfig,
+}
+
+impl JobRunner {
+    pub fn new(pool: Arc<DbPool>, mailer: Option<Arc<dyn Mailer>>, config: WorkerConfig) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            pool,
+            mailer,
+            webhook_sender: WebhookSender::new(),
+            heartbeat: Heartbeat::new(),
+            config,
+        }
+    }
+
+    /// Beats each time the runner looks for a job, for health checks
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat.clone()
+    }
+
+    /// Kinds of job this runner can run. Emails are left queued if there's no mailer.
+    fn kinds(&self) -> Vec<&'static str> {
+        let mut kinds = vec![
+            "expire_reservations",
+            "deliver_webhook",
+            "prune_jobs",
+            "normalise_users",
+        ];
+        if self.mailer.is_some() {
+            kinds.push("send_email");
+        }
+        kinds
+    }
+
+    /// Schedule the recurring jobs, and run jobs until `shutdown` is cancelled. A job which is
+    /// running at the time is finished first
+    pub async fn run(self, shutdown: CancellationToken) {
+        let recurring = [
+            (Job::ExpireReservations, self.config.expire_interval_secs),
+            (Job::PruneJobs, self.config.prune_interval_secs),
+        ];
+        for (job, every_secs) in recurring {
+            if let Err(e) = db::jobs::schedule_recurring_job(&self.pool, &job, every_secs).await {
+                log::error!("error scheduling {} job: {}", job.kind(), e);
+            }
+        }
+
+        let kinds = self.kinds();
+        while !shutdown.is_cancelled() {
+            self.heartbeat.beat();
+            match db::jobs::claim_job(&self.pool, self.id, &kinds, self.config.job_lock_secs).await
+            {
+                Ok(Some(claimed)) => {
+                    let span = tracing::info_span!("job", id = claimed.id, kind = %claimed.kind);
+                    let res = match Job::from_parts(&claimed.kind, claimed.payload.as_deref()) {
+                        Ok(job) => self.run_job(&job).instrument(span).await,
+                        Err(e) => Err(e),
+                    };
+                    self.finish_job(claimed, res).await;
+                }
+                Ok(None) => self.idle(&shutdown).await,
+                Err(e) => {
+                    log::error!("error claiming job: {}", e);
+                    self.idle(&shutdown).await;
+                }
+            }
+        }
+        log::info!("job runner stopped");
+    }
+
+    /// Wait before looking for another job, unless shutting down
+    async fn idle(&self, shutdown: &CancellationToken) {
+        tokio::select! {
+            () = sleep(self.config.poll_interval()) => {}
+            () = shutdown.cancelled() => {}
+        }
+    }
+
+    async fn run_job(&self, job: &Job) -> Result<(), JobError> {
+        match job {
+            Job::ExpireReservations => {
+                db::outbox::queue_expiry_warnings(&self.pool).await?;
+                for (ticket_type_id, duration) in db::remove_expired_orders(&self.pool).await? {
+                    metrics::record_expiry(&ticket_type_id, duration);
+                }
+            }
+            Job::SendEmail { outbox_id } => {
+                let Some(mailer) = &self.mailer else {
+                    return Err(JobError::NoMailer);
+                };
+                match db::outbox::claim_email(&self.pool, *outbox_id).await? {
+                    EmailClaim::Claimed(email) => {
+                        let message_id = format!("<outbox-{}@festival-tickets>", outbox_id);
+                        if let Err(e) = mailer.send(&message_id, &email).await {
+                            let mut conn = self.pool.acquire().await.map_err(DbError::from)?;
+                            db::outbox::release_email(&mut conn, *outbox_id).await?;
+                            return Err(e.into());
+                        }
+                        db::outbox::mark_email_sent(&self.pool, *outbox_id).await?;
+                    }
+                    EmailClaim::Sent => {}
+                    EmailClaim::Interrupted => return Err(JobError::EmailInterrupted(*outbox_id)),
+                }
+            }
+            Job::DeliverWebhook { delivery_id } => {
+                self.webhook_sender
+                    .deliver(&self.pool, *delivery_id)
+                    .await?
+            }
+            Job::PruneJobs => db::jobs::prune_jobs(&self.pool).await?,
+            Job::NormaliseUsers => {
+                let updated = db::limits::normalise_users(&self.pool).await?;
+                log::info!("normalised the details of {} users", updated);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn finish_job(&self, claimed: db::jobs::ClaimedJob, res: Result<(), JobError>) {
+        let res = match res {
+            Ok(()) => db::jobs::complete_job(&self.pool, claimed.id, self.id).await,
+            Err(e) => {
+                // Recurring jobs are retried until they succeed
+                let retry = e.is_retryable();
+                if claimed.repeat_secs.is_none()
+                    && (!retry || claimed.attempts >= claimed.max_attempts)
+                {
+                    log::error!(
+                        "{} job {} failed, giving up: {}",
+                        claimed.kind,
+                        claimed.id,
+                        e
+                    );
+                } else {
+                    log::warn!("{} job {} failed: {}", claimed.kind, claimed.id, e);
+                }
+                db::jobs::fail_job(&self.pool, claimed.id, self.id, &e.to_string(), retry).await
+            }
+        };
+        match res {
+            Ok(true) => {}
+            // Ran for longer than `job_lock_secs`, so it's left to whoever claims it next
+            Ok(false) => log::warn!(
+                "lock on {} job {} expired before it finished, it will run again",
+                claimed.kind,
+                claimed.id
+            ),
+            // The job is claimed again once its lock expires
+            Err(e) => log::error!("error finishing {} job {}: {}", claimed.kind, claimed.id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_jobs_as_kind_and_payload() {
+        let job = Job::SendEmail { outbox_id: 42 };
+        assert_eq!(job.kind(), "send_email");
+        assert_eq!(job.payload().as_deref(), Some(r#"{"outbox_id":42}"#));
+        assert_eq!(
+            Job::from_parts("send_email", job.payload().as_deref()).unwrap(),
+            job
+        );
+
+        let job = Job::ExpireReservations;
+        assert_eq!(job.kind(), "expire_reservations");
+        assert_eq!(job.payload(), None);
+        assert_eq!(Job::from_parts("expire_reservations", None).unwrap(), job);
+
+        assert!(Job::from_parts("send_email", None).is_err());
+        assert!(Job::from_parts("unknown", None).is_err());
+    }
+}
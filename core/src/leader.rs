@@ -0,0 +1,148 @@
+use std::future::Future;
+use std::time::Duration;
+
+use sqlx::PgConnection;
+use tokio::time::sleep;
+
+use crate::db::{self, error::DbError, DbPool};
+use crate::metrics;
+use crate::shutdown::CancellationToken;
+
+/// Work run by one instance at a time. The other instances wait to take over, if the leader
+/// stops or loses its database connection
+#[derive(Clone, Copy, Debug)]
+pub enum Singleton {
+    /// Read order stats, and publish them to every instance
+    OrderStats,
+}
+
+impl Singleton {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Singleton::OrderStats => "order_stats",
+        }
+    }
+
+    fn lock_id(&self) -> i32 {
+        match self {
+            Singleton::OrderStats => 1,
+        }
+    }
+}
+
+/// Run `task` whenever this instance leads `singleton`, until `shutdown` is cancelled.
+///
+/// Leadership is a session advisory lock held on its own connection, so it's released if the
+/// instance dies. Followers try to take the lock every `retry`, and the leader checks its
+/// connection as often. `task` is passed a token which is cancelled on shutdown, or if the lock
+/// is lost, and should return soon after.
+pub async fn run_as_leader<F, Fut>(
+    pool: &DbPool,
+    singleton: Singleton,
+    retry: Duration,
+    shutdown: &CancellationToken,
+    mut task: F,
+) where
+    F: FnMut(CancellationToken) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    while !shutdown.is_cancelled() {
+        if let Err(e) = lead(pool, singleton, retry, shutdown, &mut task).await {
+            log::error!("error leading {}: {}", singleton.name(), e);
+        }
+        tokio::select! {
+            () = sleep(retry) => {}
+            () = shutdown.cancelled() => {}
+        }
+    }
+}
+
+/// Run `task` if the lock can be taken, until it's lost or shutting down
+async fn lead<F, Fut>(
+    pool: &DbPool,
+    singleton: Singleton,
+    retry: Duration,
+    shutdown: &CancellationToken,
+    task: &mut F,
+) -> Result<(), DbError>
+where
+    F: FnMut(CancellationToken) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut conn = pool.acquire().await?;
+    if !db::leader::try_lock_leader(&mut conn, singleton.lock_id()).await? {
+        return Ok(());
+    }
+
+    log::info!("leading {}", singleton.name());
+    metrics::set_leader(singleton.name(), true);
+
+    let stop = shutdown.child_token();
+    let res = {
+        let hold = hold_lock(&mut conn, retry, &stop);
+        let task = task(stop.clone());
+        tokio::pin!(hold, task);
+        tokio::select! {
+            () = &mut task => Ok(()),
+            res = &mut hold => {
+                stop.cancel();
+                task.await;
+                res
+            }
+        }
+    };
+
+    metrics::set_leader(singleton.name(), false);
+    match res {
+        Ok(()) => {
+            log::info!("stopped leading {}", singleton.name());
+            db::leader::unlock_leader(&mut conn, singleton.lock_id()).await
+        }
+        Err(e) => {
+            log::warn!("lost lock leading {}", singleton.name());
+            // Closed rather than returned to the pool, in case the lock is somehow still held
+            drop(conn.detach());
+            Err(e)
+        }
+    }
+}
+
+/// Check the lock is still held every `retry`, until `stop` is cancelled
+async fn hold_lock(
+    conn: &mut PgConnection,
+    retry: Duration,
+    stop: &CancellationToken,
+) -> Result<(), DbError> {
+    loop {
+        tokio::select! {
+            () = sleep(retry) => {}
+            () = stop.cancelled() => return Ok(()),
+        }
+        db::leader::check_leader(conn).await?;
+    }
+}
+
+/// Publish order stats to every instance each `interval`, while this instance leads
+/// `Singleton::OrderStats`. See `TicketingService::subscribe_order_stats`
+pub async fn publish_order_stats(
+    pool: DbPool,
+    interval: Duration,
+    retry: Duration,
+    shutdown: CancellationToken,
+) {
+    run_as_leader(&pool, Singleton::OrderStats, retry, &shutdown, |stop| {
+        let pool = pool.clone();
+        async move {
+            while !stop.is_cancelled() {
+                if let Err(e) = db::publish_order_stats(&pool).await {
+                    log::error!("error publishing order stats: {}", e);
+                }
+                tokio::select! {
+                    () = sleep(interval) => {}
+                    () = stop.cancelled() => {}
+                }
+            }
+        }
+    })
+    .await
+}
@@ -0,0 +1,22 @@
+pub mod app;
+pub mod catalogue;
+pub mod cli;
+pub mod config;
+pub mod db;
+pub mod env;
+pub mod error;
+pub mod export;
+pub mod health;
+pub mod jobs;
+pub mod leader;
+pub mod mail;
+pub mod metrics;
+pub mod model;
+pub mod service;
+pub mod shutdown;
+pub mod telemetry;
+pub mod tickets;
+pub mod webhooks;
+
+pub use error::{Error, Result};
+pub use service::{Ticketing, TicketingService};
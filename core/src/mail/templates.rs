@@ -0,0 +1,156 @@
+use chrono::{DateTime, Utc};
+
+use super::Email;
+
+/// What the templates need to know about an order
+#[derive(Clone, Debug)]
+pub struct OrderDetails {
+    pub order_id: String,
+    pub holder_name: String,
+    pub email: String,
+    pub ticket_type: String,
+    pub duration: i32,
+    pub price: f32,
+    pub reserved_until: DateTime<Utc>,
+}
+
+impl OrderDetails {
+    fn summary(&self) -> String {
+        format!(
+            "Order:    {}\nTicket:   {}, {} day{}\nPrice:    {:.2}",
+            self.order_id,
+            self.ticket_type,
+            self.duration,
+            if self.duration == 1 { "" } else { "s" },
+            self.price
+        )
+    }
+}
+
+fn format_time(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+/// Sent when user info is added to a reserved order
+pub fn reservation_confirmation(order: &OrderDetails) -> Email {
+    Email {
+        to: order.email.clone(),
+        subject: "Your festival ticket is reserved".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+             Your ticket is reserved until {}. Complete your purchase before then to keep it.\n\n\
+             {}\n",
+            order.holder_name,
+            format_time(order.reserved_until),
+            order.summary()
+        ),
+    }
+}
+
+/// Sent when an order is purchased, with the ticket credential
+pub fn purchase_receipt(order: &OrderDetails, credential: &str) -> Email {
+    Email {
+        to: order.email.clone(),
+        subject: "Your festival ticket".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+             Thanks for your purchase. Show this ticket at the gate, or the QR code from your \
+             order page:\n\n\
+             {}\n\n\
+             {}\n",
+            order.holder_name,
+            credential,
+            order.summary()
+        ),
+    }
+}
+
+/// Sent shortly before a reserved order expires
+pub fn expiry_warning(order: &OrderDetails) -> Email {
+    Email {
+        to: order.email.clone(),
+        subject: "Your ticket reservation is about to expire".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+             Your reservation expires at {}. After that the ticket is released for sale again.\n\n\
+             {}\n",
+            order.holder_name,
+            format_time(order.reserved_until),
+            order.summary()
+        ),
+    }
+}
+
+/// Sent when an order is refunded
+pub fn refund_confirmation(order: &OrderDetails, amount: f32) -> Email {
+    Email {
+        to: order.email.clone(),
+        subject: "Your ticket has been refunded".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+             We've refunded {:.2} for your order. Your ticket is no longer valid.\n\n\
+             {}\n",
+            order.holder_name,
+            amount,
+            order.summary()
+        ),
+    }
+}
+
+/// Sent to the recipient of a ticket transfer
+pub fn transfer_invitation(order: &OrderDetails, recipient: &str, accept_url: &str) -> Email {
+    Email {
+        to: recipient.to_string(),
+        subject: format!("{} sent you a festival ticket", order.holder_name),
+        body: format!(
+            "Hi,\n\n\
+             {} would like to transfer their {} day {} ticket to you. Accept it at:\n\n\
+             {}\n",
+            order.holder_name, order.duration, order.ticket_type, accept_url
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order() -> OrderDetails {
+        OrderDetails {
+            order_id: "6d1c2e57-0a6b-4b8e-9f8e-2f2b5d1f0c3a".to_string(),
+            holder_name: "Oscar".to_string(),
+            email: "oscar@oscar.com".to_string(),
+            ticket_type: "Weekend".to_string(),
+            duration: 3,
+            price: 39.6,
+            reserved_until: "2024-07-01T12:10:00Z".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn render_order_emails() {
+        let order = order();
+
+        let email = reservation_confirmation(&order);
+        assert_eq!(email.to, "oscar@oscar.com");
+        assert!(email.body.contains("Hi Oscar"));
+        assert!(email.body.contains("reserved until 2024-07-01 12:10 UTC"));
+        assert!(email.body.contains("Weekend, 3 days"));
+        assert!(email.body.contains("39.60"));
+
+        let email = purchase_receipt(&order, "FT1.claims.sig");
+        assert!(email.body.contains("FT1.claims.sig"));
+        assert!(email.body.contains(&order.order_id));
+
+        let email = expiry_warning(&order);
+        assert!(email.body.contains("expires at 2024-07-01 12:10 UTC"));
+
+        let email = refund_confirmation(&order, 39.6);
+        assert!(email.body.contains("refunded 39.60"));
+
+        let email = transfer_invitation(&order, "ann@ann.com", "https://example.com/accept");
+        assert_eq!(email.to, "ann@ann.com");
+        assert_eq!(email.subject, "Oscar sent you a festival ticket");
+        assert!(email.body.contains("https://example.com/accept"));
+    }
+}
@@ -0,0 +1,202 @@
+use std::sync::{LazyLock, OnceLock};
+use std::time::Duration;
+
+use prometheus::{
+    register_gauge, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Gauge, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+
+use crate::db::DbPool;
+use crate::TicketingService;
+
+pub const CONTENT_TYPE: &str = prometheus::TEXT_FORMAT;
+
+static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "festival_requests_total",
+        "Requests handled, by api (grpc or rest), route and status",
+        &["api", "route", "status"]
+    )
+    .unwrap()
+});
+
+static REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "festival_request_duration_seconds",
+        "Time to respond to requests, by api (grpc or rest) and route",
+        &["api", "route"]
+    )
+    .unwrap()
+});
+
+static RESERVATIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "festival_orders_reserved_total",
+        "Tickets added to a basket",
+        &["ticket_type", "duration"]
+    )
+    .unwrap()
+});
+
+static PURCHASES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "festival_orders_purchased_total",
+        "Orders purchased, by whether they're comps (true) or bought by customers (false)",
+        &["ticket_type", "duration", "comp"]
+    )
+    .unwrap()
+});
+
+static EXPIRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "festival_orders_expired_total",
+        "Reservations released without being purchased",
+        &["ticket_type", "duration"]
+    )
+    .unwrap()
+});
+
+static INVENTORY_REMAINING: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "festival_inventory_remaining",
+        "Tickets left to reserve, by duration",
+        &["duration"]
+    )
+    .unwrap()
+});
+
+static ORDER_STATS_SUBSCRIBERS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "festival_order_stats_subscribers",
+        "Clients streaming order stats"
+    )
+    .unwrap()
+});
+
+static ORDER_STATS_LAGGED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "festival_order_stats_lagged_total",
+        "Order stats updates dropped because a subscriber fell behind"
+    )
+    .unwrap()
+});
+
+static POOL_CONNECTIONS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "festival_db_pool_connections",
+        "Database connections, by state (idle, in_use or max)",
+        &["state"]
+    )
+    .unwrap()
+});
+
+static LEADER: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "festival_leader",
+        "Whether this instance leads a singleton task (1) or not (0), by task",
+        &["task"]
+    )
+    .unwrap()
+});
+
+static DB_READS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "festival_db_reads_total",
+        "Read-only queries, by the pool they ran on (primary or replica)",
+        &["pool"]
+    )
+    .unwrap()
+});
+
+static REPLICA_LAG: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge!(
+        "festival_db_replica_lag_seconds",
+        "How far the read replica was behind the primary, at the last check"
+    )
+    .unwrap()
+});
+
+/// Pool reported by `render`, set once it's connected
+static POOL: OnceLock<DbPool> = OnceLock::new();
+
+pub fn observe_pool(pool: &DbPool) {
+    let _ = POOL.set(pool.clone());
+}
+
+pub fn record_request(api: &str, route: &str, status: &str, elapsed: Duration) {
+    REQUESTS.with_label_values(&[api, route, status]).inc();
+    REQUEST_DURATION
+        .with_label_values(&[api, route])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn record_reservation(ticket_type: &str, duration: i32) {
+    RESERVATIONS
+        .with_label_values(&[ticket_type, &duration.to_string()])
+        .inc();
+}
+
+pub fn record_purchase(ticket_type: &str, duration: i32, comp: bool) {
+    PURCHASES
+        .with_label_values(&[ticket_type, &duration.to_string(), &comp.to_string()])
+        .inc();
+}
+
+pub fn record_expiry(ticket_type: &str, duration: i32) {
+    EXPIRIES
+        .with_label_values(&[ticket_type, &duration.to_string()])
+        .inc();
+}
+
+pub fn set_order_stats_subscribers(count: usize) {
+    ORDER_STATS_SUBSCRIBERS.set(count as i64);
+}
+
+pub fn record_order_stats_lag(skipped: u64) {
+    ORDER_STATS_LAGGED.inc_by(skipped);
+}
+
+pub fn set_leader(task: &str, leading: bool) {
+    LEADER.with_label_values(&[task]).set(leading.into());
+}
+
+pub fn record_db_read(pool: &str) {
+    DB_READS.with_label_values(&[pool]).inc();
+}
+
+pub fn set_replica_lag(seconds: f64) {
+    REPLICA_LAG.set(seconds);
+}
+
+/// Metrics in the Prometheus text format. Inventory and pool usage are read when called
+pub async fn render(ticketing: &dyn TicketingService) -> String {
+    match ticketing.get_order_stats().await {
+        Ok(stats) => {
+            for s in stats {
+                INVENTORY_REMAINING
+                    .with_label_values(&[&s.duration_days.to_string()])
+                    .set((s.order_limit - s.order_count).into());
+            }
+        }
+        Err(e) => log::warn!("failed to get order stats for metrics: {}", e),
+    }
+
+    if let Some(pool) = POOL.get() {
+        let size = i64::from(pool.size());
+        let idle = pool.num_idle() as i64;
+        POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+        POOL_CONNECTIONS
+            .with_label_values(&["in_use"])
+            .set(size - idle);
+        POOL_CONNECTIONS
+            .with_label_values(&["max"])
+            .set(pool.options().get_max_connections().into());
+    }
+
+    let mut buf = vec![];
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buf) {
+        log::error!("failed to encode metrics: {}", e);
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OrderStats {
+    pub duration_days: i32,
+    pub order_limit: i32,
+    pub order_count: i32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Order {
+    pub id: Uuid,
+    pub ticket_type_id: String,
+    pub user_id: Option<String>,
+    pub duration: i32,
+    pub price: f32,
+    pub reserved_until: DateTime<Utc>,
+    pub purchased_at: Option<DateTime<Utc>>,
+    pub promo_code: Option<String>,
+    /// Discount from applied promo code, already subtracted from price
+    pub discount: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct User {
+    pub id: Uuid,
+    pub name: String,
+    pub address: String,
+    pub email: String,
+    pub order_id: Uuid,
+}
+
+/// User info attached to an order before it's purchased
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewUser {
+    pub name: String,
+    pub email: String,
+    pub address: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct TicketType {
+    pub id: String,
+    pub display: String,
+    pub sold_out: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Presale {
+    pub id: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PresaleCode {
+    /// The code, or for allow-list entries the email, which customers enter to reserve
+    pub code: String,
+    pub presale_id: String,
+    /// Allow-listed email, which the order's user info must match
+    pub email: Option<String>,
+    pub ticket_limit: i32,
+    /// Tickets reserved or purchased with this code
+    pub redeemed: i32,
+    pub purchased: i32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PurchaseLimitOverride {
+    /// Normalised email
+    pub email: String,
+    pub ticket_limit: i32,
+    pub note: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Job {
+    pub id: i64,
+    /// i.e. send_email
+    pub kind: String,
+    /// JSON
+    pub payload: Option<String>,
+    /// pending, running, succeeded or dead
+    pub state: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub run_after: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub url: String,
+    /// order.reserved, order.purchased, order.expired or order.refunded (not sent yet)
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub endpoint_id: Uuid,
+    /// Same for every endpoint receiving the event
+    pub event_id: Uuid,
+    pub event: String,
+    /// JSON body which was posted
+    pub payload: String,
+    pub attempts: i32,
+    /// Status of the last response, if there was one
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Ticket {
+    pub order_id: Uuid,
+    pub ticket_type_id: String,
+    pub duration: i32,
+    pub holder_name: String,
+    /// Signed credential presented at the gate, `FT1.<payload>.<signature>`
+    pub credential: String,
+    pub issued_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum QrCodeFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+/// Rendered ticket QR code
+#[derive(Clone, Debug)]
+pub struct QrCode {
+    pub content_type: &'static str,
+    pub image: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ScanDirection {
+    Entry,
+    Exit,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CheckIn {
+    pub id: i64,
+    pub order_id: Uuid,
+    /// Day of the festival, from 1
+    pub festival_day: i32,
+    /// entry or exit
+    pub direction: String,
+    pub gate: String,
+    pub scanned_at: DateTime<Utc>,
+    /// Scanned offline and synced later
+    pub offline: bool,
+    /// Why an offline scan broke the entry rules, if it did
+    pub conflict: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ScannerConfig {
+    /// Base64 encoded Ed25519 public key, for verifying ticket credentials
+    pub verifying_key: String,
+    /// Tickets are valid from this day, for their duration in days
+    pub festival_start_date: NaiveDate,
+    /// Offset from UTC of the festival's local time, i.e. `+01:00`, which festival days follow
+    pub festival_utc_offset: String,
+    /// Whether tickets may leave and re-enter on the same day
+    pub allow_reentry: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OfflineCheckIn {
+    /// Generated by the scanner, so the same scan is only recorded once
+    pub client_ref: Uuid,
+    pub credential: String,
+    pub direction: ScanDirection,
+    pub gate: String,
+    pub scanned_at: DateTime<Utc>,
+}
@@ -0,0 +1,510 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use tracing::{instrument, Span};
+use uuid::Uuid;
+
+use crate::catalogue::{self, Catalogue};
+use crate::config::{Config, ConfigError};
+use crate::db::checkin::CheckInPolicy;
+use crate::db::comps::CompImportReport;
+use crate::db::export::ExportFilter;
+use crate::db::limits::PurchaseLimit;
+use crate::db::presale::ImportReport;
+use crate::db::replica::ReadPool;
+use crate::db::{self, DbPool};
+use crate::error::{Error, Result};
+use crate::export::{self, ExportFormat};
+use crate::metrics;
+use crate::model::{
+    CheckIn, Job, NewUser, OfflineCheckIn, Order, OrderStats, Presale, PresaleCode,
+    PurchaseLimitOverride, QrCode, QrCodeFormat, ScanDirection, ScannerConfig, Ticket, TicketType,
+    User, WebhookDelivery, WebhookEndpoint,
+};
+use crate::shutdown::CancellationToken;
+use crate::tickets::{self, TicketSigner};
+use crate::webhooks::WebhookSender;
+
+/// Number of jobs or webhook deliveries listed when no limit is given
+const DEFAULT_LIST_LIMIT: i64 = 100;
+
+/// Every ticketing operation, independent of the API it's served over. The gRPC and REST
+/// servers only translate requests and responses, and map `Error` to a status.
+#[async_trait]
+pub trait TicketingService: Send + Sync {
+    async fn get_ticket_types(&self) -> Result<Vec<TicketType>>;
+
+    /// Durations in days which aren't sold out
+    async fn get_ticket_durations(&self, ticket_type_id: &str) -> Result<Vec<i32>>;
+
+    /// Reserve a ticket in a new order. `presale_code` is required while a presale is running.
+    async fn add_ticket_to_basket(
+        &self,
+        ticket_type_id: &str,
+        duration: i32,
+        presale_code: Option<&str>,
+    ) -> Result<Order>;
+
+    async fn add_user_info(&self, order_id: &Uuid, user: &NewUser) -> Result<Order>;
+
+    async fn apply_promo_code(&self, order_id: &Uuid, promo_code: &str) -> Result<Order>;
+
+    /// Purchase an order with user info, and issue its ticket
+    async fn purchase_order(&self, order_id: &Uuid) -> Result<Order>;
+
+    async fn get_order(&self, order_id: &Uuid) -> Result<Order>;
+
+    async fn get_user(&self, user_id: &Uuid) -> Result<User>;
+
+    /// Tickets issued for an order. Empty until it's purchased.
+    async fn get_tickets(&self, order_id: &Uuid) -> Result<Vec<Ticket>>;
+
+    async fn get_ticket_qr_code(&self, order_id: &Uuid, format: QrCodeFormat) -> Result<QrCode>;
+
+    async fn get_order_stats(&self) -> Result<Vec<OrderStats>>;
+
+    /// Order stats each time they're published, by whichever instance leads
+    /// `leader::Singleton::OrderStats`. Holds a database connection, so subscribe once and fan out
+    /// to clients.
+    async fn subscribe_order_stats(&self) -> Result<BoxStream<'static, Result<Vec<OrderStats>>>>;
+
+    async fn upsert_presale(
+        &self,
+        presale_id: &str,
+        starts_at: DateTime<Utc>,
+        ends_at: DateTime<Utc>,
+    ) -> Result<Presale>;
+
+    async fn import_presale_codes(
+        &self,
+        presale_id: &str,
+        csv: &str,
+        default_ticket_limit: i32,
+    ) -> Result<ImportReport>;
+
+    async fn get_presale_codes(&self, presale_id: &str) -> Result<Vec<PresaleCode>>;
+
+    async fn set_purchase_limit_override(
+        &self,
+        email: &str,
+        ticket_limit: i32,
+        note: Option<&str>,
+    ) -> Result<PurchaseLimitOverride>;
+
+    async fn delete_purchase_limit_override(&self, email: &str) -> Result<()>;
+
+    async fn get_jobs(
+        &self,
+        state: Option<&str>,
+        kind: Option<&str>,
+        limit: Option<i64>,
+    ) -> Result<Vec<Job>>;
+
+    async fn retry_job(&self, job_id: i64) -> Result<Job>;
+
+    /// Returns the endpoint, and the secret its deliveries are signed with
+    async fn create_webhook_endpoint(
+        &self,
+        url: &str,
+        events: &[String],
+    ) -> Result<(WebhookEndpoint, String)>;
+
+    async fn get_webhook_endpoints(&self) -> Result<Vec<WebhookEndpoint>>;
+
+    async fn delete_webhook_endpoint(&self, endpoint_id: &Uuid) -> Result<()>;
+
+    async fn get_webhook_deliveries(
+        &self,
+        endpoint_id: &Uuid,
+        limit: Option<i64>,
+    ) -> Result<Vec<WebhookDelivery>>;
+
+    /// Send a test event to an endpoint now. Failed deliveries are returned, not errors.
+    async fn test_webhook_endpoint(&self, endpoint_id: &Uuid) -> Result<WebhookDelivery>;
+
+    /// Purchased orders matching `filter`, in chunks of the export file
+    fn export_orders(
+        &self,
+        filter: ExportFilter,
+        format: ExportFormat,
+    ) -> BoxStream<'static, Result<Vec<u8>>>;
+
+    async fn import_comp_tickets(
+        &self,
+        csv: &str,
+        allocation: Option<&str>,
+        dry_run: bool,
+    ) -> Result<CompImportReport>;
+
+    /// Verify a credential and record the scan. Returns the ticket, to check against the holder.
+    async fn check_in(
+        &self,
+        credential: &str,
+        direction: ScanDirection,
+        gate: &str,
+    ) -> Result<(CheckIn, Option<Ticket>)>;
+
+    fn get_scanner_config(&self) -> ScannerConfig;
+
+    /// Record scans made offline. Returns the recorded scans, and why any were rejected.
+    async fn sync_check_ins(
+        &self,
+        check_ins: &[OfflineCheckIn],
+    ) -> Result<(Vec<CheckIn>, Vec<String>)>;
+}
+
+/// `TicketingService` backed by the database
+pub struct Ticketing {
+    pool: DbPool,
+    purchase_limit: PurchaseLimit,
+    /// How long tickets are held in a basket
+    reserve_for: chrono::Duration,
+    ticket_signer: TicketSigner,
+    check_in_policy: CheckInPolicy,
+    webhook_sender: WebhookSender,
+    catalogue: Arc<Catalogue>,
+    /// Read-only queries, routed to the replica when there is one
+    reads: Arc<ReadPool>,
+}
+
+impl Ticketing {
+    pub fn new(
+        pool: DbPool,
+        purchase_limit: PurchaseLimit,
+        reserve_for: chrono::Duration,
+        ticket_signer: TicketSigner,
+        check_in_policy: CheckInPolicy,
+        catalogue: Catalogue,
+        reads: ReadPool,
+    ) -> Self {
+        Self {
+            pool,
+            purchase_limit,
+            reserve_for,
+            ticket_signer,
+            check_in_policy,
+            webhook_sender: WebhookSender::new(),
+            catalogue: Arc::new(catalogue),
+            reads: Arc::new(reads),
+        }
+    }
+
+    /// `replica` is the pool from `db::replica::connect_to_replica`, if one is configured
+    pub fn from_config(
+        pool: DbPool,
+        replica: Option<DbPool>,
+        config: &Config,
+    ) -> std::result::Result<Self, ConfigError> {
+        let reads = ReadPool::new(pool.clone(), replica, config.database.replica_max_lag());
+        Ok(Self::new(
+            pool,
+            PurchaseLimit(config.reservations.max_tickets_per_customer),
+            chrono::Duration::minutes(config.reservations.minutes.into()),
+            config.tickets.signer()?,
+            config.tickets.check_in_policy()?,
+            Catalogue::new(&config.catalogue),
+            reads,
+        ))
+    }
+
+    /// Keep the catalogue cache up to date with changes from other instances, until `shutdown`
+    /// is cancelled
+    pub fn watch_catalogue(
+        &self,
+        shutdown: CancellationToken,
+    ) -> impl std::future::Future<Output = ()> + Send + 'static {
+        catalogue::watch(self.pool.clone(), self.catalogue.clone(), shutdown)
+    }
+
+    /// Check the replica's lag, to route reads by, until `shutdown` is cancelled
+    pub fn watch_replica(
+        &self,
+        shutdown: CancellationToken,
+    ) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let reads = self.reads.clone();
+        async move { reads.watch_replica(shutdown).await }
+    }
+}
+
+#[async_trait]
+impl TicketingService for Ticketing {
+    async fn get_ticket_types(&self) -> Result<Vec<TicketType>> {
+        Ok(self.catalogue.ticket_types(&self.reads).await?)
+    }
+
+    async fn get_ticket_durations(&self, ticket_type_id: &str) -> Result<Vec<i32>> {
+        Ok(self
+            .catalogue
+            .ticket_durations(&self.reads, ticket_type_id)
+            .await?)
+    }
+
+    #[instrument(skip_all, fields(ticket_type = ticket_type_id, duration = duration, order_id))]
+    async fn add_ticket_to_basket(
+        &self,
+        ticket_type_id: &str,
+        duration: i32,
+        presale_code: Option<&str>,
+    ) -> Result<Order> {
+        let order = db::add_ticket_to_basket(
+            &self.pool,
+            ticket_type_id,
+            duration,
+            presale_code,
+            self.reserve_for,
+        )
+        .await?;
+        Span::current().record("order_id", tracing::field::display(order.id));
+        self.reads.wrote(&order.id);
+        metrics::record_reservation(&order.ticket_type_id, order.duration);
+        Ok(order)
+    }
+
+    #[instrument(skip_all, fields(order_id = %order_id))]
+    async fn add_user_info(&self, order_id: &Uuid, user: &NewUser) -> Result<Order> {
+        let order = db::add_user_to_order(&self.pool, order_id, user, self.purchase_limit).await?;
+        self.reads.wrote(order_id);
+        if let Some(user_id) = order.user_id.as_deref().and_then(|id| id.parse().ok()) {
+            self.reads.wrote(&user_id);
+        }
+        Ok(order)
+    }
+
+    #[instrument(skip_all, fields(order_id = %order_id))]
+    async fn apply_promo_code(&self, order_id: &Uuid, promo_code: &str) -> Result<Order> {
+        let order = db::promo::apply_promo_code(&self.pool, order_id, promo_code).await?;
+        self.reads.wrote(order_id);
+        Ok(order)
+    }
+
+    #[instrument(skip_all, fields(order_id = %order_id, ticket_type))]
+    async fn purchase_order(&self, order_id: &Uuid) -> Result<Order> {
+        let order = db::purchase_order(
+            &self.pool,
+            order_id,
+            self.purchase_limit,
+            &self.ticket_signer,
+        )
+        .await?;
+        Span::current().record("ticket_type", &order.ticket_type_id);
+        self.reads.wrote(order_id);
+        metrics::record_purchase(&order.ticket_type_id, order.duration, false);
+        Ok(order)
+    }
+
+    #[instrument(skip_all, fields(order_id = %order_id))]
+    async fn get_order(&self, order_id: &Uuid) -> Result<Order> {
+        self.reads
+            .read(Some(order_id), |pool| async move {
+                db::get_order(&pool, order_id).await
+            })
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("order {} not found", order_id)))
+    }
+
+    #[instrument(skip_all, fields(user_id = %user_id))]
+    async fn get_user(&self, user_id: &Uuid) -> Result<User> {
+        self.reads
+            .read(Some(user_id), |pool| async move {
+                db::get_user(&pool, user_id).await
+            })
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("user {} not found", user_id)))
+    }
+
+    #[instrument(skip_all, fields(order_id = %order_id))]
+    async fn get_tickets(&self, order_id: &Uuid) -> Result<Vec<Ticket>> {
+        Ok(db::tickets::get_tickets(&self.pool, order_id).await?)
+    }
+
+    #[instrument(skip_all, fields(order_id = %order_id))]
+    async fn get_ticket_qr_code(&self, order_id: &Uuid, format: QrCodeFormat) -> Result<QrCode> {
+        let credential = db::tickets::get_ticket_credential(&self.pool, order_id).await?;
+
+        let qr_code = match format {
+            QrCodeFormat::Png => QrCode {
+                content_type: "image/png",
+                image: tickets::render_qr_png(&credential)?,
+            },
+            QrCodeFormat::Svg => QrCode {
+                content_type: "image/svg+xml",
+                image: tickets::render_qr_svg(&credential)?.into_bytes(),
+            },
+        };
+        Ok(qr_code)
+    }
+
+    async fn get_order_stats(&self) -> Result<Vec<OrderStats>> {
+        Ok(self
+            .reads
+            .read(None, |pool| async move { db::get_order_stats(&pool).await })
+            .await?)
+    }
+
+    async fn subscribe_order_stats(&self) -> Result<BoxStream<'static, Result<Vec<OrderStats>>>> {
+        let stats = db::listen_order_stats(&self.pool).await?;
+        Ok(stats.map_err(Error::from).boxed())
+    }
+
+    async fn upsert_presale(
+        &self,
+        presale_id: &str,
+        starts_at: DateTime<Utc>,
+        ends_at: DateTime<Utc>,
+    ) -> Result<Presale> {
+        Ok(db::presale::upsert_presale(&self.pool, presale_id, starts_at, ends_at).await?)
+    }
+
+    async fn import_presale_codes(
+        &self,
+        presale_id: &str,
+        csv: &str,
+        default_ticket_limit: i32,
+    ) -> Result<ImportReport> {
+        Ok(
+            db::presale::import_presale_codes(&self.pool, presale_id, csv, default_ticket_limit)
+                .await?,
+        )
+    }
+
+    async fn get_presale_codes(&self, presale_id: &str) -> Result<Vec<PresaleCode>> {
+        Ok(db::presale::get_presale_codes(&self.pool, presale_id).await?)
+    }
+
+    async fn set_purchase_limit_override(
+        &self,
+        email: &str,
+        ticket_limit: i32,
+        note: Option<&str>,
+    ) -> Result<PurchaseLimitOverride> {
+        Ok(db::limits::set_purchase_limit_override(&self.pool, email, ticket_limit, note).await?)
+    }
+
+    async fn delete_purchase_limit_override(&self, email: &str) -> Result<()> {
+        Ok(db::limits::delete_purchase_limit_override(&self.pool, email).await?)
+    }
+
+    async fn get_jobs(
+        &self,
+        state: Option<&str>,
+        kind: Option<&str>,
+        limit: Option<i64>,
+    ) -> Result<Vec<Job>> {
+        let limit = limit.unwrap_or(DEFAULT_LIST_LIMIT);
+        Ok(db::jobs::get_jobs(&self.pool, state, kind, limit).await?)
+    }
+
+    async fn retry_job(&self, job_id: i64) -> Result<Job> {
+        Ok(db::jobs::retry_job(&self.pool, job_id).await?)
+    }
+
+    async fn create_webhook_endpoint(
+        &self,
+        url: &str,
+        events: &[String],
+    ) -> Result<(WebhookEndpoint, String)> {
+        Ok(db::webhooks::create_webhook_endpoint(&self.pool, url, events).await?)
+    }
+
+    async fn get_webhook_endpoints(&self) -> Result<Vec<WebhookEndpoint>> {
+        Ok(db::webhooks::get_webhook_endpoints(&self.pool).await?)
+    }
+
+    async fn delete_webhook_endpoint(&self, endpoint_id: &Uuid) -> Result<()> {
+        Ok(db::webhooks::delete_webhook_endpoint(&self.pool, endpoint_id).await?)
+    }
+
+    async fn get_webhook_deliveries(
+        &self,
+        endpoint_id: &Uuid,
+        limit: Option<i64>,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let limit = limit.unwrap_or(DEFAULT_LIST_LIMIT);
+        Ok(db::webhooks::get_webhook_deliveries(&self.pool, endpoint_id, limit).await?)
+    }
+
+    async fn test_webhook_endpoint(&self, endpoint_id: &Uuid) -> Result<WebhookDelivery> {
+        let delivery_id = db::webhooks::queue_test_event(&self.pool, endpoint_id).await?;
+        // Failed deliveries are recorded, and returned rather than treated as errors
+        if let Err(e) = self.webhook_sender.deliver(&self.pool, delivery_id).await {
+            log::warn!("test webhook delivery {} failed: {}", delivery_id, e);
+        }
+        Ok(db::webhooks::get_webhook_delivery(&self.pool, delivery_id).await?)
+    }
+
+    fn export_orders(
+        &self,
+        filter: ExportFilter,
+        format: ExportFormat,
+    ) -> BoxStream<'static, Result<Vec<u8>>> {
+        export::export_orders(self.pool.clone(), filter, format)
+            .map_err(Error::from)
+            .boxed()
+    }
+
+    async fn import_comp_tickets(
+        &self,
+        csv: &str,
+        allocation: Option<&str>,
+        dry_run: bool,
+    ) -> Result<CompImportReport> {
+        let report = db::comps::import_comp_tickets(
+            &self.pool,
+            csv,
+            allocation,
+            dry_run,
+            &self.ticket_signer,
+        )
+        .await?;
+        Ok(report)
+    }
+
+    #[instrument(skip_all, fields(gate = gate, order_id))]
+    async fn check_in(
+        &self,
+        credential: &str,
+        direction: ScanDirection,
+        gate: &str,
+    ) -> Result<(CheckIn, Option<Ticket>)> {
+        let check_in = db::checkin::check_in(
+            &self.pool,
+            &self.check_in_policy,
+            &self.ticket_signer,
+            credential,
+            direction,
+            gate,
+        )
+        .await?;
+        Span::current().record("order_id", tracing::field::display(check_in.order_id));
+        let ticket = db::tickets::get_tickets(&self.pool, &check_in.order_id)
+            .await?
+            .pop();
+
+        Ok((check_in, ticket))
+    }
+
+    fn get_scanner_config(&self) -> ScannerConfig {
+        ScannerConfig {
+            verifying_key: self.ticket_signer.verifying_key(),
+            festival_start_date: self.check_in_policy.festival_start,
+            festival_utc_offset: self.check_in_policy.utc_offset.to_string(),
+            allow_reentry: self.check_in_policy.allow_reentry,
+        }
+    }
+
+    async fn sync_check_ins(
+        &self,
+        check_ins: &[OfflineCheckIn],
+    ) -> Result<(Vec<CheckIn>, Vec<String>)> {
+        Ok(db::checkin::sync_check_ins(
+            &self.pool,
+            &self.check_in_policy,
+            &self.ticket_signer,
+            check_ins,
+        )
+        .await?)
+    }
+}
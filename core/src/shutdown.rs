@@ -0,0 +1,121 @@
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use tokio::time::{timeout_at, Instant};
+pub use tokio_util::sync::CancellationToken;
+
+/// Token cancelled on ctrl-c or SIGTERM, to stop the servers and background workers
+pub fn on_signal() -> CancellationToken {
+    let shutdown = CancellationToken::new();
+    let token = shutdown.clone();
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        log::info!("shutting down");
+        token.cancel();
+    });
+    shutdown
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// When everything has to finish by once shutting down: `timeout` after `shutdown` is cancelled.
+/// Clones share the one deadline, so servers and the workers drained after them still finish by
+/// the same time
+#[derive(Clone)]
+pub struct Deadline {
+    shutdown: CancellationToken,
+    timeout: Duration,
+    at: Arc<OnceLock<Instant>>,
+}
+
+impl Deadline {
+    /// Starts watching `shutdown`, to fix the deadline as soon as it's cancelled
+    pub fn new(shutdown: CancellationToken, timeout: Duration) -> Self {
+        let deadline = Self {
+            shutdown,
+            timeout,
+            at: Arc::new(OnceLock::new()),
+        };
+        let watch = deadline.clone();
+        tokio::spawn(async move {
+            watch.at().await;
+        });
+        deadline
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Wait until shutting down, then return the deadline
+    async fn at(&self) -> Instant {
+        self.shutdown.cancelled().await;
+        *self.at.get_or_init(|| Instant::now() + self.timeout)
+    }
+}
+
+/// Run `task` until it finishes, or until `deadline` once shutting down. `None` if it was still
+/// running at the deadline
+pub async fn drain<F: Future>(task: F, deadline: &Deadline) -> Option<F::Output> {
+    tokio::pin!(task);
+    let at = tokio::select! {
+        res = &mut task => return Some(res),
+        at = deadline.at() => at,
+    };
+
+    timeout_at(at, task).await.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_until_deadline() {
+        let shutdown = CancellationToken::new();
+        let deadline = Deadline::new(shutdown.clone(), Duration::from_millis(50));
+
+        // Finished tasks return straight away
+        assert_eq!(drain(async { 1 }, &deadline).await, Some(1));
+
+        shutdown.cancel();
+        let task = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            2
+        };
+        assert_eq!(drain(task, &deadline).await, Some(2));
+
+        let stuck = std::future::pending::<()>();
+        assert_eq!(drain(stuck, &deadline).await, None);
+    }
+
+    #[tokio::test]
+    async fn one_deadline_for_every_task() {
+        let shutdown = CancellationToken::new();
+        let deadline = Deadline::new(shutdown.clone(), Duration::from_millis(50));
+        shutdown.cancel();
+        let started = Instant::now();
+
+        // Drained one after the other, the second only gets what's left
+        let stuck = std::future::pending::<()>();
+        assert_eq!(drain(stuck, &deadline).await, None);
+        let stuck = std::future::pending::<()>();
+        assert_eq!(drain(stuck, &deadline).await, None);
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,121 @@
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use thiserror::Error;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+use uuid::Uuid;
+
+use crate::env;
+
+/// Header carrying the request ID, accepted from clients and returned in responses
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request ID accepted from a client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+#[derive(Error, Debug)]
+pub enum TelemetryError {
+    #[error("invalid LOG_FORMAT {0:?}, expected text or json")]
+    InvalidLogFormat(String),
+    #[error("failed to set up OTLP exporter: {0}")]
+    Otlp(#[from] opentelemetry::trace::TraceError),
+    #[error("failed to set up logging: {0}")]
+    Init(#[from] tracing_subscriber::util::TryInitError),
+}
+
+/// Flushes exported spans when dropped, so keep it alive until exit
+pub struct TelemetryGuard {
+    otlp: bool,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if self.otlp {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Set up logging, filtered by RUST_LOG (default INFO), as text or JSON per LOG_FORMAT.
+/// Spans are also exported to OTEL_EXPORTER_OTLP_ENDPOINT over OTLP/HTTP, if set.
+/// Records from the `log` crate are included, within the current span.
+pub fn init(service_name: &'static str) -> Result<TelemetryGuard, TelemetryError> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let format = env::Cfg::LogFormat
+        .load()
+        .unwrap_or_else(|_| "text".to_string());
+    let fmt = match format.as_str() {
+        "text" => tracing_subscriber::fmt::layer().boxed(),
+        "json" => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+        _ => return Err(TelemetryError::InvalidLogFormat(format)),
+    };
+
+    let endpoint = env::Cfg::OtelExporterOtlpEndpoint
+        .load()
+        .ok()
+        .filter(|e| !e.is_empty());
+    let otlp = match &endpoint {
+        Some(endpoint) => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .http()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+                    opentelemetry_sdk::Resource::new([KeyValue::new("service.name", service_name)]),
+                ))
+                .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(otlp)
+        .try_init()?;
+
+    if let Some(endpoint) = &endpoint {
+        tracing::info!("exporting spans to {}", endpoint);
+    }
+
+    Ok(TelemetryGuard {
+        otlp: endpoint.is_some(),
+    })
+}
+
+/// Request ID sent by the client, if it's usable, otherwise a new one
+pub fn request_id(header: Option<&str>) -> String {
+    header
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_usable_request_ids() {
+        assert_eq!(request_id(Some("abc-123")), "abc-123");
+
+        let long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        for header in [None, Some(""), Some("has space"), Some(long.as_str())] {
+            let id = request_id(header);
+            assert!(Uuid::parse_str(&id).is_ok(), "{:?}", header);
+        }
+    }
+}
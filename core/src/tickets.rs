@@ -0,0 +1,171 @@
+use std::io::Cursor;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier};
+use qrcode::QrCode;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Prefix of ticket credentials, identifying the token format
+const CREDENTIAL_PREFIX: &str = "FT1";
+
+/// Minimum width and height of rendered QR codes, in pixels
+const QR_CODE_SIZE: u32 = 256;
+
+#[derive(Error, Debug)]
+pub enum TicketError {
+    #[error("invalid ticket signing key: {0}")]
+    InvalidKey(String),
+    #[error("invalid ticket credential: {0}")]
+    InvalidCredential(String),
+    #[error("failed to render QR code: {0}")]
+    QrCode(String),
+}
+
+/// Claims signed into a ticket credential. Field names are kept short to keep QR codes small.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TicketClaims {
+    /// Order ID
+    pub oid: String,
+    /// Ticket type ID
+    pub tt: String,
+    /// Duration in days
+    pub d: i32,
+    /// Issued at, in seconds since the Unix epoch
+    pub iat: i64,
+}
+
+/// Signs ticket credentials with an Ed25519 key
+#[derive(Clone)]
+pub struct TicketSigner {
+    key: SigningKey,
+}
+
+impl TicketSigner {
+    /// Load the signing key from a base64 encoded 32 byte Ed25519 seed
+    pub fn from_base64(seed: &str) -> Result<Self, TicketError> {
+        let seed: [u8; 32] = STANDARD
+            .decode(seed.trim())
+            .map_err(|e| TicketError::InvalidKey(e.to_string()))?
+            .try_into()
+            .map_err(|_| TicketError::InvalidKey("expected a 32 byte seed".to_string()))?;
+
+        Ok(Self {
+            key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    /// Sign claims into a compact credential, `FT1.<payload>.<signature>`.
+    ///
+    /// The payload is the JSON encoded claims, and the signature covers everything before it.
+    /// Both are base64url encoded without padding.
+    pub fn sign(&self, claims: &TicketClaims) -> String {
+        let payload = serde_json::to_vec(claims).expect("ticket claims should serialise");
+        let message = format!("{}.{}", CREDENTIAL_PREFIX, URL_SAFE_NO_PAD.encode(payload));
+        let signature = self.key.sign(message.as_bytes());
+        format!(
+            "{}.{}",
+            message,
+            URL_SAFE_NO_PAD.encode(signature.to_bytes())
+        )
+    }
+
+    /// Public key for verifying credentials, base64 encoded. Shared with offline scanners.
+    pub fn verifying_key(&self) -> String {
+        STANDARD.encode(self.key.verifying_key().to_bytes())
+    }
+
+    /// Verify a credential's signature and decode its claims
+    pub fn verify(&self, credential: &str) -> Result<TicketClaims, TicketError> {
+        let invalid = |reason: &str| TicketError::InvalidCredential(reason.to_string());
+
+        let (message, signature) = credential
+            .trim()
+            .rsplit_once('.')
+            .ok_or_else(|| invalid("malformed"))?;
+        let (prefix, payload) = message
+            .split_once('.')
+            .ok_or_else(|| invalid("malformed"))?;
+        if prefix != CREDENTIAL_PREFIX {
+            return Err(invalid("unsupported format"));
+        }
+
+        let signature: [u8; 64] = URL_SAFE_NO_PAD
+            .decode(signature)
+            .ok()
+            .and_then(|s| s.try_into().ok())
+            .ok_or_else(|| invalid("malformed signature"))?;
+        self.key
+            .verifying_key()
+            .verify(message.as_bytes(), &Signature::from_bytes(&signature))
+            .map_err(|_| invalid("signature doesn't match"))?;
+
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| invalid("malformed payload"))?;
+        serde_json::from_slice(&payload).map_err(|e| TicketError::InvalidCredential(e.to_string()))
+    }
+}
+
+/// Render a credential as a PNG QR code
+pub fn render_qr_png(credential: &str) -> Result<Vec<u8>, TicketError> {
+    let code = QrCode::new(credential).map_err(|e| TicketError::QrCode(e.to_string()))?;
+    let image = code
+        .render::<image::Luma<u8>>()
+        .min_dimensions(QR_CODE_SIZE, QR_CODE_SIZE)
+        .build();
+
+    let mut png = Cursor::new(vec![]);
+    image
+        .write_to(&mut png, image::ImageOutputFormat::Png)
+        .map_err(|e| TicketError::QrCode(e.to_string()))?;
+    Ok(png.into_inner())
+}
+
+/// Render a credential as an SVG QR code
+pub fn render_qr_svg(credential: &str) -> Result<String, TicketError> {
+    let code = QrCode::new(credential).map_err(|e| TicketError::QrCode(e.to_string()))?;
+    Ok(code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(QR_CODE_SIZE, QR_CODE_SIZE)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify_ticket_credential() {
+        let signer = TicketSigner::from_base64(&STANDARD.encode([7u8; 32])).unwrap();
+        let claims = TicketClaims {
+            oid: "0b5a3f04-5bd0-4b8e-9d3e-3d5c2f0e6a71".to_string(),
+            tt: "chalet3".to_string(),
+            d: 3,
+            iat: 1710158400,
+        };
+
+        let credential = signer.sign(&claims);
+        assert!(credential.starts_with("FT1."));
+        assert_eq!(signer.verify(&credential).unwrap(), claims);
+
+        // Tampered payload
+        let (_, signature) = credential.rsplit_once('.').unwrap();
+        let forged = TicketClaims { d: 4, ..claims };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        assert!(signer
+            .verify(&format!("FT1.{}.{}", payload, signature))
+            .is_err());
+
+        // Signed with another key
+        let other = TicketSigner::from_base64(&STANDARD.encode([8u8; 32])).unwrap();
+        assert!(other.verify(&credential).is_err());
+        assert!(signer.verify("not-a-credential").is_err());
+
+        assert!(render_qr_png(&credential).unwrap().starts_with(b"\x89PNG"));
+        assert!(render_qr_svg(&credential).unwrap().contains("<svg"));
+
+        assert!(TicketSigner::from_base64("dG9vIHNob3J0").is_err());
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::db::{self, error::DbError, DbPool};
+
+/// Order lifecycle events endpoints can subscribe to. `order.refunded` is reserved: endpoints
+/// can subscribe to it, but nothing sends it until orders can be refunded
+pub const ORDER_EVENTS: [&str; 4] = [
+    "order.reserved",
+    "order.purchased",
+    "order.expired",
+    "order.refunded",
+];
+
+/// Sent to a single endpoint by the test-fire API
+pub const TEST_EVENT: &str = "webhook.test";
+
+/// Header with the delivery signature, `t=<unix timestamp>,v1=<hex HMAC-SHA256>`
+pub const SIGNATURE_HEADER: &str = "x-festival-signature";
+
+/// How long endpoints have to respond
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum WebhookError {
+    #[error("webhook request failed: {0}")]
+    RequestFailed(String),
+    #[error("webhook endpoint responded with status {0}")]
+    Rejected(u16),
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+/// Generate a secret for signing deliveries to a new endpoint
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    format!("whsec_{}", hex::encode(secret))
+}
+
+/// Sign a delivery body. The timestamp is signed with the body, so receivers can reject
+/// replayed deliveries.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Check an event type can be subscribed to
+pub fn check_event(event: &str) -> Result<(), String> {
+    if ORDER_EVENTS.contains(&event) {
+        Ok(())
+    } else {
+        Err(format!(
+            "unknown webhook event {}, expected one of {}",
+            event,
+            ORDER_EVENTS.join(", ")
+        ))
+    }
+}
+
+/// Posts deliveries to endpoints
+pub struct WebhookSender {
+    client: reqwest::Client,
+}
+
+impl Default for WebhookSender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WebhookSender {
+    pub fn new() -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .build()
+            .expect("failed to build webhook client");
+
+        Self { client }
+    }
+
+    /// Attempt a delivery, unless it was already delivered, and record the outcome
+    pub async fn deliver(&self, pool: &DbPool, delivery_id: i64) -> Result<(), WebhookError> {
+        let Some(delivery) = db::webhooks::get_pending_delivery(pool, delivery_id).await? else {
+            return Ok(());
+        };
+
+        let signature = sign(
+            &delivery.secret,
+            chrono::Utc::now().timestamp(),
+            &delivery.payload,
+        );
+        let res = self
+            .client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, signature)
+            .header("x-festival-event", &delivery.event)
+            .header("x-festival-event-id", delivery.event_id.to_string())
+            .body(delivery.payload)
+            .send()
+            .await;
+
+        let (status, res) = match res {
+            Ok(res) if res.status().is_success() => (Some(res.status().as_u16()), Ok(())),
+            Ok(res) => (
+                Some(res.status().as_u16()),
+                Err(WebhookError::Rejected(res.status().as_u16())),
+            ),
+            Err(e) => (None, Err(WebhookError::RequestFailed(e.to_string()))),
+        };
+
+        db::webhooks::record_delivery_attempt(
+            pool,
+            delivery_id,
+            status.map(i32::from),
+            res.as_ref().err().map(|e| e.to_string()).as_deref(),
+        )
+        .await?;
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_deliveries() {
+        // Checked with `printf '1700000000.{}' | openssl dgst -sha256 -hmac secret`
+        assert_eq!(
+            sign("secret", 1700000000, "{}"),
+            "t=1700000000,v1=b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"
+        );
+        assert_ne!(
+            sign("secret", 1700000001, "{}"),
+            sign("secret", 1700000000, "{}")
+        );
+
+        let secret = generate_secret();
+        assert!(secret.starts_with("whsec_"));
+        assert_eq!(secret.len(), 6 + 64);
+        assert_ne!(secret, generate_secret());
+    }
+
+    #[test]
+    fn check_events() {
+        assert!(check_event("order.purchased").is_ok());
+        assert!(check_event("order.shipped").is_err());
+        assert!(check_event(TEST_EVENT).is_err());
+    }
+}
@@ -0,0 +1,319 @@
+//! Reservations, purchases and expiry racing each other on a small inventory. Each test gets its
+//! own database, created from `DATABASE_URL` and migrated by `sqlx::test`.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use chrono::{FixedOffset, NaiveDate};
+use festival_tickets_core::catalogue::Catalogue;
+use festival_tickets_core::config::CatalogueConfig;
+use festival_tickets_core::db::checkin::CheckInPolicy;
+use festival_tickets_core::db::limits::PurchaseLimit;
+use festival_tickets_core::db::replica::ReadPool;
+use festival_tickets_core::db::{self, DbPool};
+use festival_tickets_core::model::NewUser;
+use festival_tickets_core::tickets::TicketSigner;
+use festival_tickets_core::{Error, Ticketing, TicketingService};
+use futures::future::join_all;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+const SIGNING_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+/// More than the default for tests, so requests queue in the database rather than the pool
+const MAX_CONNECTIONS: u32 = 20;
+
+async fn connect(pool_options: PgPoolOptions, options: PgConnectOptions) -> DbPool {
+    pool_options
+        .max_connections(MAX_CONNECTIONS)
+        .connect_with(options)
+        .await
+        .unwrap()
+}
+
+fn ticketing(pool: &DbPool, reserve_for: chrono::Duration) -> Ticketing {
+    Ticketing::new(
+        pool.clone(),
+        PurchaseLimit(None),
+        reserve_for,
+        TicketSigner::from_base64(SIGNING_KEY).unwrap(),
+        CheckInPolicy {
+            festival_start: NaiveDate::from_ymd_opt(2024, 7, 18).unwrap(),
+            utc_offset: FixedOffset::east_opt(0).unwrap(),
+            allow_reentry: true,
+        },
+        Catalogue::new(&CatalogueConfig::default()),
+        ReadPool::new(pool.clone(), None, Duration::ZERO),
+    )
+}
+
+async fn set_limit(pool: &DbPool, duration: i32, limit: i32) {
+    sqlx::query("UPDATE order_stats SET order_limit = $2 WHERE duration_days = $1")
+        .bind(duration)
+        .bind(limit)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+/// Order limit, order count and the orders actually held, by duration
+async fn inventory(pool: &DbPool) -> HashMap<i32, (i32, i32, i64)> {
+    let rows: Vec<(i32, i32, i32, i64)> = sqlx::query_as(
+        r#"
+SELECT os.duration_days, os.order_limit, coalesce(os.order_count, 0), count(o.id)
+FROM order_stats AS os
+LEFT JOIN orders AS o ON o.duration_days = os.duration_days
+GROUP BY os.duration_days
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap();
+
+    rows.into_iter()
+        .map(|(duration, limit, count, orders)| (duration, (limit, count, orders)))
+        .collect()
+}
+
+/// Check orders held never exceed the limit, sampling until `done` is set
+async fn watch_limits(pool: &DbPool, done: &AtomicBool) {
+    while !done.load(Ordering::Acquire) {
+        for (duration, (limit, _, orders)) in inventory(pool).await {
+            assert!(
+                orders <= limit.into(),
+                "{} orders held for {} days, over the limit of {}",
+                orders,
+                duration,
+                limit
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+/// Orders held match each order count, and are within the limit
+async fn assert_consistent(pool: &DbPool) {
+    for (duration, (limit, count, orders)) in inventory(pool).await {
+        assert_eq!(
+            i64::from(count),
+            orders,
+            "order_count for {} days doesn't match the orders held",
+            duration
+        );
+        assert!(orders <= limit.into());
+    }
+}
+
+fn is_sold_out(e: &Error) -> bool {
+    matches!(e, Error::SoldOut(_))
+}
+
+/// Start a presale running now, with one code everyone reserves with
+async fn start_presale(pool: &DbPool, code: &str) {
+    sqlx::query(
+        "INSERT INTO presales (id, starts_at, ends_at) VALUES ('launch', now() - interval '1 hour', now() + interval '1 hour')",
+    )
+    .execute(pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO presale_codes (code, presale_id, ticket_limit) VALUES ($1, 'launch', 100000)",
+    )
+    .bind(code)
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+async fn presale_redeemed(pool: &DbPool, code: &str) -> i32 {
+    sqlx::query_scalar("SELECT redeemed FROM presale_codes WHERE code = $1")
+        .bind(code)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+async fn reserve_and_maybe_purchase(
+    ticketing: &Ticketing,
+    user: usize,
+    duration: i32,
+    presale_code: Option<&str>,
+    purchase: bool,
+) -> Result<bool, Error> {
+    let order = ticketing
+        .add_ticket_to_basket("chalet3", duration, presale_code)
+        .await?;
+    if !purchase {
+        return Ok(false);
+    }
+    let user = NewUser {
+        name: format!("User {}", user),
+        email: format!("user{}@example.com", user),
+        address: format!("{} Test Street", user),
+    };
+    ticketing.add_user_info(&order.id, &user).await?;
+    ticketing.purchase_order(&order.id).await?;
+    Ok(true)
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn reservations_never_exceed_the_limit(
+    pool_options: PgPoolOptions,
+    options: PgConnectOptions,
+) {
+    let pool = connect(pool_options, options).await;
+    set_limit(&pool, 3, 20).await;
+    set_limit(&pool, 4, 10).await;
+    let ticketing = ticketing(&pool, chrono::Duration::minutes(10));
+
+    let done = AtomicBool::new(false);
+    let launch = async {
+        let results = join_all((0..2000).map(|user| {
+            let duration = if user % 2 == 0 { 3 } else { 4 };
+            reserve_and_maybe_purchase(&ticketing, user, duration, None, user % 3 == 0)
+        }))
+        .await;
+        done.store(true, Ordering::Release);
+        results
+    };
+    let (results, ()) = tokio::join!(launch, watch_limits(&pool, &done));
+
+    let mut reserved = 0;
+    for res in results {
+        match res {
+            Ok(_) => reserved += 1,
+            Err(e) => assert!(is_sold_out(&e), "unexpected error: {:?}", e),
+        }
+    }
+    assert_eq!(reserved, 30, "every ticket should sell, and no more");
+
+    let inventory = inventory(&pool).await;
+    assert_eq!(inventory[&3], (20, 20, 20));
+    assert_eq!(inventory[&4], (10, 10, 10));
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn expiry_returns_inventory_once(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let pool = connect(pool_options, options).await;
+    set_limit(&pool, 3, 30).await;
+    let ticketing = ticketing(&pool, chrono::Duration::minutes(10));
+
+    let results = join_all(
+        (0..30).map(|user| reserve_and_maybe_purchase(&ticketing, user, 3, None, user < 10)),
+    )
+    .await;
+    assert!(results.iter().all(Result::is_ok));
+
+    // Let the unpurchased reservations lapse
+    sqlx::query(
+        "UPDATE orders SET reserved_until = reserved_until - interval '1 day' WHERE purchased_at IS NULL",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    // As if every instance's expiry job ran at once
+    let expired: usize = join_all((0..8).map(|_| db::remove_expired_orders(&pool)))
+        .await
+        .into_iter()
+        .map(|res| res.unwrap().len())
+        .sum();
+    assert_eq!(expired, 20);
+    assert_eq!(inventory(&pool).await[&3], (30, 10, 10));
+
+    let expired = db::remove_expired_orders(&pool).await.unwrap();
+    assert!(expired.is_empty());
+
+    // The returned tickets can be reserved again, once each
+    let results = join_all(
+        (100..300).map(|user| reserve_and_maybe_purchase(&ticketing, user, 3, None, false)),
+    )
+    .await;
+    assert_eq!(results.iter().filter(|res| res.is_ok()).count(), 20);
+    assert_eq!(inventory(&pool).await[&3], (30, 30, 30));
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn order_count_matches_orders_while_expiring(
+    pool_options: PgPoolOptions,
+    options: PgConnectOptions,
+) {
+    let pool = connect(pool_options, options).await;
+    reserve_while_expiring(&pool, None).await;
+}
+
+/// Expiry returns presale codes as well as order counts, so it mustn't lock them in a different
+/// order to reservations
+#[sqlx::test(migrations = "../migrations")]
+async fn order_count_matches_orders_while_expiring_during_presale(
+    pool_options: PgPoolOptions,
+    options: PgConnectOptions,
+) {
+    let pool = connect(pool_options, options).await;
+    start_presale(&pool, "launch-code").await;
+    reserve_while_expiring(&pool, Some("launch-code")).await;
+    assert_eq!(presale_redeemed(&pool, "launch-code").await, 0);
+}
+
+/// Reserve tickets which lapse straight away, while expiry runs, until some have been reserved
+/// again
+async fn reserve_while_expiring(pool: &DbPool, presale_code: Option<&str>) {
+    set_limit(pool, 3, 20).await;
+    set_limit(pool, 4, 20).await;
+    // Reservations lapse straight away, so expiry races reservations for the same tickets
+    let ticketing = ticketing(pool, chrono::Duration::zero());
+
+    let done = AtomicBool::new(false);
+    let launch = async {
+        // In rounds, until expired tickets have been reserved again, as how many are depends on
+        // when expiry runs
+        let mut results = vec![];
+        for round in 0..20 {
+            let users = round * 100..(round + 1) * 100;
+            results.extend(
+                join_all(users.map(|user| {
+                    let duration = if user % 2 == 0 { 3 } else { 4 };
+                    reserve_and_maybe_purchase(&ticketing, user, duration, presale_code, false)
+                }))
+                .await,
+            );
+            if results.iter().filter(|res| res.is_ok()).count() > 40 {
+                break;
+            }
+        }
+        done.store(true, Ordering::Release);
+        results
+    };
+    let expire = async {
+        let mut expired = 0;
+        while !done.load(Ordering::Acquire) {
+            let batches = join_all((0..4).map(|_| db::remove_expired_orders(pool))).await;
+            for batch in batches {
+                expired += batch.unwrap().len();
+            }
+            assert_consistent(pool).await;
+        }
+        expired
+    };
+    let (results, expired, ()) = tokio::join!(launch, expire, watch_limits(pool, &done));
+
+    let mut reserved = 0;
+    for res in results {
+        match res {
+            Ok(_) => reserved += 1,
+            Err(e) => assert!(is_sold_out(&e), "unexpected error: {:?}", e),
+        }
+    }
+    assert!(
+        reserved > 40,
+        "expired tickets should have been reserved again"
+    );
+
+    // Whatever's left lapses too, and every reservation is returned exactly once
+    let expired = expired + db::remove_expired_orders(pool).await.unwrap().len();
+    assert_eq!(expired, reserved);
+    assert_consistent(pool).await;
+    let inventory = inventory(pool).await;
+    assert_eq!(inventory[&3], (20, 0, 0));
+    assert_eq!(inventory[&4], (20, 0, 0));
+}
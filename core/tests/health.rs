@@ -0,0 +1,24 @@
+//! Readiness checks don't wait on the request pool. Each test gets its own database, created from
+//! `DATABASE_URL` and migrated by `sqlx::test`.
+
+use festival_tickets_core::config::WorkerConfig;
+use festival_tickets_core::health::{HealthCheck, Heartbeat};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+#[sqlx::test(migrations = "../migrations")]
+async fn ready_while_the_pool_is_busy(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let pool = pool_options
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .unwrap();
+    let health = HealthCheck::new(&pool, Heartbeat::new(), &WorkerConfig::default());
+
+    // Every request connection is in use, as in a launch
+    let _held = pool.acquire().await.unwrap();
+    let report = health.check().await;
+    assert_eq!(report.database, Some(true));
+    assert!(report.is_ready());
+
+    assert_eq!(health.check_live().database, None);
+}
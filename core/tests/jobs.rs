@@ -0,0 +1,242 @@
+//! Jobs are only finished by the worker holding their lock, failed jobs are retried with a capped
+//! backoff, and emails aren't sent again after an interrupted send until an admin retries them.
+//! Each test gets its own database, created from `DATABASE_URL` and migrated by `sqlx::test`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use festival_tickets_core::config::WorkerConfig;
+use festival_tickets_core::db::outbox::{self, EmailClaim};
+use festival_tickets_core::db::{jobs, DbPool};
+use festival_tickets_core::jobs::{Job, JobRunner};
+use festival_tickets_core::mail::MemoryMailer;
+use festival_tickets_core::shutdown::CancellationToken;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::types::Uuid;
+
+/// Wait for job `id` to be in `state`
+async fn wait_for_state(pool: &DbPool, id: i64, state: &str) {
+    for _ in 0..100 {
+        let current: String = sqlx::query_scalar("SELECT state FROM jobs WHERE id = $1")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        if current == state {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("job {} never became {}", id, state);
+}
+
+async fn state(pool: &DbPool, id: i64) -> (String, Option<String>) {
+    sqlx::query_as("SELECT state, last_error FROM jobs WHERE id = $1")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn only_the_lock_holder_finishes_a_job(
+    pool_options: PgPoolOptions,
+    options: PgConnectOptions,
+) {
+    let pool = pool_options.connect_with(options).await.unwrap();
+    let kinds = ["send_email"];
+    let mut conn = pool.acquire().await.unwrap();
+    let id = jobs::enqueue_job(&mut conn, &Job::SendEmail { outbox_id: 1 })
+        .await
+        .unwrap();
+    drop(conn);
+
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+    let claimed = jobs::claim_job(&pool, first, &kinds, 60)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(claimed.id, id);
+    assert!(!jobs::complete_job(&pool, id, second).await.unwrap());
+    assert!(!jobs::fail_job(&pool, id, second, "not mine", true)
+        .await
+        .unwrap());
+    assert_eq!(state(&pool, id).await, ("running".to_string(), None));
+
+    // The first worker's lock expires, and the job is claimed by the second
+    sqlx::query("UPDATE jobs SET locked_until = now() - interval '1 second' WHERE id = $1")
+        .bind(id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(!jobs::complete_job(&pool, id, first).await.unwrap());
+    let claimed = jobs::claim_job(&pool, second, &kinds, 60)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((claimed.id, claimed.attempts), (id, 2));
+
+    assert!(!jobs::fail_job(&pool, id, first, "too late", true)
+        .await
+        .unwrap());
+    assert!(jobs::complete_job(&pool, id, second).await.unwrap());
+    assert_eq!(state(&pool, id).await, ("succeeded".to_string(), None));
+}
+
+/// Seconds until the job next runs
+async fn backoff(pool: &DbPool, id: i64) -> f64 {
+    sqlx::query_scalar(
+        "SELECT extract(epoch FROM run_after - now())::float8 FROM jobs WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn backoff_is_capped_however_often_a_job_failed(
+    pool_options: PgPoolOptions,
+    options: PgConnectOptions,
+) {
+    let pool = pool_options.connect_with(options).await.unwrap();
+    let worker = Uuid::new_v4();
+    let mut conn = pool.acquire().await.unwrap();
+    let once = jobs::enqueue_job(&mut conn, &Job::SendEmail { outbox_id: 1 })
+        .await
+        .unwrap();
+    drop(conn);
+    jobs::schedule_recurring_job(&pool, &Job::ExpireReservations, 30)
+        .await
+        .unwrap();
+    let recurring: i64 = sqlx::query_scalar("SELECT id FROM jobs WHERE repeat_secs IS NOT NULL")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+    // A job which failed for days
+    for (id, attempts, max_secs) in [(once, 2000, 3600.0), (recurring, 2000, 30.0)] {
+        sqlx::query(
+            r#"
+UPDATE jobs
+SET state = 'running', attempts = $2, max_attempts = $2 + 1, locked_by = $3,
+    locked_until = now() + interval '1 minute'
+WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(attempts)
+        .bind(worker)
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(jobs::fail_job(&pool, id, worker, "still down", true)
+            .await
+            .unwrap());
+        let secs = backoff(&pool, id).await;
+        assert!(
+            secs > max_secs - 5.0 && secs <= max_secs,
+            "{} retries in {}s",
+            id,
+            secs
+        );
+    }
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn interrupted_emails_are_not_sent_again(
+    pool_options: PgPoolOptions,
+    options: PgConnectOptions,
+) {
+    let pool = pool_options.connect_with(options).await.unwrap();
+    let id: i64 = sqlx::query_scalar(
+        r#"
+INSERT INTO email_outbox (dedupe_key, template, recipient, subject, body)
+VALUES ('test', 'test', 'fan@example.com', 'Hello', 'Hello')
+RETURNING id
+        "#,
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+
+    // The mailer failed, so it's sent by the next attempt
+    assert!(matches!(
+        outbox::claim_email(&pool, id).await.unwrap(),
+        EmailClaim::Claimed(_)
+    ));
+    outbox::release_email(&mut pool.acquire().await.unwrap(), id)
+        .await
+        .unwrap();
+    assert!(matches!(
+        outbox::claim_email(&pool, id).await.unwrap(),
+        EmailClaim::Claimed(_)
+    ));
+
+    // The worker died while sending
+    assert_eq!(
+        outbox::claim_email(&pool, id).await.unwrap(),
+        EmailClaim::Interrupted
+    );
+
+    outbox::mark_email_sent(&pool, id).await.unwrap();
+    assert_eq!(
+        outbox::claim_email(&pool, id).await.unwrap(),
+        EmailClaim::Sent
+    );
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn interrupted_emails_are_dead_lettered_until_retried(
+    pool_options: PgPoolOptions,
+    options: PgConnectOptions,
+) {
+    let pool = pool_options.connect_with(options).await.unwrap();
+    let mut tx = pool.begin().await.unwrap();
+    let outbox_id: i64 = sqlx::query_scalar(
+        r#"
+INSERT INTO email_outbox (dedupe_key, template, recipient, subject, body)
+VALUES ('test', 'test', 'fan@example.com', 'Hello', 'Hello')
+RETURNING id
+        "#,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .unwrap();
+    let id = jobs::enqueue_job(&mut tx, &Job::SendEmail { outbox_id })
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    // A worker claimed the email, then died before marking it sent
+    assert!(matches!(
+        outbox::claim_email(&pool, outbox_id).await.unwrap(),
+        EmailClaim::Claimed(_)
+    ));
+
+    let mailer = Arc::new(MemoryMailer::default());
+    let config = WorkerConfig {
+        poll_interval_ms: 20,
+        ..WorkerConfig::default()
+    };
+    let runner = JobRunner::new(Arc::new(pool.clone()), Some(mailer.clone()), config);
+    let shutdown = CancellationToken::new();
+    let running = tokio::spawn(runner.run(shutdown.clone()));
+
+    wait_for_state(&pool, id, "dead").await;
+    let (_, last_error) = state(&pool, id).await;
+    assert!(last_error.unwrap().contains("interrupted"));
+    assert!(mailer.sent().is_empty());
+
+    jobs::retry_job(&pool, id).await.unwrap();
+    wait_for_state(&pool, id, "succeeded").await;
+    let sent = mailer.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(
+        sent[0].0,
+        format!("<outbox-{}@festival-tickets>", outbox_id)
+    );
+
+    shutdown.cancel();
+    running.await.unwrap();
+}
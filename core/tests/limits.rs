@@ -0,0 +1,136 @@
+//! Customers with blank details don't share a purchase limit, and households share overrides. Each
+//! test gets its own database, created from `DATABASE_URL` and migrated by `sqlx::test`.
+
+use std::time::Duration;
+
+use chrono::{FixedOffset, NaiveDate};
+use festival_tickets_core::catalogue::Catalogue;
+use festival_tickets_core::config::CatalogueConfig;
+use festival_tickets_core::db::checkin::CheckInPolicy;
+use festival_tickets_core::db::limits::PurchaseLimit;
+use festival_tickets_core::db::replica::ReadPool;
+use festival_tickets_core::db::DbPool;
+use festival_tickets_core::model::NewUser;
+use festival_tickets_core::tickets::TicketSigner;
+use festival_tickets_core::{Error, Ticketing, TicketingService};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::types::Uuid;
+
+const SIGNING_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+fn ticketing(pool: &DbPool, limit: i32) -> Ticketing {
+    Ticketing::new(
+        pool.clone(),
+        PurchaseLimit(Some(limit)),
+        chrono::Duration::minutes(10),
+        TicketSigner::from_base64(SIGNING_KEY).unwrap(),
+        CheckInPolicy {
+            festival_start: NaiveDate::from_ymd_opt(2024, 7, 18).unwrap(),
+            utc_offset: FixedOffset::east_opt(0).unwrap(),
+            allow_reentry: true,
+        },
+        Catalogue::new(&CatalogueConfig::default()),
+        ReadPool::new(pool.clone(), None, Duration::ZERO),
+    )
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn blank_details_are_rejected(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let pool = pool_options.connect_with(options).await.unwrap();
+    let ticketing = ticketing(&pool, 1);
+
+    for (email, address) in [("fan@example.com", " ., "), ("  ", "1 High Street")] {
+        let order = ticketing
+            .add_ticket_to_basket("chalet3", 3, None)
+            .await
+            .unwrap();
+        let user = NewUser {
+            name: "Fan".to_string(),
+            email: email.to_string(),
+            address: address.to_string(),
+        };
+        assert!(
+            matches!(
+                ticketing.add_user_info(&order.id, &user).await,
+                Err(Error::FailedPrecondition(_))
+            ),
+            "{:?} and {:?} should be rejected",
+            email,
+            address
+        );
+    }
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn blank_addresses_are_not_shared(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let pool = pool_options.connect_with(options).await.unwrap();
+    let ticketing = ticketing(&pool, 1);
+
+    // As users added before blank details were rejected
+    for email in ["one@example.com", "two@example.com"] {
+        let order = ticketing
+            .add_ticket_to_basket("chalet3", 3, None)
+            .await
+            .unwrap();
+        let user_id: Uuid = sqlx::query_scalar(
+            r#"
+INSERT INTO users (name, email, address, email_normalised, address_normalised)
+VALUES ('Fan', $1, '', $1, '')
+RETURNING id
+            "#,
+        )
+        .bind(email)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        sqlx::query("UPDATE orders SET user_id = $2 WHERE id = $1")
+            .bind(order.id)
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        ticketing.purchase_order(&order.id).await.unwrap();
+    }
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn overrides_cover_group_bookings_at_a_shared_address(
+    pool_options: PgPoolOptions,
+    options: PgConnectOptions,
+) {
+    let pool = pool_options.connect_with(options).await.unwrap();
+    let ticketing = ticketing(&pool, 1);
+    ticketing
+        .set_purchase_limit_override("booker@example.com", 4, Some("group booking"))
+        .await
+        .unwrap();
+
+    let buy = |email: &'static str| {
+        let ticketing = &ticketing;
+        async move {
+            let order = ticketing.add_ticket_to_basket("chalet3", 3, None).await?;
+            let user = NewUser {
+                name: "Fan".to_string(),
+                email: email.to_string(),
+                address: "1 High Street".to_string(),
+            };
+            ticketing.add_user_info(&order.id, &user).await?;
+            ticketing.purchase_order(&order.id).await
+        }
+    };
+
+    // The group buy under their own emails, against the booker's limit rather than the default
+    for email in [
+        "booker@example.com",
+        "booker@example.com",
+        "one@example.com",
+        "two@example.com",
+    ] {
+        buy(email).await.unwrap();
+    }
+    assert!(matches!(
+        buy("three@example.com").await,
+        Err(Error::FailedPrecondition(_))
+    ));
+}
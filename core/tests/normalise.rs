@@ -0,0 +1,47 @@
+//! Users from before purchase limits are normalised by a one-off job. Each test gets its own
+//! database, created from `DATABASE_URL` and migrated by `sqlx::test`.
+
+use festival_tickets_core::db::limits;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+#[sqlx::test(migrations = "../migrations")]
+async fn normalise_existing_users(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let pool = pool_options.connect_with(options).await.unwrap();
+
+    // As the purchase limits migration backfilled them
+    let users = [
+        ("O.Scar+Tickets@Gmail.com", "22 Oscar St.,  Dorset, UK"),
+        ("fan@example.com", "1 High Street"),
+        ("Fan+Two@Example.com", "2 High St."),
+    ];
+    for (email, address) in users {
+        sqlx::query(
+            r#"
+INSERT INTO users (name, email, address, email_normalised, address_normalised)
+VALUES ('User', $1, $2, lower(trim($1)), lower(trim($2)))
+            "#,
+        )
+        .bind(email)
+        .bind(address)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    assert_eq!(limits::normalise_users(&pool).await.unwrap(), 2);
+
+    let normalised: Vec<(String, String, String, String)> = sqlx::query_as(
+        "SELECT email, address, email_normalised, address_normalised FROM users ORDER BY email",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(normalised.len(), users.len());
+    for (email, address, email_normalised, address_normalised) in normalised {
+        assert_eq!(email_normalised, limits::normalise_email(&email));
+        assert_eq!(address_normalised, limits::normalise_address(&address));
+    }
+
+    // Nothing left to change
+    assert_eq!(limits::normalise_users(&pool).await.unwrap(), 0);
+}
@@ -0,0 +1,53 @@
+//! Promo code uses are only spent on live reservations. Each test gets its own database, created
+//! from `DATABASE_URL` and migrated by `sqlx::test`.
+
+use festival_tickets_core::db::{self, error::DbError, promo};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+#[sqlx::test(migrations = "../migrations")]
+async fn expired_reservations_dont_use_promo_codes(
+    pool_options: PgPoolOptions,
+    options: PgConnectOptions,
+) {
+    let pool = pool_options.connect_with(options).await.unwrap();
+    sqlx::query(
+        r#"
+INSERT INTO promo_codes (code, discount_kind, discount_value, max_uses)
+VALUES ('ONCE', 'fixed', 5, 1)
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let order = db::add_ticket_to_basket(&pool, "chalet3", 3, None, chrono::Duration::minutes(10))
+        .await
+        .unwrap();
+    // Lapsed, but expiry hasn't removed it yet
+    sqlx::query("UPDATE orders SET reserved_until = now() - interval '1 second' WHERE id = $1")
+        .bind(order.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let res = promo::apply_promo_code(&pool, &order.id, "ONCE").await;
+    assert!(
+        matches!(&res, Err(DbError::FailedPrecondition(e)) if e.contains("reservation expired")),
+        "{:?}",
+        res.map(|o| o.id)
+    );
+    let use_count: i32 =
+        sqlx::query_scalar("SELECT use_count FROM promo_codes WHERE code = 'ONCE'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(use_count, 0);
+
+    // A live reservation can still use it
+    let order = db::add_ticket_to_basket(&pool, "chalet3", 3, None, chrono::Duration::minutes(10))
+        .await
+        .unwrap();
+    promo::apply_promo_code(&pool, &order.id, "ONCE")
+        .await
+        .unwrap();
+}
@@ -0,0 +1,140 @@
+use festival_tickets_client::types::{AddTicketToBasketRequest, AddUserInfoRequest};
+use festival_tickets_tonic::pb;
+use festival_tickets_tonic::pb::product_service_client::ProductServiceClient;
+use tonic::transport::Channel;
+use uuid::Uuid;
+
+use crate::report::Failure;
+use crate::scenario::{ApiKind, Scenario};
+
+/// Client for the API virtual users call. Cheap to clone, sharing connections.
+#[derive(Clone)]
+pub enum Api {
+    Grpc(ProductServiceClient<Channel>),
+    Rest(festival_tickets_client::Client),
+}
+
+/// Details a virtual user adds to their order
+pub struct UserInfo {
+    pub name: String,
+    pub email: String,
+    pub address: String,
+}
+
+impl Api {
+    pub async fn connect(scenario: &Scenario) -> Result<Self, tonic::transport::Error> {
+        Ok(match scenario.api {
+            ApiKind::Grpc => Api::Grpc(connect_grpc(&scenario.grpc_url).await?),
+            ApiKind::Rest => Api::Rest(festival_tickets_client::Client::new(&scenario.rest_url)),
+        })
+    }
+
+    /// Returns the new order's ID
+    pub async fn add_ticket_to_basket(
+        &mut self,
+        ticket_type_id: &str,
+        duration: i32,
+    ) -> Result<String, Failure> {
+        match self {
+            Api::Grpc(client) => {
+                let res = client
+                    .add_ticket_to_basket(pb::AddTicketToBasketRequest {
+                        ticket_type_id: ticket_type_id.to_string(),
+                        duration,
+                        presale_code: None,
+                    })
+                    .await?;
+                res.into_inner()
+                    .order
+                    .map(|order| order.id)
+                    .ok_or_else(|| Failure::new("missing order", "no order in response"))
+            }
+            Api::Rest(client) => {
+                let order = client
+                    .add_ticket_to_basket(&AddTicketToBasketRequest {
+                        ticket_type_id: ticket_type_id.to_string(),
+                        duration,
+                        presale_code: None,
+                    })
+                    .await?;
+                Ok(order.into_inner().id.to_string())
+            }
+        }
+    }
+
+    pub async fn add_user_info(&mut self, order_id: &str, user: &UserInfo) -> Result<(), Failure> {
+        match self {
+            Api::Grpc(client) => {
+                client
+                    .add_user_info(pb::AddUserInfoRequest {
+                        order_id: order_id.to_string(),
+                        user_name: user.name.clone(),
+                        user_address: user.address.clone(),
+                        user_email: user.email.clone(),
+                    })
+                    .await?;
+            }
+            Api::Rest(client) => {
+                client
+                    .add_user_info(
+                        &parse_order_id(order_id)?,
+                        &AddUserInfoRequest {
+                            name: user.name.clone(),
+                            address: user.address.clone(),
+                            email: user.email.clone(),
+                        },
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn purchase_order(&mut self, order_id: &str) -> Result<(), Failure> {
+        match self {
+            Api::Grpc(client) => {
+                client
+                    .purchase_order(pb::PurchaseOrderRequest {
+                        id: order_id.to_string(),
+                    })
+                    .await?;
+            }
+            Api::Rest(client) => {
+                client.purchase_order(&parse_order_id(order_id)?).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn connect_grpc(
+    url: &str,
+) -> Result<ProductServiceClient<Channel>, tonic::transport::Error> {
+    ProductServiceClient::connect(url.to_string()).await
+}
+
+fn parse_order_id(order_id: &str) -> Result<Uuid, Failure> {
+    order_id
+        .parse()
+        .map_err(|_| Failure::new("invalid order id", order_id))
+}
+
+impl From<tonic::Status> for Failure {
+    fn from(value: tonic::Status) -> Self {
+        Failure::new(format!("{:?}", value.code()), value.message())
+    }
+}
+
+impl<E: std::fmt::Debug> From<festival_tickets_client::Error<E>> for Failure {
+    fn from(value: festival_tickets_client::Error<E>) -> Self {
+        let kind = match value.status() {
+            Some(status) => status.to_string(),
+            None => "no response".to_string(),
+        };
+        let message = match value {
+            festival_tickets_client::Error::ErrorResponse(res) => format!("{:?}", res.into_inner()),
+            e => e.to_string(),
+        };
+        Failure::new(kind, message)
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+
+use sqlx::PgPool;
+
+/// Order stats for a duration, and the orders actually held for it
+#[derive(Clone, Debug, PartialEq, sqlx::FromRow)]
+pub struct Inventory {
+    pub duration_days: i32,
+    pub order_limit: i32,
+    pub order_count: i32,
+    pub orders: i64,
+}
+
+pub async fn read(pool: &PgPool) -> Result<Vec<Inventory>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+SELECT
+    os.duration_days,
+    os.order_limit,
+    coalesce(os.order_count, 0) AS order_count,
+    count(o.id) AS orders
+FROM order_stats AS os
+LEFT JOIN orders AS o ON o.duration_days = os.duration_days
+GROUP BY os.duration_days
+ORDER BY os.duration_days
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Compare the inventory after the launch with before it, and the reservations which succeeded.
+/// Returns every problem found. Assumes no reservations expired during the launch.
+pub fn check(
+    before: &[Inventory],
+    after: &[Inventory],
+    reserved: &BTreeMap<i32, u64>,
+) -> Vec<String> {
+    let mut problems = vec![];
+    for inventory in after {
+        let days = inventory.duration_days;
+        if inventory.orders > inventory.order_limit.into() {
+            problems.push(format!(
+                "{} days oversold: {} orders for a limit of {}",
+                days, inventory.orders, inventory.order_limit
+            ));
+        }
+        if i64::from(inventory.order_count) != inventory.orders {
+            problems.push(format!(
+                "{} days order_count is {}, but there are {} orders",
+                days, inventory.order_count, inventory.orders
+            ));
+        }
+        let reserved = reserved.get(&days).copied().unwrap_or(0);
+        if let Some(before) = before.iter().find(|b| b.duration_days == days) {
+            let left = i64::from(before.order_limit) - before.orders;
+            if reserved as i64 > left {
+                problems.push(format!(
+                    "{} days: {} reserved, but only {} were left",
+                    days, reserved, left
+                ));
+            }
+        }
+    }
+    problems
+}
+
+pub fn print(before: &[Inventory], after: &[Inventory], reserved: &BTreeMap<i32, u64>) {
+    println!(
+        "{:<10}{:>8}{:>8}{:>10}{:>8}{:>13}",
+        "duration", "limit", "before", "reserved", "after", "order_count"
+    );
+    for inventory in after {
+        let days = inventory.duration_days;
+        let before = before
+            .iter()
+            .find(|b| b.duration_days == days)
+            .map_or("-".to_string(), |b| b.orders.to_string());
+        println!(
+            "{:<10}{:>8}{:>8}{:>10}{:>8}{:>13}",
+            format!("{} days", days),
+            inventory.order_limit,
+            before,
+            reserved.get(&days).copied().unwrap_or(0),
+            inventory.orders,
+            inventory.order_count
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inventory(order_limit: i32, order_count: i32, orders: i64) -> Inventory {
+        Inventory {
+            duration_days: 3,
+            order_limit,
+            order_count,
+            orders,
+        }
+    }
+
+    #[test]
+    fn check_for_overselling() {
+        let before = [inventory(300, 100, 100)];
+        let reserved = BTreeMap::from([(3, 200)]);
+        assert!(check(&before, &[inventory(300, 300, 300)], &reserved).is_empty());
+
+        let problems = check(&before, &[inventory(300, 301, 301)], &reserved);
+        assert_eq!(
+            problems,
+            vec!["3 days oversold: 301 orders for a limit of 300"]
+        );
+
+        let problems = check(&before, &[inventory(300, 298, 300)], &reserved);
+        assert_eq!(
+            problems,
+            vec!["3 days order_count is 298, but there are 300 orders"]
+        );
+
+        let reserved = BTreeMap::from([(3, 201)]);
+        let problems = check(&before, &[inventory(300, 300, 300)], &reserved);
+        assert_eq!(
+            problems,
+            vec!["3 days: 201 reserved, but only 200 were left"]
+        );
+    }
+}
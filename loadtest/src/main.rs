@@ -0,0 +1,155 @@
+//! Reproduces a ticket launch against a running server: virtual users reserve tickets all at
+//! once, some go on to purchase, and clients stream order stats throughout. Reports throughput,
+//! latency and errors for each request, then checks nothing was oversold.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use sqlx::PgPool;
+use tokio::sync::{watch, Semaphore};
+
+mod api;
+mod inventory;
+mod report;
+mod scenario;
+mod subscribers;
+
+use api::{Api, UserInfo};
+use report::{Op, Report};
+use scenario::{Scenario, USAGE};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let scenario = Arc::new(Scenario::parse(&args).map_err(|e| format!("{}\n{}", e, USAGE))?);
+
+    // The database is only read, to check for overselling
+    let pool = match std::env::var("DATABASE_URL") {
+        Ok(url) if !url.is_empty() => Some(PgPool::connect(&url).await?),
+        _ => {
+            println!("DATABASE_URL not set, only order stats are checked for overselling");
+            None
+        }
+    };
+    let before = match &pool {
+        Some(pool) => inventory::read(pool).await?,
+        None => vec![],
+    };
+
+    let api = Api::connect(&scenario).await?;
+    let (stop, stopped) = watch::channel(false);
+    let subscribers: Vec<_> = (0..scenario.subscribers)
+        .map(|_| {
+            tokio::spawn(subscribers::subscribe(
+                scenario.grpc_url.clone(),
+                stopped.clone(),
+            ))
+        })
+        .collect();
+
+    println!(
+        "{} users reserving {} over {:?}, {} at once, {:.0}% purchasing, {} stats subscribers",
+        scenario.users,
+        scenario.ticket_type,
+        scenario.api,
+        scenario.concurrency,
+        scenario.purchase_ratio * 100.0,
+        scenario.subscribers
+    );
+    let run_id = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    let permits = Arc::new(Semaphore::new(scenario.concurrency));
+    let started = Instant::now();
+    let users: Vec<_> = (0..scenario.users)
+        .map(|user| {
+            tokio::spawn(virtual_user(
+                api.clone(),
+                scenario.clone(),
+                user,
+                run_id,
+                permits.clone(),
+            ))
+        })
+        .collect();
+    let mut report = Report::default();
+    for user in users {
+        report.merge(user.await?);
+    }
+    let elapsed = started.elapsed();
+
+    stop.send(true)?;
+    let mut seen = vec![];
+    for subscriber in subscribers {
+        seen.push(subscriber.await?);
+    }
+
+    println!();
+    report.print(elapsed);
+    println!();
+    let mut oversold = subscribers::print(&seen);
+    if let Some(pool) = &pool {
+        let after = inventory::read(pool).await?;
+        println!();
+        inventory::print(&before, &after, &report.reserved);
+        for problem in inventory::check(&before, &after, &report.reserved) {
+            println!("  {}", problem);
+            oversold = true;
+        }
+    }
+
+    if oversold {
+        return Err("oversell checks failed".into());
+    }
+    println!();
+    println!("oversell checks passed");
+    Ok(())
+}
+
+/// Reserve a ticket, and purchase it if the scenario says this user does
+async fn virtual_user(
+    mut api: Api,
+    scenario: Arc<Scenario>,
+    user: usize,
+    run_id: u64,
+    permits: Arc<Semaphore>,
+) -> Report {
+    let _permit = permits
+        .acquire_owned()
+        .await
+        .expect("semaphore is never closed");
+    let mut report = Report::default();
+
+    let duration = scenario.duration(user);
+    let (elapsed, res) = timed(api.add_ticket_to_basket(&scenario.ticket_type, duration)).await;
+    report.record(Op::AddTicketToBasket, elapsed, &res);
+    let Ok(order_id) = res else {
+        return report;
+    };
+    *report.reserved.entry(duration).or_default() += 1;
+    if !scenario.purchases(user) {
+        return report;
+    }
+
+    // A distinct email and address for each user and run, so purchase limits aren't hit
+    let user_info = UserInfo {
+        name: format!("Load Test {}", user),
+        email: format!("loadtest-{}-{}@example.com", run_id, user),
+        address: format!("{} Test Street, Run {}", user, run_id),
+    };
+    let (elapsed, res) = timed(api.add_user_info(&order_id, &user_info)).await;
+    report.record(Op::AddUserInfo, elapsed, &res);
+    if res.is_err() {
+        return report;
+    }
+
+    let (elapsed, res) = timed(api.purchase_order(&order_id)).await;
+    report.record(Op::PurchaseOrder, elapsed, &res);
+    report
+}
+
+async fn timed<T>(request: impl std::future::Future<Output = T>) -> (Duration, T) {
+    let started = Instant::now();
+    let res = request.await;
+    (started.elapsed(), res)
+}
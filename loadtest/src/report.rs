@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Requests virtual users make, in order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Op {
+    AddTicketToBasket,
+    AddUserInfo,
+    PurchaseOrder,
+}
+
+impl Op {
+    fn name(&self) -> &'static str {
+        match self {
+            Op::AddTicketToBasket => "add_ticket_to_basket",
+            Op::AddUserInfo => "add_user_info",
+            Op::PurchaseOrder => "purchase_order",
+        }
+    }
+}
+
+/// A failed request, by gRPC code or HTTP status
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: String,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(kind: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            kind: kind.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Outcome of every request, merged from each virtual user
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Latency of each successful request
+    latencies: BTreeMap<Op, Vec<Duration>>,
+    /// Count of failures by request and kind, with the first message
+    errors: BTreeMap<(Op, String), (u64, String)>,
+    /// Successful reservations by duration
+    pub reserved: BTreeMap<i32, u64>,
+}
+
+impl Report {
+    pub fn record<T>(&mut self, op: Op, elapsed: Duration, result: &Result<T, Failure>) {
+        match result {
+            Ok(_) => self.latencies.entry(op).or_default().push(elapsed),
+            Err(failure) => {
+                self.errors
+                    .entry((op, failure.kind.clone()))
+                    .or_insert_with(|| (0, failure.message.clone()))
+                    .0 += 1
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: Report) {
+        for (op, latencies) in other.latencies {
+            self.latencies.entry(op).or_default().extend(latencies);
+        }
+        for (key, (count, message)) in other.errors {
+            self.errors.entry(key).or_insert((0, message)).0 += count;
+        }
+        for (duration, count) in other.reserved {
+            *self.reserved.entry(duration).or_default() += count;
+        }
+    }
+
+    /// Print throughput and latency percentiles of each request, and the errors
+    pub fn print(&mut self, elapsed: Duration) {
+        let total: usize = self.latencies.values().map(Vec::len).sum::<usize>()
+            + self
+                .errors
+                .values()
+                .map(|(count, _)| *count as usize)
+                .sum::<usize>();
+        println!(
+            "{} requests in {:.2}s, {:.0} req/s",
+            total,
+            elapsed.as_secs_f64(),
+            total as f64 / elapsed.as_secs_f64()
+        );
+        println!();
+        println!(
+            "{:<22}{:>8}{:>8}{:>9}{:>9}{:>9}{:>9}{:>9}",
+            "request", "ok", "errors", "ok/s", "p50", "p90", "p99", "max"
+        );
+        for op in [Op::AddTicketToBasket, Op::AddUserInfo, Op::PurchaseOrder] {
+            let latencies = self.latencies.entry(op).or_default();
+            latencies.sort();
+            let errors: u64 = self
+                .errors
+                .iter()
+                .filter(|((o, _), _)| *o == op)
+                .map(|(_, (count, _))| count)
+                .sum();
+            let latencies = &self.latencies[&op];
+            println!(
+                "{:<22}{:>8}{:>8}{:>9.0}{:>9}{:>9}{:>9}{:>9}",
+                op.name(),
+                latencies.len(),
+                errors,
+                latencies.len() as f64 / elapsed.as_secs_f64(),
+                format_ms(percentile(latencies, 0.5)),
+                format_ms(percentile(latencies, 0.9)),
+                format_ms(percentile(latencies, 0.99)),
+                format_ms(latencies.last().copied()),
+            );
+        }
+
+        if !self.errors.is_empty() {
+            println!();
+            println!("errors:");
+            for ((op, kind), (count, message)) in &self.errors {
+                println!("  {:<22}{:<24}{:>8}  {}", op.name(), kind, count, message);
+            }
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted latencies
+fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+fn format_ms(value: Option<Duration>) -> String {
+    match value {
+        Some(value) => format!("{:.1}ms", value.as_secs_f64() * 1000.0),
+        None => "-".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let sorted: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sorted, 0.5), Some(Duration::from_millis(50)));
+        assert_eq!(percentile(&sorted, 0.99), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&sorted, 1.0), Some(Duration::from_millis(100)));
+        assert_eq!(
+            percentile(&sorted[..1], 0.5),
+            Some(Duration::from_millis(1))
+        );
+        assert_eq!(percentile(&[], 0.5), None);
+    }
+
+    #[test]
+    fn merge_reports() {
+        let mut report = Report::default();
+        report.record(Op::AddTicketToBasket, Duration::from_millis(5), &Ok(()));
+        let mut other = Report::default();
+        other.record::<()>(
+            Op::AddTicketToBasket,
+            Duration::from_millis(1),
+            &Err(Failure::new(
+                "FailedPrecondition",
+                "ticket chalet3/3 sold out",
+            )),
+        );
+        other.record::<()>(
+            Op::AddTicketToBasket,
+            Duration::from_millis(1),
+            &Err(Failure::new(
+                "FailedPrecondition",
+                "ticket chalet3/4 sold out",
+            )),
+        );
+        report.merge(other);
+
+        assert_eq!(report.latencies[&Op::AddTicketToBasket].len(), 1);
+        assert_eq!(
+            report.errors[&(Op::AddTicketToBasket, "FailedPrecondition".to_string())],
+            (2, "ticket chalet3/3 sold out".to_string())
+        );
+    }
+}
@@ -0,0 +1,151 @@
+pub const USAGE: &str = "usage: loadtest [--api grpc|rest] [--grpc-url <url>] [--rest-url <url>] \
+[--users <n>] [--concurrency <n>] [--purchase-ratio <0-1>] [--subscribers <n>] \
+[--ticket-type <id>] [--durations <days,...>]";
+
+/// Which API virtual users call. Stats subscribers always use gRPC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiKind {
+    Grpc,
+    Rest,
+}
+
+/// A ticket launch: virtual users all arriving at once to reserve a ticket, some of them going on
+/// to buy it, while clients stream order stats
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scenario {
+    pub api: ApiKind,
+    pub grpc_url: String,
+    pub rest_url: String,
+    pub users: usize,
+    /// Virtual users with a request in flight at once
+    pub concurrency: usize,
+    /// Fraction of users who add their details and purchase, after reserving
+    pub purchase_ratio: f64,
+    /// Clients streaming `GetOrderStats` throughout
+    pub subscribers: usize,
+    pub ticket_type: String,
+    /// Spread evenly across users
+    pub durations: Vec<i32>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            api: ApiKind::Grpc,
+            grpc_url: "http://localhost:50051".to_string(),
+            rest_url: "http://localhost:8080".to_string(),
+            users: 1000,
+            concurrency: 100,
+            purchase_ratio: 0.3,
+            subscribers: 10,
+            ticket_type: "chalet3".to_string(),
+            durations: vec![3, 4],
+        }
+    }
+}
+
+impl Scenario {
+    /// Parse the process arguments, starting from the default scenario
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut scenario = Self::default();
+
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = match flag.as_str() {
+                flag if flag.starts_with("--") => args
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", flag))?,
+                value => return Err(format!("unexpected argument {}", value)),
+            };
+            match flag.as_str() {
+                "--api" => {
+                    scenario.api = match value.as_str() {
+                        "grpc" => ApiKind::Grpc,
+                        "rest" => ApiKind::Rest,
+                        _ => return Err(format!("unknown api {}", value)),
+                    }
+                }
+                "--grpc-url" => scenario.grpc_url = value.clone(),
+                "--rest-url" => scenario.rest_url = value.clone(),
+                "--users" => scenario.users = parse(flag, value)?,
+                "--concurrency" => scenario.concurrency = parse(flag, value)?,
+                "--purchase-ratio" => scenario.purchase_ratio = parse(flag, value)?,
+                "--subscribers" => scenario.subscribers = parse(flag, value)?,
+                "--ticket-type" => scenario.ticket_type = value.clone(),
+                "--durations" => {
+                    scenario.durations = value
+                        .split(',')
+                        .map(|d| parse(flag, d.trim()))
+                        .collect::<Result<_, _>>()?
+                }
+                _ => return Err(format!("unknown flag {}", flag)),
+            }
+        }
+
+        if scenario.concurrency == 0 {
+            return Err("--concurrency must be positive".to_string());
+        }
+        if !(0.0..=1.0).contains(&scenario.purchase_ratio) {
+            return Err("--purchase-ratio must be between 0 and 1".to_string());
+        }
+        if scenario.durations.is_empty() {
+            return Err("--durations needs at least one duration".to_string());
+        }
+        Ok(scenario)
+    }
+
+    /// Duration `user` reserves
+    pub fn duration(&self, user: usize) -> i32 {
+        self.durations[user % self.durations.len()]
+    }
+
+    /// Whether `user` goes on to purchase, spread so `purchase_ratio` of users do
+    pub fn purchases(&self, user: usize) -> bool {
+        let before = (user as f64 * self.purchase_ratio).floor();
+        ((user + 1) as f64 * self.purchase_ratio).floor() > before
+    }
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", flag, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_scenario() {
+        assert_eq!(Scenario::parse(&args(&[])), Ok(Scenario::default()));
+
+        let scenario = Scenario::parse(&args(&[
+            "--api",
+            "rest",
+            "--users",
+            "50",
+            "--purchase-ratio",
+            "0.5",
+            "--durations",
+            "4",
+        ]))
+        .unwrap();
+        assert_eq!(scenario.api, ApiKind::Rest);
+        assert_eq!(scenario.users, 50);
+        assert_eq!(scenario.durations, vec![4]);
+        assert_eq!((0..50).filter(|u| scenario.purchases(*u)).count(), 25);
+
+        assert!(Scenario::parse(&args(&["--users"])).is_err());
+        assert!(Scenario::parse(&args(&["--users", "lots"])).is_err());
+        assert!(Scenario::parse(&args(&["--api", "soap"])).is_err());
+        assert!(Scenario::parse(&args(&["--purchase-ratio", "2"])).is_err());
+        assert!(Scenario::parse(&args(&["--concurrency", "0"])).is_err());
+        assert!(Scenario::parse(&args(&["--force", "1"])).is_err());
+        assert!(Scenario::parse(&args(&["users"])).is_err());
+    }
+}
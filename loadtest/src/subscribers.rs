@@ -0,0 +1,74 @@
+use festival_tickets_tonic::pb;
+use tokio::sync::watch;
+use tokio_stream::StreamExt;
+
+use crate::api::connect_grpc;
+use crate::report::Failure;
+
+/// Order stats updates a subscriber received, until the launch finished or its stream failed
+#[derive(Debug, Default)]
+pub struct Seen {
+    pub updates: u64,
+    /// Updates showing more orders than the limit
+    pub over_limit: u64,
+    pub failure: Option<Failure>,
+}
+
+/// Stream `GetOrderStats` from `url` until `stop` changes
+pub async fn subscribe(url: String, mut stop: watch::Receiver<bool>) -> Seen {
+    let mut seen = Seen::default();
+    let mut client = match connect_grpc(&url).await {
+        Ok(client) => client,
+        Err(e) => {
+            seen.failure = Some(Failure::new("connect", e.to_string()));
+            return seen;
+        }
+    };
+    let mut stream = match client.get_order_stats(pb::GetOrderStatsRequest {}).await {
+        Ok(res) => res.into_inner(),
+        Err(status) => {
+            seen.failure = Some(status.into());
+            return seen;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = stop.changed() => return seen,
+            update = stream.next() => match update {
+                Some(Ok(stats)) => {
+                    seen.updates += 1;
+                    if stats.order_count > stats.order_limit {
+                        seen.over_limit += 1;
+                    }
+                }
+                Some(Err(status)) => {
+                    seen.failure = Some(status.into());
+                    return seen;
+                }
+                None => {
+                    seen.failure = Some(Failure::new("ended", "stream ended early"));
+                    return seen;
+                }
+            }
+        }
+    }
+}
+
+/// Print what subscribers saw. Returns whether any saw a duration oversold.
+pub fn print(seen: &[Seen]) -> bool {
+    let failed: Vec<&Failure> = seen.iter().filter_map(|s| s.failure.as_ref()).collect();
+    let updates: u64 = seen.iter().map(|s| s.updates).sum();
+    let over_limit: u64 = seen.iter().map(|s| s.over_limit).sum();
+    println!(
+        "stats subscribers: {}, {} updates received, {} failed, {} updates over the limit",
+        seen.len(),
+        updates,
+        failed.len(),
+        over_limit
+    );
+    for failure in failed {
+        println!("  {}: {}", failure.kind, failure.message);
+    }
+    over_limit > 0
+}
@@ -0,0 +1,16 @@
+DROP TRIGGER IF EXISTS release_promo_code ON orders;
+DROP FUNCTION IF EXISTS release_promo_code_on_delete;
+
+ALTER TABLE orders
+DROP CONSTRAINT IF EXISTS fk_promo_code;
+
+ALTER TABLE orders
+DROP COLUMN IF EXISTS promo_code;
+
+ALTER TABLE orders
+DROP COLUMN IF EXISTS discount;
+
+ALTER TABLE orders
+DROP COLUMN IF EXISTS base_price;
+
+DROP TABLE IF EXISTS promo_codes;
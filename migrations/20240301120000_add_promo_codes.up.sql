@@ -0,0 +1,51 @@
+-- Promo codes, which can be applied to a reserved order for a discount
+CREATE TABLE promo_codes (
+    code varchar PRIMARY KEY,
+    discount_kind varchar NOT NULL CHECK (discount_kind IN ('percentage', 'fixed')),
+    discount_value real NOT NULL CHECK (discount_value >= 0),
+    -- NULL means the code is valid for any ticket type/duration
+    ticket_types varchar[],
+    durations integer[],
+    max_uses integer,
+    use_count integer NOT NULL DEFAULT 0,
+    per_customer_limit integer,
+    valid_from timestamp with time zone,
+    valid_until timestamp with time zone,
+
+    CONSTRAINT use_count_within_max_uses
+        CHECK (max_uses IS NULL OR use_count <= max_uses)
+);
+
+-- Price is no longer hard-coded in queries: price = base_price - discount
+ALTER TABLE orders
+ADD base_price real NOT NULL DEFAULT 44.0;
+
+ALTER TABLE orders
+ADD discount real NOT NULL DEFAULT 0;
+
+ALTER TABLE orders
+ADD promo_code varchar;
+
+ALTER TABLE orders
+ADD CONSTRAINT fk_promo_code
+FOREIGN KEY (promo_code) REFERENCES promo_codes(code);
+
+-- Give the use back to the promo code when a reserved order expires
+CREATE FUNCTION release_promo_code_on_delete()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+BEGIN
+    IF OLD.promo_code IS NOT NULL AND OLD.purchased_at IS NULL THEN
+        UPDATE promo_codes
+        SET use_count = use_count - 1
+        WHERE code = OLD.promo_code;
+    END IF;
+
+    RETURN OLD;
+END;
+$$;
+
+CREATE TRIGGER release_promo_code
+BEFORE DELETE ON orders
+FOR EACH ROW EXECUTE FUNCTION release_promo_code_on_delete();
@@ -0,0 +1,13 @@
+DROP TRIGGER IF EXISTS release_presale_code ON orders;
+DROP FUNCTION IF EXISTS release_presale_code_on_delete;
+
+DROP INDEX IF EXISTS orders_presale_code;
+
+ALTER TABLE orders
+DROP CONSTRAINT IF EXISTS fk_presale_code;
+
+ALTER TABLE orders
+DROP COLUMN IF EXISTS presale_code;
+
+DROP TABLE IF EXISTS presale_codes;
+DROP TABLE IF EXISTS presales;
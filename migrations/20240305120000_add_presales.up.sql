@@ -0,0 +1,56 @@
+-- Presale windows. While a presale is running, tickets can only be added to a basket
+-- with a valid code for that presale
+CREATE TABLE presales (
+    id varchar PRIMARY KEY,
+    starts_at timestamp with time zone NOT NULL,
+    ends_at timestamp with time zone NOT NULL,
+
+    CONSTRAINT presale_window CHECK (starts_at < ends_at)
+);
+
+-- Codes are stored lower-case. Allow-listed emails are imported as codes with the email
+-- set, and the order's user info must then match it.
+CREATE TABLE presale_codes (
+    code varchar PRIMARY KEY,
+    presale_id varchar NOT NULL,
+    email text,
+    ticket_limit integer NOT NULL CHECK (ticket_limit > 0),
+    redeemed integer NOT NULL DEFAULT 0,
+
+    CONSTRAINT fk_presale_id
+        FOREIGN KEY (presale_id)
+            REFERENCES presales(id)
+            ON DELETE CASCADE,
+    CONSTRAINT redeemed_within_ticket_limit CHECK (redeemed <= ticket_limit)
+);
+
+CREATE INDEX presale_codes_presale_id ON presale_codes (presale_id);
+
+ALTER TABLE orders
+ADD presale_code varchar;
+
+ALTER TABLE orders
+ADD CONSTRAINT fk_presale_code
+FOREIGN KEY (presale_code) REFERENCES presale_codes(code);
+
+CREATE INDEX orders_presale_code ON orders (presale_code);
+
+-- Give the ticket back to the presale code when a reserved order expires
+CREATE FUNCTION release_presale_code_on_delete()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+BEGIN
+    IF OLD.presale_code IS NOT NULL AND OLD.purchased_at IS NULL THEN
+        UPDATE presale_codes
+        SET redeemed = redeemed - 1
+        WHERE code = OLD.presale_code;
+    END IF;
+
+    RETURN OLD;
+END;
+$$;
+
+CREATE TRIGGER release_presale_code
+BEFORE DELETE ON orders
+FOR EACH ROW EXECUTE FUNCTION release_presale_code_on_delete();
@@ -0,0 +1,11 @@
+DROP TABLE IF EXISTS purchase_limit_overrides;
+
+DROP INDEX IF EXISTS orders_user_id;
+DROP INDEX IF EXISTS users_address_normalised;
+DROP INDEX IF EXISTS users_email_normalised;
+
+ALTER TABLE users
+DROP COLUMN IF EXISTS address_normalised;
+
+ALTER TABLE users
+DROP COLUMN IF EXISTS email_normalised;
@@ -0,0 +1,29 @@
+-- Normalised user details, used to enforce per-customer purchase limits
+ALTER TABLE users
+ADD email_normalised text;
+
+ALTER TABLE users
+ADD address_normalised text;
+
+UPDATE users
+SET email_normalised = lower(trim(email)),
+    address_normalised = lower(trim(address));
+
+ALTER TABLE users
+ALTER COLUMN email_normalised SET NOT NULL;
+
+ALTER TABLE users
+ALTER COLUMN address_normalised SET NOT NULL;
+
+CREATE INDEX users_email_normalised ON users (email_normalised);
+CREATE INDEX users_address_normalised ON users (address_normalised);
+
+CREATE INDEX orders_user_id ON orders (user_id);
+
+-- Per-customer limit overrides for group/bulk bookers, keyed by normalised email
+CREATE TABLE purchase_limit_overrides (
+    email_normalised text PRIMARY KEY,
+    ticket_limit integer NOT NULL CHECK (ticket_limit >= 0),
+    note text,
+    updated_at timestamp with time zone NOT NULL DEFAULT now()
+);
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS tickets;
+
+ALTER TABLE orders
+DROP CONSTRAINT IF EXISTS orders_pkey;
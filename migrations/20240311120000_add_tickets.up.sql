@@ -0,0 +1,9 @@
+-- Tickets reference orders, which needs a primary key
+ALTER TABLE orders ADD PRIMARY KEY (id);
+
+-- Signed credential issued for each purchased order, presented at the gate
+CREATE TABLE tickets (
+    order_id uuid PRIMARY KEY REFERENCES orders(id) ON DELETE CASCADE,
+    credential text NOT NULL,
+    issued_at timestamp with time zone NOT NULL DEFAULT now()
+);
@@ -0,0 +1,17 @@
+-- Gate scans of tickets
+CREATE TABLE check_ins (
+    id bigserial PRIMARY KEY,
+    order_id uuid NOT NULL REFERENCES tickets(order_id) ON DELETE CASCADE,
+    -- Day of the festival the scan was made on, from 1
+    festival_day integer NOT NULL,
+    direction varchar NOT NULL CHECK (direction IN ('entry', 'exit')),
+    gate varchar NOT NULL,
+    scanned_at timestamp with time zone NOT NULL,
+    synced_at timestamp with time zone NOT NULL DEFAULT now(),
+    -- Set by offline scanners, so syncing the same scans twice is a no-op
+    client_ref uuid UNIQUE,
+    -- Why an offline scan broke the entry rules. Conflicting scans are ignored by later checks.
+    conflict text
+);
+
+CREATE INDEX check_ins_order_id ON check_ins(order_id, festival_day, scanned_at);
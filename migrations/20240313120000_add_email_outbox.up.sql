@@ -0,0 +1,19 @@
+-- Emails are queued in the same transaction as the change they're about, and delivered by a
+-- background worker, so a crash can't lose them
+CREATE TABLE email_outbox (
+    id bigserial PRIMARY KEY,
+    -- Identifies the event an email is about, so it's only queued once
+    dedupe_key varchar NOT NULL UNIQUE,
+    template varchar NOT NULL,
+    recipient text NOT NULL,
+    subject text NOT NULL,
+    body text NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    -- Pushed back while an email is being sent, and after failures
+    send_after timestamp with time zone NOT NULL DEFAULT now(),
+    attempts integer NOT NULL DEFAULT 0,
+    last_error text,
+    sent_at timestamp with time zone
+);
+
+CREATE INDEX email_outbox_pending ON email_outbox(send_after) WHERE sent_at IS NULL;
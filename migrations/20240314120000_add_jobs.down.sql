@@ -0,0 +1,8 @@
+ALTER TABLE email_outbox
+ADD send_after timestamp with time zone NOT NULL DEFAULT now(),
+ADD attempts integer NOT NULL DEFAULT 0,
+ADD last_error text;
+
+CREATE INDEX email_outbox_pending ON email_outbox(send_after) WHERE sent_at IS NULL;
+
+DROP TABLE IF EXISTS jobs;
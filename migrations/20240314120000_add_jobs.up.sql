@@ -0,0 +1,36 @@
+-- Background jobs, claimed by workers in any server process with FOR UPDATE SKIP LOCKED
+CREATE TABLE jobs (
+    id bigserial PRIMARY KEY,
+    kind varchar NOT NULL,
+    payload jsonb,
+    state varchar NOT NULL DEFAULT 'pending'
+        CHECK (state IN ('pending', 'running', 'succeeded', 'dead')),
+    -- Jobs with a key are only queued once while pending or running
+    dedupe_key varchar,
+    -- Recurring jobs are run again this long after they succeed
+    repeat_secs integer CHECK (repeat_secs > 0),
+    run_after timestamp with time zone NOT NULL DEFAULT now(),
+    -- Running jobs are claimed again once their lock expires, in case their worker died
+    locked_until timestamp with time zone,
+    attempts integer NOT NULL DEFAULT 0,
+    max_attempts integer NOT NULL DEFAULT 10,
+    last_error text,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    updated_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX jobs_dedupe_key ON jobs(dedupe_key) WHERE state IN ('pending', 'running');
+CREATE INDEX jobs_runnable ON jobs(run_after) WHERE state IN ('pending', 'running');
+
+-- Email retries are handled by send_email jobs
+INSERT INTO jobs (kind, payload)
+SELECT 'send_email', jsonb_build_object('outbox_id', id)
+FROM email_outbox
+WHERE sent_at IS NULL
+ORDER BY id;
+
+DROP INDEX email_outbox_pending;
+ALTER TABLE email_outbox
+DROP COLUMN send_after,
+DROP COLUMN attempts,
+DROP COLUMN last_error;
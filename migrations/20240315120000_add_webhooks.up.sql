@@ -0,0 +1,28 @@
+-- Endpoints receiving signed order lifecycle events
+CREATE TABLE webhook_endpoints (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    url text NOT NULL,
+    -- Key for the HMAC-SHA256 signature sent with each delivery
+    secret text NOT NULL,
+    -- Event types the endpoint receives, i.e. order.purchased
+    events text[] NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+-- Each event sent to each endpoint, and the outcome of the last attempt
+CREATE TABLE webhook_deliveries (
+    id bigserial PRIMARY KEY,
+    endpoint_id uuid NOT NULL REFERENCES webhook_endpoints(id) ON DELETE CASCADE,
+    -- Same for every endpoint receiving the event, so receivers can drop duplicates
+    event_id uuid NOT NULL,
+    event varchar NOT NULL,
+    payload text NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    attempts integer NOT NULL DEFAULT 0,
+    response_status integer,
+    last_error text,
+    delivered_at timestamp with time zone,
+    UNIQUE (endpoint_id, event_id)
+);
+
+CREATE INDEX webhook_deliveries_endpoint ON webhook_deliveries(endpoint_id, created_at);
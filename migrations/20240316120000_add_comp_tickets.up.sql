@@ -0,0 +1,4 @@
+-- Complimentary tickets for artists, crew and sponsors are purchased, zero-price orders.
+-- The allocation they were issued from, i.e. crew, is NULL for orders placed by customers.
+ALTER TABLE orders
+ADD comp_allocation text;
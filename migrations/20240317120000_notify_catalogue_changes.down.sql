@@ -0,0 +1,3 @@
+DROP TRIGGER notify_order_limits_change ON order_stats;
+DROP TRIGGER notify_ticket_types_change ON ticket_types;
+DROP FUNCTION notify_catalogue_change;
@@ -0,0 +1,19 @@
+-- Let instances drop cached ticket types and availability when the catalogue changes. Only
+-- changes to limits notify, not the order counts updated by each reservation
+CREATE FUNCTION notify_catalogue_change()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+BEGIN
+    PERFORM pg_notify('catalogue', TG_TABLE_NAME);
+    RETURN NULL;
+END;
+$$;
+
+CREATE TRIGGER notify_ticket_types_change
+AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON ticket_types
+FOR EACH STATEMENT EXECUTE FUNCTION notify_catalogue_change();
+
+CREATE TRIGGER notify_order_limits_change
+AFTER INSERT OR DELETE OR UPDATE OF duration_days, order_limit OR TRUNCATE ON order_stats
+FOR EACH STATEMENT EXECUTE FUNCTION notify_catalogue_change();
@@ -0,0 +1,41 @@
+CREATE OR REPLACE FUNCTION update_order_stats()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+DECLARE
+    cur_order_limit order_stats.order_limit%type;
+    cur_order_count order_stats.order_count%type;
+BEGIN
+    SELECT os.order_limit, os.order_count
+    FROM order_stats as os
+    WHERE os.duration_days = NEW.duration_days
+    INTO cur_order_limit, cur_order_count;
+
+    if NEW.duration_days = 3 OR NEW.duration_days = 4 THEN
+        IF cur_order_count >= cur_order_limit THEN
+            RAISE EXCEPTION 'Order limit = % reached for duration_days = %', cur_order_limit, NEW.duration_days;
+        END IF;
+
+        UPDATE order_stats
+        SET order_count = order_count + 1
+        WHERE duration_days = NEW.duration_days;
+    ELSE
+        RAISE EXCEPTION 'Invalid duration_days = %. Should be 3 or 4.', NEW.duration_days;
+    END IF;
+
+    RETURN NEW;
+END;
+$$;
+
+CREATE OR REPLACE FUNCTION subtract_order_count_on_delete()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+BEGIN
+    UPDATE order_stats
+    SET order_count = order_count - 1
+    WHERE duration_days = NEW.duration_days;
+
+    RETURN OLD;
+END;
+$$;
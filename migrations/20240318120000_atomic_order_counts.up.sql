@@ -0,0 +1,40 @@
+-- Check the limit and count the order in one statement. Reading the count first let concurrent
+-- reservations all see the last ticket left, and oversell
+CREATE OR REPLACE FUNCTION update_order_stats()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+BEGIN
+    IF NEW.duration_days IS NULL OR NEW.duration_days NOT IN (3, 4) THEN
+        RAISE EXCEPTION 'Invalid duration_days = %. Should be 3 or 4.', NEW.duration_days;
+    END IF;
+
+    UPDATE order_stats
+    SET order_count = coalesce(order_count, 0) + 1
+    WHERE duration_days = NEW.duration_days AND coalesce(order_count, 0) < order_limit;
+
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'Order limit reached for duration_days = %', NEW.duration_days;
+    END IF;
+
+    RETURN NEW;
+END;
+$$;
+
+-- NEW is null when deleting, so expired orders never gave their tickets back
+CREATE OR REPLACE FUNCTION subtract_order_count_on_delete()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+BEGIN
+    UPDATE order_stats
+    SET order_count = order_count - 1
+    WHERE duration_days = OLD.duration_days;
+
+    RETURN OLD;
+END;
+$$;
+
+-- Recount, as counts were never decremented
+UPDATE order_stats AS os
+SET order_count = (SELECT count(*) FROM orders AS o WHERE o.duration_days = os.duration_days);
@@ -0,0 +1,20 @@
+CREATE OR REPLACE FUNCTION update_order_stats()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+BEGIN
+    IF NEW.duration_days IS NULL OR NEW.duration_days NOT IN (3, 4) THEN
+        RAISE EXCEPTION 'Invalid duration_days = %. Should be 3 or 4.', NEW.duration_days;
+    END IF;
+
+    UPDATE order_stats
+    SET order_count = coalesce(order_count, 0) + 1
+    WHERE duration_days = NEW.duration_days AND coalesce(order_count, 0) < order_limit;
+
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'Order limit reached for duration_days = %', NEW.duration_days;
+    END IF;
+
+    RETURN NEW;
+END;
+$$;
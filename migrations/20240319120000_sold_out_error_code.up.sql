@@ -0,0 +1,23 @@
+-- Raise a sold out reservation with its own SQLSTATE, so it isn't reported as an internal error.
+-- Must match `SOLD_OUT` in core/src/error.rs
+CREATE OR REPLACE FUNCTION update_order_stats()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+BEGIN
+    IF NEW.duration_days IS NULL OR NEW.duration_days NOT IN (3, 4) THEN
+        RAISE EXCEPTION 'Invalid duration_days = %. Should be 3 or 4.', NEW.duration_days;
+    END IF;
+
+    UPDATE order_stats
+    SET order_count = coalesce(order_count, 0) + 1
+    WHERE duration_days = NEW.duration_days AND coalesce(order_count, 0) < order_limit;
+
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'ticket %/% sold out', NEW.ticket_type, NEW.duration_days
+            USING ERRCODE = 'FT001';
+    END IF;
+
+    RETURN NEW;
+END;
+$$;
@@ -0,0 +1 @@
+DELETE FROM jobs WHERE kind = 'normalise_users';
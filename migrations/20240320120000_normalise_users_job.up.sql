@@ -0,0 +1,4 @@
+-- Users from before purchase limits were backfilled with only lower(trim()), so older orders
+-- didn't count against the same customer as newer ones. Normalise them as new users are, with
+-- `limits::normalise_email` and `limits::normalise_address`, in a one-off job
+INSERT INTO jobs (kind, dedupe_key) VALUES ('normalise_users', 'normalise_users');
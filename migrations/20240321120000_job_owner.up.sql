@@ -0,0 +1,3 @@
+-- The worker running a job, so a worker whose lock expired can't finish the job after another
+-- worker has claimed it
+ALTER TABLE jobs ADD locked_by uuid;
@@ -0,0 +1,50 @@
+CREATE OR REPLACE FUNCTION release_presale_code_on_delete()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+BEGIN
+    IF OLD.presale_code IS NOT NULL AND OLD.purchased_at IS NULL THEN
+        UPDATE presale_codes
+        SET redeemed = redeemed - 1
+        WHERE code = OLD.presale_code;
+    END IF;
+
+    RETURN OLD;
+END;
+$$;
+
+DROP INDEX IF EXISTS orders_presale_email;
+DROP INDEX IF EXISTS orders_presale_code;
+
+ALTER TABLE orders
+DROP CONSTRAINT IF EXISTS presale_redeemed,
+DROP CONSTRAINT IF EXISTS presale_code_or_email,
+DROP CONSTRAINT IF EXISTS fk_presale_email,
+DROP CONSTRAINT IF EXISTS fk_presale_code;
+
+-- Fails if the same code is used by more than one presale, or as an allow-listed email
+ALTER TABLE presale_codes
+DROP CONSTRAINT presale_codes_pkey,
+ADD PRIMARY KEY (code);
+
+CREATE INDEX presale_codes_presale_id ON presale_codes (presale_id);
+
+INSERT INTO presale_codes (code, presale_id, email, ticket_limit, redeemed)
+SELECT email, presale_id, email, ticket_limit, redeemed
+FROM presale_allow_list;
+
+UPDATE orders
+SET presale_code = presale_email
+WHERE presale_email IS NOT NULL;
+
+ALTER TABLE orders
+DROP COLUMN presale_email,
+DROP COLUMN presale_id;
+
+DROP TABLE presale_allow_list;
+
+ALTER TABLE orders
+ADD CONSTRAINT fk_presale_code
+FOREIGN KEY (presale_code) REFERENCES presale_codes(code);
+
+CREATE INDEX orders_presale_code ON orders (presale_code);
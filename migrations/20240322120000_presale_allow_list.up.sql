@@ -0,0 +1,86 @@
+-- Codes were unique across every presale, and allow-listed emails were imported as codes, so an
+-- email could only be allow-listed for one presale. Codes are now unique per presale, and the
+-- allow-list has its own table, keyed by presale too.
+ALTER TABLE orders
+DROP CONSTRAINT fk_presale_code;
+
+DROP INDEX orders_presale_code;
+
+-- Allow-listed customers enter their email as the code, and the order's user info must then
+-- match it
+CREATE TABLE presale_allow_list (
+    presale_id varchar NOT NULL,
+    email text NOT NULL,
+    ticket_limit integer NOT NULL CHECK (ticket_limit > 0),
+    redeemed integer NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (presale_id, email),
+    CONSTRAINT fk_presale_id
+        FOREIGN KEY (presale_id)
+            REFERENCES presales(id)
+            ON DELETE CASCADE,
+    CONSTRAINT redeemed_within_ticket_limit CHECK (redeemed <= ticket_limit)
+);
+
+-- Imported from rows with only an email, which used the email as the code
+INSERT INTO presale_allow_list (presale_id, email, ticket_limit, redeemed)
+SELECT presale_id, email, ticket_limit, redeemed
+FROM presale_codes
+WHERE code = email;
+
+ALTER TABLE orders
+ADD presale_id varchar,
+ADD presale_email text;
+
+UPDATE orders
+SET
+    presale_id = pc.presale_id,
+    presale_code = CASE WHEN pc.code = pc.email THEN NULL ELSE pc.code END,
+    presale_email = CASE WHEN pc.code = pc.email THEN pc.email END
+FROM presale_codes AS pc
+WHERE pc.code = orders.presale_code;
+
+DELETE FROM presale_codes
+WHERE code = email;
+
+-- Codes imported with an email can still only be used by that email
+ALTER TABLE presale_codes
+DROP CONSTRAINT presale_codes_pkey,
+ADD PRIMARY KEY (presale_id, code);
+
+DROP INDEX presale_codes_presale_id;
+
+ALTER TABLE orders
+ADD CONSTRAINT fk_presale_code
+    FOREIGN KEY (presale_id, presale_code) REFERENCES presale_codes(presale_id, code),
+ADD CONSTRAINT fk_presale_email
+    FOREIGN KEY (presale_id, presale_email) REFERENCES presale_allow_list(presale_id, email),
+ADD CONSTRAINT presale_code_or_email
+    CHECK (presale_code IS NULL OR presale_email IS NULL),
+ADD CONSTRAINT presale_redeemed
+    CHECK ((presale_id IS NULL) = (presale_code IS NULL AND presale_email IS NULL));
+
+CREATE INDEX orders_presale_code ON orders (presale_id, presale_code);
+CREATE INDEX orders_presale_email ON orders (presale_id, presale_email);
+
+-- Give the ticket back to the presale code or allow-list entry when a reserved order expires
+CREATE OR REPLACE FUNCTION release_presale_code_on_delete()
+    RETURNS TRIGGER
+    LANGUAGE PLPGSQL
+AS $$
+BEGIN
+    IF OLD.presale_id IS NOT NULL AND OLD.purchased_at IS NULL THEN
+        IF OLD.presale_code IS NOT NULL THEN
+            UPDATE presale_codes
+            SET redeemed = redeemed - 1
+            WHERE presale_id = OLD.presale_id AND code = OLD.presale_code;
+        ELSE
+            UPDATE presale_allow_list
+            SET redeemed = redeemed - 1
+            WHERE presale_id = OLD.presale_id AND email = OLD.presale_email;
+        END IF;
+    END IF;
+
+    RETURN OLD;
+END;
+$$;
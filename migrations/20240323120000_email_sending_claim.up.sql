@@ -0,0 +1,4 @@
+-- Set just before an email is handed to the mailer, and cleared if the mailer fails. An email
+-- with this set but not sent_at was being sent when a worker died or lost the database, so may
+-- have been sent, and isn't sent again
+ALTER TABLE email_outbox ADD sending_at timestamp with time zone;
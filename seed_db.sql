@@ -1,2 +1,10 @@
--- Placeholder
 -- Add any data to seed db with before tests here
+
+INSERT INTO promo_codes (
+    code, discount_kind, discount_value, ticket_types, durations, max_uses, per_customer_limit
+)
+VALUES
+    ('TEST10PCT', 'percentage', 10, NULL, NULL, NULL, NULL),
+    ('TESTHOTEL', 'fixed', 5, '{hotel2,hotel3}', '{4}', NULL, NULL),
+    ('TESTONCE', 'fixed', 5, NULL, NULL, NULL, 1)
+ON CONFLICT (code) DO NOTHING;
@@ -0,0 +1,59 @@
+use festival_tickets_core::app::App;
+use festival_tickets_core::{shutdown, telemetry};
+
+/// Serves the gRPC and REST APIs from one process, sharing the db pool and background jobs
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Setup logging, level INFO unless RUST_LOG is set
+    let _telemetry = telemetry::init("festival-tickets-server")?;
+
+    let Some(app) = App::init().await? else {
+        return Ok(());
+    };
+    let grpc_addr = app.config.server.grpc_addr;
+    let rest_addr = app.config.server.rest_addr;
+
+    log::info!("gRPC listening on {}", grpc_addr);
+    let grpc = festival_tickets_tonic::grpc_server(
+        app.ticketing.clone(),
+        app.health.clone(),
+        app.tokens.clone(),
+        &app.config.server,
+        app.shutdown.clone(),
+    )
+    .serve_with_shutdown(grpc_addr, app.shutdown.clone().cancelled_owned());
+    log::info!("REST listening on {}", rest_addr);
+    let rest = festival_tickets_actix::server(
+        std::net::TcpListener::bind(rest_addr)?,
+        app.ticketing.clone(),
+        app.health.clone(),
+        app.tokens.clone(),
+        &app.config,
+        app.shutdown.clone(),
+    )?;
+
+    // Run until a signal, or either server fails, then give requests and the running job until
+    // the one deadline to finish
+    let (shutdown, deadline) = (&app.shutdown, &app.deadline);
+    let timeout = deadline.timeout();
+    let grpc = async {
+        let res = shutdown::drain(grpc, deadline).await;
+        shutdown.cancel();
+        res.map(|res| res.map_err(Box::<dyn std::error::Error>::from))
+    };
+    let rest = async {
+        let res = shutdown::drain(rest, deadline).await;
+        shutdown.cancel();
+        res.map(|res| res.map_err(Box::<dyn std::error::Error>::from))
+    };
+    let (grpc, rest) = tokio::join!(grpc, rest);
+    app.finish().await;
+    for res in [grpc, rest] {
+        match res {
+            Some(res) => res?,
+            None => log::warn!("requests still in flight after {:?}", timeout),
+        }
+    }
+
+    Ok(())
+}
@@ -1,3 +1,11 @@
+use std::env;
+use std::path::PathBuf;
+
 fn main() {
-    tonic_build::compile_protos("proto/purchase.proto").unwrap();
+    // Descriptors are served by the reflection service
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("purchase_descriptor.bin"))
+        .compile(&["proto/purchase.proto"], &["proto"])
+        .unwrap();
 }
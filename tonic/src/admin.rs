@@ -0,0 +1,309 @@
+use std::sync::Arc;
+
+use async_stream::stream;
+use futures::Stream;
+use tonic::codegen::InterceptedService;
+use tonic::{Request, Response, Status};
+
+use festival_tickets_core::db::export::ExportFilter;
+use festival_tickets_core::TicketingService;
+
+use crate::auth::BearerAuth;
+use crate::convert::convert_all;
+use crate::error::{error_chain, ServiceError};
+use crate::pb::admin_service_server::{AdminService as AdminServiceTrait, AdminServiceServer};
+use crate::pb::{
+    CreateWebhookEndpointRequest, CreateWebhookEndpointResponse,
+    DeletePurchaseLimitOverrideRequest, DeletePurchaseLimitOverrideResponse,
+    DeleteWebhookEndpointRequest, DeleteWebhookEndpointResponse, ExportOrdersRequest,
+    ExportOrdersResponse, GetJobsRequest, GetJobsResponse, GetPresaleCodesRequest,
+    GetPresaleCodesResponse, GetWebhookDeliveriesRequest, GetWebhookDeliveriesResponse,
+    GetWebhookEndpointsRequest, GetWebhookEndpointsResponse, ImportCompTicketsRequest,
+    ImportCompTicketsResponse, ImportPresaleCodesRequest, ImportPresaleCodesResponse,
+    RetryJobRequest, RetryJobResponse, SetPurchaseLimitOverrideRequest,
+    SetPurchaseLimitOverrideResponse, TestWebhookEndpointRequest, TestWebhookEndpointResponse,
+    UpsertPresaleRequest, UpsertPresaleResponse,
+};
+use crate::{parse_timestamp, parse_uuid, ServiceResult};
+use sqlx::types::Uuid;
+
+pub struct AdminService {
+    ticketing: Arc<dyn TicketingService>,
+}
+
+impl AdminService {
+    pub fn new(ticketing: Arc<dyn TicketingService>) -> Self {
+        Self { ticketing }
+    }
+
+    /// Requires an `authorization: Bearer <ADMIN_TOKEN>` header
+    pub fn into_service(
+        self,
+        token: Option<String>,
+    ) -> InterceptedService<AdminServiceServer<AdminService>, BearerAuth> {
+        AdminServiceServer::with_interceptor(self, BearerAuth::new("admin", token))
+    }
+}
+
+#[tonic::async_trait]
+impl AdminServiceTrait for AdminService {
+    async fn upsert_presale(
+        &self,
+        request: Request<UpsertPresaleRequest>,
+    ) -> ServiceResult<UpsertPresaleResponse> {
+        let req = request.into_inner();
+        let starts_at = parse_timestamp(&req.starts_at)?;
+        let ends_at = parse_timestamp(&req.ends_at)?;
+
+        let presale = self
+            .ticketing
+            .upsert_presale(&req.id, starts_at, ends_at)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(UpsertPresaleResponse {
+            presale: Some(presale.into()),
+        }))
+    }
+
+    async fn import_presale_codes(
+        &self,
+        request: Request<ImportPresaleCodesRequest>,
+    ) -> ServiceResult<ImportPresaleCodesResponse> {
+        let req = request.into_inner();
+
+        let report = self
+            .ticketing
+            .import_presale_codes(&req.presale_id, &req.csv, req.default_ticket_limit)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(ImportPresaleCodesResponse {
+            imported: report.imported,
+            rejected: report.rejected,
+        }))
+    }
+
+    async fn get_presale_codes(
+        &self,
+        request: Request<GetPresaleCodesRequest>,
+    ) -> ServiceResult<GetPresaleCodesResponse> {
+        let req = request.into_inner();
+
+        let presale_codes = self
+            .ticketing
+            .get_presale_codes(&req.presale_id)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(GetPresaleCodesResponse {
+            presale_codes: convert_all(presale_codes),
+        }))
+    }
+
+    async fn set_purchase_limit_override(
+        &self,
+        request: Request<SetPurchaseLimitOverrideRequest>,
+    ) -> ServiceResult<SetPurchaseLimitOverrideResponse> {
+        let req = request.into_inner();
+
+        let limit_override = self
+            .ticketing
+            .set_purchase_limit_override(&req.email, req.ticket_limit, req.note.as_deref())
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(SetPurchaseLimitOverrideResponse {
+            purchase_limit_override: Some(limit_override.into()),
+        }))
+    }
+
+    async fn delete_purchase_limit_override(
+        &self,
+        request: Request<DeletePurchaseLimitOverrideRequest>,
+    ) -> ServiceResult<DeletePurchaseLimitOverrideResponse> {
+        let req = request.into_inner();
+
+        self.ticketing
+            .delete_purchase_limit_override(&req.email)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(DeletePurchaseLimitOverrideResponse {}))
+    }
+
+    async fn get_jobs(&self, request: Request<GetJobsRequest>) -> ServiceResult<GetJobsResponse> {
+        let req = request.into_inner();
+
+        let jobs = self
+            .ticketing
+            .get_jobs(req.state.as_deref(), req.kind.as_deref(), req.limit)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(GetJobsResponse {
+            jobs: convert_all(jobs),
+        }))
+    }
+
+    async fn retry_job(
+        &self,
+        request: Request<RetryJobRequest>,
+    ) -> ServiceResult<RetryJobResponse> {
+        let req = request.into_inner();
+
+        let job = self
+            .ticketing
+            .retry_job(req.id)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(RetryJobResponse {
+            job: Some(job.into()),
+        }))
+    }
+
+    async fn create_webhook_endpoint(
+        &self,
+        request: Request<CreateWebhookEndpointRequest>,
+    ) -> ServiceResult<CreateWebhookEndpointResponse> {
+        let req = request.into_inner();
+
+        let (endpoint, secret) = self
+            .ticketing
+            .create_webhook_endpoint(&req.url, &req.events)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(CreateWebhookEndpointResponse {
+            endpoint: Some(endpoint.into()),
+            secret,
+        }))
+    }
+
+    async fn get_webhook_endpoints(
+        &self,
+        _request: Request<GetWebhookEndpointsRequest>,
+    ) -> ServiceResult<GetWebhookEndpointsResponse> {
+        let endpoints = self
+            .ticketing
+            .get_webhook_endpoints()
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(GetWebhookEndpointsResponse {
+            endpoints: convert_all(endpoints),
+        }))
+    }
+
+    async fn delete_webhook_endpoint(
+        &self,
+        request: Request<DeleteWebhookEndpointRequest>,
+    ) -> ServiceResult<DeleteWebhookEndpointResponse> {
+        let req = request.into_inner();
+        let endpoint_id = parse_uuid(&req.id)?;
+
+        self.ticketing
+            .delete_webhook_endpoint(&endpoint_id)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(DeleteWebhookEndpointResponse {}))
+    }
+
+    async fn get_webhook_deliveries(
+        &self,
+        request: Request<GetWebhookDeliveriesRequest>,
+    ) -> ServiceResult<GetWebhookDeliveriesResponse> {
+        let req = request.into_inner();
+        let endpoint_id = parse_uuid(&req.endpoint_id)?;
+
+        let deliveries = self
+            .ticketing
+            .get_webhook_deliveries(&endpoint_id, req.limit)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(GetWebhookDeliveriesResponse {
+            deliveries: convert_all(deliveries),
+        }))
+    }
+
+    async fn test_webhook_endpoint(
+        &self,
+        request: Request<TestWebhookEndpointRequest>,
+    ) -> ServiceResult<TestWebhookEndpointResponse> {
+        let req = request.into_inner();
+        let endpoint_id = parse_uuid(&req.endpoint_id)?;
+
+        let delivery = self
+            .ticketing
+            .test_webhook_endpoint(&endpoint_id)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(TestWebhookEndpointResponse {
+            delivery: Some(delivery.into()),
+        }))
+    }
+
+    type ExportOrdersStream =
+        std::pin::Pin<Box<dyn Stream<Item = Result<ExportOrdersResponse, Status>> + Send>>;
+
+    async fn export_orders(
+        &self,
+        request: Request<ExportOrdersRequest>,
+    ) -> ServiceResult<Self::ExportOrdersStream> {
+        let req = request.into_inner();
+        let format = crate::pb::ExportFormat::try_from(req.format)
+            .map_err(|e| ServiceError::ParseError(format!("export format ({})", e)))?;
+        let filter = ExportFilter {
+            ticket_type_id: req.ticket_type_id,
+            duration: req.duration,
+            purchased_from: req
+                .purchased_from
+                .as_deref()
+                .map(parse_timestamp)
+                .transpose()?,
+            purchased_to: req
+                .purchased_to
+                .as_deref()
+                .map(parse_timestamp)
+                .transpose()?,
+        };
+
+        let chunks = self.ticketing.export_orders(filter, format.into());
+        let span = tracing::Span::current();
+        let stream = stream! {
+            for await chunk in chunks {
+                yield chunk.map(|data| ExportOrdersResponse { data }).map_err(|e| {
+                    let e = ServiceError::from(e);
+                    // The response has already been sent, and the request logged
+                    tracing::error!(parent: &span, error = %error_chain(&e), "export failed");
+                    e.into()
+                })
+            }
+        };
+
+        Ok(Response::new(Box::pin(stream) as Self::ExportOrdersStream))
+    }
+
+    async fn import_comp_tickets(
+        &self,
+        request: Request<ImportCompTicketsRequest>,
+    ) -> ServiceResult<ImportCompTicketsResponse> {
+        let req = request.into_inner();
+
+        let report = self
+            .ticketing
+            .import_comp_tickets(&req.csv, req.allocation.as_deref(), req.dry_run)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(ImportCompTicketsResponse {
+            imported: report.imported,
+            rejected: report.rejected,
+            order_ids: report.order_ids.iter().map(Uuid::to_string).collect(),
+        }))
+    }
+}
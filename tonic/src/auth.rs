@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use subtle::ConstantTimeEq;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::error::ServiceError;
+
+/// Checks requests carry an `authorization: Bearer <token>` header
+#[derive(Clone)]
+pub struct BearerAuth {
+    api: &'static str,
+    token: Option<Arc<str>>,
+}
+
+impl BearerAuth {
+    /// All requests are rejected if `token` isn't set
+    pub fn new(api: &'static str, token: Option<String>) -> Self {
+        Self {
+            api,
+            token: token.map(Arc::from),
+        }
+    }
+
+    /// Check an `authorization` header value is `Bearer <token>`
+    pub fn check(&self, authorization: Option<&str>) -> Result<(), ServiceError> {
+        let Some(token) = &self.token else {
+            return Err(ServiceError::Unauthenticated(format!(
+                "{} API is disabled",
+                self.api
+            )));
+        };
+
+        let bearer = authorization.and_then(|v| v.strip_prefix("Bearer "));
+
+        // In constant time, so the token can't be guessed a byte at a time from response times
+        match bearer {
+            Some(bearer) if bool::from(bearer.as_bytes().ct_eq(token.as_bytes())) => Ok(()),
+            _ => Err(ServiceError::Unauthenticated(format!(
+                "invalid {} token",
+                self.api
+            ))),
+        }
+    }
+}
+
+impl Interceptor for BearerAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok());
+        self.check(authorization)?;
+        Ok(request)
+    }
+}
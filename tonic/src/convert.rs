@@ -0,0 +1,209 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use festival_tickets_core::export::ExportFormat;
+use festival_tickets_core::model;
+
+use crate::pb;
+
+/// Format a timestamp for a response, as RFC3339 in UTC to the second
+pub(crate) fn format_timestamp(value: DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+impl From<model::Order> for pb::Order {
+    fn from(value: model::Order) -> Self {
+        Self {
+            id: value.id.to_string(),
+            ticket_type_id: value.ticket_type_id,
+            user_id: value.user_id,
+            duration: value.duration,
+            price: value.price,
+            reserved_until: format_timestamp(value.reserved_until),
+            purchased_at: value.purchased_at.map(format_timestamp),
+            promo_code: value.promo_code,
+            discount: value.discount,
+        }
+    }
+}
+
+impl From<model::User> for pb::User {
+    fn from(value: model::User) -> Self {
+        Self {
+            id: value.id.to_string(),
+            name: value.name,
+            address: value.address,
+            email: value.email,
+            order_id: value.order_id.to_string(),
+        }
+    }
+}
+
+impl From<model::TicketType> for pb::TicketType {
+    fn from(value: model::TicketType) -> Self {
+        Self {
+            id: value.id,
+            display: value.display,
+            sold_out: value.sold_out,
+        }
+    }
+}
+
+impl From<model::OrderStats> for pb::OrderStats {
+    fn from(value: model::OrderStats) -> Self {
+        Self {
+            duration_days: value.duration_days,
+            order_limit: value.order_limit,
+            order_count: value.order_count,
+        }
+    }
+}
+
+impl From<model::Presale> for pb::Presale {
+    fn from(value: model::Presale) -> Self {
+        Self {
+            id: value.id,
+            starts_at: format_timestamp(value.starts_at),
+            ends_at: format_timestamp(value.ends_at),
+        }
+    }
+}
+
+impl From<model::PresaleCode> for pb::PresaleCode {
+    fn from(value: model::PresaleCode) -> Self {
+        Self {
+            code: value.code,
+            presale_id: value.presale_id,
+            email: value.email,
+            ticket_limit: value.ticket_limit,
+            redeemed: value.redeemed,
+            purchased: value.purchased,
+        }
+    }
+}
+
+impl From<model::PurchaseLimitOverride> for pb::PurchaseLimitOverride {
+    fn from(value: model::PurchaseLimitOverride) -> Self {
+        Self {
+            email: value.email,
+            ticket_limit: value.ticket_limit,
+            note: value.note,
+        }
+    }
+}
+
+impl From<model::Job> for pb::Job {
+    fn from(value: model::Job) -> Self {
+        Self {
+            id: value.id,
+            kind: value.kind,
+            payload: value.payload,
+            state: value.state,
+            attempts: value.attempts,
+            max_attempts: value.max_attempts,
+            last_error: value.last_error,
+            run_after: format_timestamp(value.run_after),
+            created_at: format_timestamp(value.created_at),
+            updated_at: format_timestamp(value.updated_at),
+        }
+    }
+}
+
+impl From<model::WebhookEndpoint> for pb::WebhookEndpoint {
+    fn from(value: model::WebhookEndpoint) -> Self {
+        Self {
+            id: value.id.to_string(),
+            url: value.url,
+            events: value.events,
+            created_at: format_timestamp(value.created_at),
+        }
+    }
+}
+
+impl From<model::WebhookDelivery> for pb::WebhookDelivery {
+    fn from(value: model::WebhookDelivery) -> Self {
+        Self {
+            id: value.id,
+            endpoint_id: value.endpoint_id.to_string(),
+            event_id: value.event_id.to_string(),
+            event: value.event,
+            payload: value.payload,
+            attempts: value.attempts,
+            response_status: value.response_status,
+            last_error: value.last_error,
+            created_at: format_timestamp(value.created_at),
+            delivered_at: value.delivered_at.map(format_timestamp),
+        }
+    }
+}
+
+impl From<model::Ticket> for pb::Ticket {
+    fn from(value: model::Ticket) -> Self {
+        Self {
+            order_id: value.order_id.to_string(),
+            ticket_type_id: value.ticket_type_id,
+            duration: value.duration,
+            holder_name: value.holder_name,
+            credential: value.credential,
+            issued_at: format_timestamp(value.issued_at),
+        }
+    }
+}
+
+impl From<model::CheckIn> for pb::CheckIn {
+    fn from(value: model::CheckIn) -> Self {
+        Self {
+            id: value.id,
+            order_id: value.order_id.to_string(),
+            festival_day: value.festival_day,
+            direction: value.direction,
+            gate: value.gate,
+            scanned_at: format_timestamp(value.scanned_at),
+            offline: value.offline,
+            conflict: value.conflict,
+        }
+    }
+}
+
+impl From<pb::QrCodeFormat> for model::QrCodeFormat {
+    fn from(value: pb::QrCodeFormat) -> Self {
+        match value {
+            pb::QrCodeFormat::Png => Self::Png,
+            pb::QrCodeFormat::Svg => Self::Svg,
+        }
+    }
+}
+
+impl From<pb::ScanDirection> for model::ScanDirection {
+    fn from(value: pb::ScanDirection) -> Self {
+        match value {
+            pb::ScanDirection::Entry => Self::Entry,
+            pb::ScanDirection::Exit => Self::Exit,
+        }
+    }
+}
+
+impl From<pb::ExportFormat> for ExportFormat {
+    fn from(value: pb::ExportFormat) -> Self {
+        match value {
+            pb::ExportFormat::Csv => Self::Csv,
+            pb::ExportFormat::Ndjson => Self::Ndjson,
+        }
+    }
+}
+
+/// Convert a list of model types for a response
+pub(crate) fn convert_all<T, U: From<T>>(values: Vec<T>) -> Vec<U> {
+    values.into_iter().map(U::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn format_timestamps_to_the_second() {
+        let value = Utc.with_ymd_and_hms(2024, 3, 16, 12, 0, 5).unwrap()
+            + chrono::Duration::milliseconds(750);
+        assert_eq!(format_timestamp(value), "2024-03-16T12:00:05Z");
+    }
+}
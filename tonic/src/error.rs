@@ -1,7 +1,8 @@
 use thiserror::Error;
 use tonic::Code;
 
-use crate::db::error::DbError;
+use festival_tickets_core::error::RETRY_AFTER_SECS;
+use festival_tickets_core::tickets::TicketError;
 
 #[derive(Error, Debug)]
 pub enum ServiceError {
@@ -11,20 +12,36 @@ pub enum ServiceError {
     StreamStartError,
     #[error("stream error")]
     StreamError,
+    #[error("server shutting down")]
+    ShuttingDown,
     #[error("database error")]
     DatabaseError(#[from] sqlx::Error),
     #[error("failed precondition: {0}")]
     FailedPrecondition(String),
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("unavailable: {0}")]
+    Unavailable(String),
+    #[error("unauthenticated: {0}")]
+    Unauthenticated(String),
+    #[error("ticket error: {0}")]
+    TicketError(#[from] TicketError),
     #[error("unknown service error")]
     Unknown,
 }
 
-impl From<DbError> for ServiceError {
-    fn from(value: DbError) -> Self {
+impl From<festival_tickets_core::Error> for ServiceError {
+    fn from(value: festival_tickets_core::Error) -> Self {
+        use festival_tickets_core::Error;
+
         match value {
-            DbError::Unknown => ServiceError::Unknown,
-            DbError::ExecutionError(e) => ServiceError::DatabaseError(e),
-            DbError::FailedPrecondition(e) => ServiceError::FailedPrecondition(e),
+            Error::NotFound(e) => ServiceError::NotFound(e),
+            Error::FailedPrecondition(e) => ServiceError::FailedPrecondition(e),
+            Error::SoldOut(e) => ServiceError::FailedPrecondition(e),
+            Error::Db(e) => ServiceError::DatabaseError(e),
+            Error::Ticket(e) => ServiceError::TicketError(e),
+            Error::Unavailable(e) => ServiceError::Unavailable(e),
+            Error::Unknown => ServiceError::Unknown,
         }
     }
 }
@@ -35,15 +52,42 @@ impl<'a> From<&'a ServiceError> for Code {
             ServiceError::ParseError(_) => Code::InvalidArgument,
             ServiceError::StreamStartError => Code::Internal,
             ServiceError::StreamError => Code::Internal,
+            ServiceError::ShuttingDown => Code::Unavailable,
             ServiceError::DatabaseError(_e) => Code::Internal,
             ServiceError::FailedPrecondition(_s) => Code::FailedPrecondition,
+            ServiceError::NotFound(_s) => Code::NotFound,
+            ServiceError::Unavailable(_s) => Code::Unavailable,
+            ServiceError::Unauthenticated(_s) => Code::Unauthenticated,
+            ServiceError::TicketError(_e) => Code::Internal,
             ServiceError::Unknown => Code::Unknown,
         }
     }
 }
 
+/// `error` with its sources, i.e. the database error behind `DatabaseError`
+pub(crate) fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut chain = error.to_string();
+    let mut source = error.source();
+    while let Some(e) = source {
+        chain.push_str(": ");
+        chain.push_str(&e.to_string());
+        source = e.source();
+    }
+    chain
+}
+
 impl From<ServiceError> for tonic::Status {
+    /// Also records the error on the request span, which the trace layer logs once the response
+    /// is sent
     fn from(value: ServiceError) -> Self {
-        tonic::Status::new((&value).into(), value.to_string())
+        tracing::Span::current().record("error", tracing::field::display(error_chain(&value)));
+        let mut status = tonic::Status::new((&value).into(), value.to_string());
+        if let ServiceError::Unavailable(_) = value {
+            // As HTTP's Retry-After, in seconds
+            status
+                .metadata_mut()
+                .insert("retry-after", RETRY_AFTER_SECS.into());
+        }
+        status
     }
 }
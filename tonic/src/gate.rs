@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use festival_tickets_core::model::OfflineCheckIn;
+use festival_tickets_core::TicketingService;
+use sqlx::types::Uuid;
+use tonic::codegen::InterceptedService;
+use tonic::{Request, Response};
+
+use crate::auth::BearerAuth;
+use crate::convert::convert_all;
+use crate::error::ServiceError;
+use crate::pb::gate_service_server::{GateService as GateServiceTrait, GateServiceServer};
+use crate::pb::{
+    self, CheckInRequest, CheckInResponse, GetScannerConfigRequest, GetScannerConfigResponse,
+    SyncCheckInsRequest, SyncCheckInsResponse,
+};
+use crate::ServiceResult;
+
+pub struct GateService {
+    ticketing: Arc<dyn TicketingService>,
+}
+
+impl GateService {
+    pub fn new(ticketing: Arc<dyn TicketingService>) -> Self {
+        Self { ticketing }
+    }
+
+    /// Requires an `authorization: Bearer <SCANNER_TOKEN>` header
+    pub fn into_service(
+        self,
+        token: Option<String>,
+    ) -> InterceptedService<GateServiceServer<GateService>, BearerAuth> {
+        GateServiceServer::with_interceptor(self, BearerAuth::new("scanner", token))
+    }
+}
+
+/// Parse an offline scan. Returns why it was rejected if it can't be parsed.
+fn parse_offline_check_in(scan: &pb::OfflineCheckIn) -> Result<OfflineCheckIn, String> {
+    let scanned_at = chrono::DateTime::parse_from_rfc3339(&scan.scanned_at)
+        .map_err(|e| format!("{}: invalid scanned_at ({})", scan.client_ref, e))?;
+    let client_ref = Uuid::parse_str(&scan.client_ref)
+        .map_err(|e| format!("{}: invalid client_ref ({})", scan.client_ref, e))?;
+
+    Ok(OfflineCheckIn {
+        client_ref,
+        credential: scan.credential.clone(),
+        direction: scan.direction().into(),
+        gate: scan.gate.clone(),
+        scanned_at: scanned_at.with_timezone(&chrono::Utc),
+    })
+}
+
+#[tonic::async_trait]
+impl GateServiceTrait for GateService {
+    async fn check_in(&self, request: Request<CheckInRequest>) -> ServiceResult<CheckInResponse> {
+        let req = request.into_inner();
+
+        let (check_in, ticket) = self
+            .ticketing
+            .check_in(&req.credential, req.direction().into(), &req.gate)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(CheckInResponse {
+            check_in: Some(check_in.into()),
+            ticket: ticket.map(Into::into),
+        }))
+    }
+
+    async fn get_scanner_config(
+        &self,
+        _request: Request<GetScannerConfigRequest>,
+    ) -> ServiceResult<GetScannerConfigResponse> {
+        let config = self.ticketing.get_scanner_config();
+
+        Ok(Response::new(GetScannerConfigResponse {
+            verifying_key: config.verifying_key,
+            festival_start_date: config.festival_start_date.to_string(),
+            allow_reentry: config.allow_reentry,
+            festival_utc_offset: config.festival_utc_offset,
+        }))
+    }
+
+    async fn sync_check_ins(
+        &self,
+        request: Request<SyncCheckInsRequest>,
+    ) -> ServiceResult<SyncCheckInsResponse> {
+        let req = request.into_inner();
+
+        let mut scans = vec![];
+        let mut rejected = vec![];
+        for scan in &req.check_ins {
+            match parse_offline_check_in(scan) {
+                Ok(scan) => scans.push(scan),
+                Err(reason) => rejected.push(reason),
+            }
+        }
+
+        let (check_ins, sync_rejected) = self
+            .ticketing
+            .sync_check_ins(&scans)
+            .await
+            .map_err(ServiceError::from)?;
+        rejected.extend(sync_rejected);
+
+        Ok(Response::new(SyncCheckInsResponse {
+            check_ins: convert_all(check_ins),
+            rejected,
+        }))
+    }
+}
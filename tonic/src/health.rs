@@ -0,0 +1,61 @@
+use festival_tickets_core::health::HealthCheck;
+use festival_tickets_core::shutdown::CancellationToken;
+use tokio::time::{sleep, Duration};
+use tonic::server::NamedService;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use crate::admin::AdminService;
+use crate::gate::GateService;
+use crate::pb::admin_service_server::AdminServiceServer;
+use crate::pb::gate_service_server::GateServiceServer;
+use crate::pb::product_service_server::ProductServiceServer;
+use crate::Service;
+
+/// How often the database and workers are checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Services to report, where "" is the server as a whole
+const SERVICES: [&str; 4] = [
+    "",
+    <ProductServiceServer<Service> as NamedService>::NAME,
+    <AdminServiceServer<AdminService> as NamedService>::NAME,
+    <GateServiceServer<GateService> as NamedService>::NAME,
+];
+
+/// Keep `grpc.health.v1.Health` up to date. Services are serving while the database and
+/// background workers are healthy, until shutting down
+pub(crate) async fn report_health(
+    mut reporter: HealthReporter,
+    health: HealthCheck,
+    shutdown: CancellationToken,
+) {
+    let mut serving = None;
+    while !shutdown.is_cancelled() {
+        let report = health.check().await;
+        if serving != Some(report.is_ready()) {
+            let status = if report.is_ready() {
+                log::info!("serving, {:?}", report);
+                ServingStatus::Serving
+            } else {
+                log::warn!("not serving, {:?}", report);
+                ServingStatus::NotServing
+            };
+            for service in SERVICES {
+                reporter.set_service_status(service, status).await;
+            }
+            serving = Some(report.is_ready());
+        }
+        tokio::select! {
+            () = sleep(CHECK_INTERVAL) => {}
+            () = shutdown.cancelled() => {}
+        }
+    }
+
+    // Take the server out of load balancers while requests drain
+    for service in SERVICES {
+        reporter
+            .set_service_status(service, ServingStatus::NotServing)
+            .await;
+    }
+}
@@ -1,6 +1,10 @@
 use async_stream::stream;
-use db::DbPool;
-use futures::{future::FutureExt, pin_mut};
+use festival_tickets_core::config::{ApiTokens, ServerConfig};
+use festival_tickets_core::health::HealthCheck;
+use festival_tickets_core::model::NewUser;
+use festival_tickets_core::shutdown::CancellationToken;
+use festival_tickets_core::TicketingService;
+use futures::StreamExt;
 use sqlx::types::Uuid;
 use std::sync::Arc;
 use tokio::select;
@@ -8,53 +12,134 @@ use tokio::sync::broadcast;
 use tokio::time::{sleep, Duration};
 use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
 use tokio_stream::{wrappers::BroadcastStream, Stream};
+use tonic::transport::server::Router;
+use tonic::transport::Server;
 use tonic::{Request, Response, Status};
+use tonic_web::GrpcWebLayer;
+use tower_http::cors::CorsLayer;
+use tower_layer::{Identity, Stack};
 
 use pb::product_service_server::{ProductService, ProductServiceServer};
 use pb::{
     AddTicketToBasketRequest, AddTicketToBasketResponse, AddUserInfoRequest, AddUserInfoResponse,
-    GetOrderRequest, GetOrderResponse, GetOrderStatsRequest, GetTicketDurationsRequest,
-    GetTicketDurationsResponse, GetTicketTypesRequest, GetTicketTypesResponse, GetUserRequest,
-    GetUserResponse, OrderStats, PurchaseOrderRequest, PurchaseOrderResponse,
+    ApplyPromoCodeRequest, ApplyPromoCodeResponse, GetOrderRequest, GetOrderResponse,
+    GetOrderStatsRequest, GetTicketDurationsRequest, GetTicketDurationsResponse,
+    GetTicketQrCodeRequest, GetTicketQrCodeResponse, GetTicketTypesRequest, GetTicketTypesResponse,
+    GetTicketsRequest, GetTicketsResponse, GetUserRequest, GetUserResponse, OrderStats,
+    PurchaseOrderRequest, PurchaseOrderResponse, QrCodeFormat,
 };
 
-pub mod db;
-mod env;
+pub mod admin;
+mod auth;
+mod convert;
 pub mod error;
+pub mod gate;
+mod health;
+mod metrics;
+mod trace;
+mod web;
 
+use convert::convert_all;
 use error::ServiceError;
 
 type ServiceResult<T> = tonic::Result<tonic::Response<T>>;
 
+/// Parse an RFC3339 timestamp from a request
+fn parse_timestamp(value: &str) -> Result<chrono::DateTime<chrono::Utc>, ServiceError> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&chrono::Utc))
+        .map_err(|e| ServiceError::ParseError(format!("timestamp {} ({})", value, e)))
+}
+
+/// Parse a UUID from a request
+fn parse_uuid(value: &str) -> Result<Uuid, ServiceError> {
+    Uuid::parse_str(value).map_err(|e| ServiceError::ParseError(format!("uuid ({})", e)))
+}
+
 pub mod pb {
     tonic::include_proto!("purchase");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("purchase_descriptor");
+}
+
+/// Tracing, metrics, CORS and gRPC-web translation, applied to every service
+pub type Layers = Stack<
+    GrpcWebLayer,
+    Stack<CorsLayer, Stack<metrics::MetricsLayer, Stack<trace::TraceLayer, Identity>>>,
+>;
+
+/// gRPC server with the product, admin and gate services, plus reflection, health checks and
+/// metrics at `GET /metrics`. The admin and gate services, and metrics, require the tokens in
+/// `tokens`.
+/// Also serves gRPC-web over HTTP/1.1, so browsers can connect without a proxy.
+/// Once `shutdown` is cancelled, health checks report not serving and order stats streams are
+/// closed, so serve with `serve_with_shutdown` to let other calls finish
+pub fn grpc_server(
+    ticketing: Arc<dyn TicketingService>,
+    health: HealthCheck,
+    tokens: ApiTokens,
+    config: &ServerConfig,
+    shutdown: CancellationToken,
+) -> Router<Layers> {
+    let (reporter, health_service) = tonic_health::server::health_reporter();
+    tokio::spawn(health::report_health(reporter, health, shutdown.clone()));
+
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(pb::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()
+        .expect("failed to build reflection service");
+
+    Server::builder()
+        .accept_http1(true)
+        .layer(trace::TraceLayer)
+        .layer(metrics::MetricsLayer::new(
+            ticketing.clone(),
+            tokens.metrics,
+        ))
+        .layer(web::cors(&config.cors_allowed_origins))
+        .layer(GrpcWebLayer::new())
+        .add_service(health_service)
+        .add_service(reflection)
+        .add_service(Service::new(ticketing.clone(), config, shutdown).into_service())
+        .add_service(admin::AdminService::new(ticketing.clone()).into_service(tokens.admin))
+        .add_service(gate::GateService::new(ticketing).into_service(tokens.scanner))
 }
 
 struct OrderStatsSubMsg {
     resp: tokio::sync::oneshot::Sender<tokio::sync::broadcast::Receiver<pb::OrderStats>>,
 }
 
+/// How long to wait before subscribing to order stats again, if the subscription fails
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
 pub struct Service {
-    dbpool: Arc<DbPool>,
+    ticketing: Arc<dyn TicketingService>,
     order_stats_sub: tokio::sync::mpsc::Sender<OrderStatsSubMsg>,
 }
 
 impl Service {
-    pub fn new(dbpool: Arc<db::DbPool>) -> Self {
+    /// Order stats streams are closed with `Unavailable` once `shutdown` is cancelled
+    pub fn new(
+        ticketing: Arc<dyn TicketingService>,
+        config: &ServerConfig,
+        shutdown: CancellationToken,
+    ) -> Self {
         // Setup broadcast channel for order stats updates
-        let (tx, _rx) = broadcast::channel::<pb::OrderStats>(16);
+        let (tx, _rx) = broadcast::channel::<pb::OrderStats>(config.stats_buffer);
         let (order_stats_sub_tx, order_stats_sub_rx) = tokio::sync::mpsc::channel(32);
 
-        let _order_stats_handle = tokio::spawn(Self::send_order_stats(
-            dbpool.clone(),
-            tx,
-            order_stats_sub_rx,
+        let _forward_handle = tokio::spawn(Self::forward_order_stats(
+            ticketing.clone(),
+            tx.clone(),
+            shutdown.clone(),
         ));
-
-        tokio::spawn(Self::remove_old_orders(dbpool.clone()));
+        let _order_stats_handle =
+            tokio::spawn(Self::send_order_stats(tx, order_stats_sub_rx, shutdown));
 
         Self {
-            dbpool,
+            ticketing,
             order_stats_sub: order_stats_sub_tx,
         }
     }
@@ -63,47 +148,63 @@ impl Service {
         ProductServiceServer::new(self)
     }
 
-    async fn remove_old_orders(pool: Arc<DbPool>) {
+    /// Respond to subscribe requests until shutting down
+    async fn send_order_stats(
+        tx: tokio::sync::broadcast::Sender<pb::OrderStats>,
+        mut order_stats_sub_rx: tokio::sync::mpsc::Receiver<OrderStatsSubMsg>,
+        shutdown: CancellationToken,
+    ) {
+        // Dropping `tx` when shutting down, along with the copy in `forward_order_stats`, ends
+        // every subscriber's stream
         loop {
-            match db::remove_expired_orders(&pool).await {
-                Ok(_) => (),
-                Err(e) => log::error!("error removing old orders: {}", e),
+            select! {
+                () = shutdown.cancelled() => break,
+                msg = order_stats_sub_rx.recv() => {
+                    if let Some(v) = msg {
+                        let _ = v.resp.send(tx.subscribe())
+                            .map_err(|e| log::error!("error responding to order stats sub: {:?}", e));
+                    }
+                }
             }
-            sleep(Duration::from_secs(5)).await;
         }
     }
 
-    async fn send_order_stats(
-        pool: Arc<DbPool>,
+    /// Send order stats to this instance's subscribers, as they're published by the leading
+    /// instance. Subscribes again if the subscription fails
+    async fn forward_order_stats(
+        ticketing: Arc<dyn TicketingService>,
         tx: tokio::sync::broadcast::Sender<pb::OrderStats>,
-        mut order_stats_sub_rx: tokio::sync::mpsc::Receiver<OrderStatsSubMsg>,
+        shutdown: CancellationToken,
     ) {
-        loop {
-            let sleep_future = sleep(Duration::from_millis(500)).fuse();
-            pin_mut!(sleep_future);
-
-            // Wait for 500ms sleep, interrupt to respond to subscribe requests
-            loop {
-                select! {
-                    () = &mut sleep_future => break,
-                    msg = order_stats_sub_rx.recv() => {
-                        if let Some(v) = msg {
-                            let _ = v.resp.send(tx.subscribe())
-                                .map_err(|e| log::error!("error responding to order stats sub: {:?}", e));
+        while !shutdown.is_cancelled() {
+            match ticketing.subscribe_order_stats().await {
+                Ok(mut updates) => loop {
+                    select! {
+                        () = shutdown.cancelled() => return,
+                        update = updates.next() => match update {
+                            Some(Ok(stats)) => {
+                                festival_tickets_core::metrics::set_order_stats_subscribers(
+                                    tx.receiver_count(),
+                                );
+                                // Ignore errors - this fails if there are no order stats listeners
+                                for s in stats {
+                                    let _ = tx.send(s.into());
+                                }
+                            }
+                            Some(Err(e)) => {
+                                log::error!("error receiving order stats: {:?}", e);
+                                break;
+                            }
+                            None => break,
                         }
                     }
-                }
+                },
+                Err(e) => log::error!("error subscribing to order stats: {:?}", e),
             }
 
-            if tx.receiver_count() > 0 {
-                db::get_order_stats(&pool)
-                    .await
-                    .unwrap_or(vec![])
-                    .into_iter()
-                    .for_each(|s| {
-                        // Ignore errors - this fails if there are no order stats listeners
-                        let _ = tx.send(s);
-                    })
+            select! {
+                () = sleep(RESUBSCRIBE_DELAY) => {}
+                () = shutdown.cancelled() => {}
             }
         }
     }
@@ -116,15 +217,18 @@ impl ProductService for Service {
         request: Request<AddTicketToBasketRequest>,
     ) -> ServiceResult<AddTicketToBasketResponse> {
         let req = request.into_inner();
-        let order = db::add_ticket_to_basket(&self.dbpool, &req.ticket_type_id, req.duration)
+        let order = self
+            .ticketing
+            .add_ticket_to_basket(
+                &req.ticket_type_id,
+                req.duration,
+                req.presale_code.as_deref(),
+            )
             .await
-            .map_err(|e| {
-                log::error!("{:#?}", e);
-                ServiceError::from(e)
-            })?;
+            .map_err(ServiceError::from)?;
 
         Ok(Response::new(pb::AddTicketToBasketResponse {
-            order: Some(order),
+            order: Some(order.into()),
         }))
     }
 
@@ -132,13 +236,15 @@ impl ProductService for Service {
         &self,
         _request: Request<GetTicketTypesRequest>,
     ) -> ServiceResult<GetTicketTypesResponse> {
-        let reply = pb::GetTicketTypesResponse {
-            ticket_types: db::get_ticket_types(&self.dbpool).await.map_err(|e| {
-                log::error!("{:#?}", e);
-                ServiceError::from(e)
-            })?,
-        };
-        Ok(Response::new(reply))
+        let ticket_types = self
+            .ticketing
+            .get_ticket_types()
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(pb::GetTicketTypesResponse {
+            ticket_types: convert_all(ticket_types),
+        }))
     }
 
     async fn get_ticket_durations(
@@ -147,12 +253,11 @@ impl ProductService for Service {
     ) -> ServiceResult<GetTicketDurationsResponse> {
         let req = request.into_inner();
         let reply = pb::GetTicketDurationsResponse {
-            ticket_durations: db::get_ticket_durations(&self.dbpool, &req.ticket_type_id)
+            ticket_durations: self
+                .ticketing
+                .get_ticket_durations(&req.ticket_type_id)
                 .await
-                .map_err(|e| {
-                    log::error!("{:#?}", e);
-                    ServiceError::from(e)
-                })?,
+                .map_err(ServiceError::from)?,
         };
         Ok(Response::new(reply))
     }
@@ -162,18 +267,16 @@ impl ProductService for Service {
         request: Request<PurchaseOrderRequest>,
     ) -> ServiceResult<PurchaseOrderResponse> {
         let req = request.into_inner();
-        let order_id = Uuid::parse_str(&req.id)
-            .map_err(|e| ServiceError::ParseError(format!("uuid ({})", e)))?;
+        let order_id = parse_uuid(&req.id)?;
 
-        let order = db::purchase_order(&self.dbpool, &order_id)
+        let order = self
+            .ticketing
+            .purchase_order(&order_id)
             .await
-            .map_err(|e| {
-                log::error!("{:#?}", e);
-                ServiceError::from(e)
-            })?;
+            .map_err(ServiceError::from)?;
 
         Ok(Response::new(pb::PurchaseOrderResponse {
-            order: Some(order),
+            order: Some(order.into()),
         }))
     }
 
@@ -182,28 +285,32 @@ impl ProductService for Service {
         request: Request<GetOrderRequest>,
     ) -> ServiceResult<GetOrderResponse> {
         let req = request.into_inner();
-        let order_id = Uuid::parse_str(&req.id)
-            .map_err(|e| ServiceError::ParseError(format!("uuid ({})", e)))?;
+        let order_id = parse_uuid(&req.id)?;
 
-        let order = db::get_order(&self.dbpool, &order_id).await.map_err(|e| {
-            log::error!("{:#?}", e);
-            ServiceError::from(e)
-        })?;
+        let order = self
+            .ticketing
+            .get_order(&order_id)
+            .await
+            .map_err(ServiceError::from)?;
 
-        Ok(Response::new(pb::GetOrderResponse { order: Some(order) }))
+        Ok(Response::new(pb::GetOrderResponse {
+            order: Some(order.into()),
+        }))
     }
 
     async fn get_user(&self, request: Request<GetUserRequest>) -> ServiceResult<GetUserResponse> {
         let req = request.into_inner();
-        let user_id = Uuid::parse_str(&req.id)
-            .map_err(|e| ServiceError::ParseError(format!("uuid ({})", e)))?;
+        let user_id = parse_uuid(&req.id)?;
 
-        let user = db::get_user(&self.dbpool, &user_id).await.map_err(|e| {
-            log::error!("{:#?}", e);
-            ServiceError::from(e)
-        })?;
+        let user = self
+            .ticketing
+            .get_user(&user_id)
+            .await
+            .map_err(ServiceError::from)?;
 
-        Ok(Response::new(pb::GetUserResponse { user: Some(user) }))
+        Ok(Response::new(pb::GetUserResponse {
+            user: Some(user.into()),
+        }))
     }
 
     async fn add_user_info(
@@ -211,19 +318,78 @@ impl ProductService for Service {
         request: Request<AddUserInfoRequest>,
     ) -> ServiceResult<AddUserInfoResponse> {
         let req = request.into_inner();
+        let order_id = parse_uuid(&req.order_id)?;
+        let user = NewUser {
+            name: req.user_name,
+            email: req.user_email,
+            address: req.user_address,
+        };
 
-        let order_id = Uuid::parse_str(&req.order_id)
-            .map_err(|e| ServiceError::ParseError(format!("uuid ({})", e)))?;
-
-        let order = db::add_user_to_order(&self.dbpool, &order_id, &req)
+        let order = self
+            .ticketing
+            .add_user_info(&order_id, &user)
             .await
-            .map_err(|e| {
-                log::error!("{:#?}", e);
-                ServiceError::from(e)
-            })?;
+            .map_err(ServiceError::from)?;
 
         Ok(Response::new(pb::AddUserInfoResponse {
-            order: Some(order),
+            order: Some(order.into()),
+        }))
+    }
+
+    async fn apply_promo_code(
+        &self,
+        request: Request<ApplyPromoCodeRequest>,
+    ) -> ServiceResult<ApplyPromoCodeResponse> {
+        let req = request.into_inner();
+        let order_id = parse_uuid(&req.order_id)?;
+
+        let order = self
+            .ticketing
+            .apply_promo_code(&order_id, &req.promo_code)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(pb::ApplyPromoCodeResponse {
+            order: Some(order.into()),
+        }))
+    }
+
+    async fn get_tickets(
+        &self,
+        request: Request<GetTicketsRequest>,
+    ) -> ServiceResult<GetTicketsResponse> {
+        let req = request.into_inner();
+        let order_id = parse_uuid(&req.order_id)?;
+
+        let tickets = self
+            .ticketing
+            .get_tickets(&order_id)
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(pb::GetTicketsResponse {
+            tickets: convert_all(tickets),
+        }))
+    }
+
+    async fn get_ticket_qr_code(
+        &self,
+        request: Request<GetTicketQrCodeRequest>,
+    ) -> ServiceResult<GetTicketQrCodeResponse> {
+        let req = request.into_inner();
+        let order_id = parse_uuid(&req.order_id)?;
+        let format = QrCodeFormat::try_from(req.format)
+            .map_err(|e| ServiceError::ParseError(format!("qr code format ({})", e)))?;
+
+        let qr_code = self
+            .ticketing
+            .get_ticket_qr_code(&order_id, format.into())
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(Response::new(pb::GetTicketQrCodeResponse {
+            content_type: qr_code.content_type.to_string(),
+            image: qr_code.image,
         }))
     }
 
@@ -261,9 +427,13 @@ fn map_broadcast_err_to_status<S: Stream<Item = Result<OrderStats, BroadcastStre
     stream! {
         for await value in input {
             yield value.map_err(|e| {
+                let BroadcastStreamRecvError::Lagged(skipped) = e;
+                festival_tickets_core::metrics::record_order_stats_lag(skipped);
                 log::error!("failed to yield new stream item: {}", e);
                 ServiceError::StreamError.into()
             })
         }
+        // Updates only stop when shutting down, so clients know to reconnect
+        yield Err(ServiceError::ShuttingDown.into());
     }
 }
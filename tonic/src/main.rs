@@ -1,28 +1,36 @@
-use std::sync::Arc;
-
-use festival_tickets_tonic::Service;
-use tonic::transport::Server;
+use festival_tickets_core::app::App;
+use festival_tickets_core::{shutdown, telemetry};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Setup logging, level INFO
-    let env = env_logger::Env::default().default_filter_or("info");
-    env_logger::Builder::from_env(env).init();
-
-    let addr = "0.0.0.0:50051".parse().unwrap();
-    log::info!("connecting to db...");
-    let pool = festival_tickets_tonic::db::connect_to_pool().await;
-    // Run database migrations
-    sqlx::migrate!("../migrations").run(&pool).await?;
+    // Setup logging, level INFO unless RUST_LOG is set
+    let _telemetry = telemetry::init("festival-tickets-tonic")?;
 
-    let service = Service::new(Arc::new(pool)).into_service();
+    let Some(app) = App::init().await? else {
+        return Ok(());
+    };
+    let addr = app.config.server.grpc_addr;
 
-    // Note: To connect via gRPC-web, an external proxy must be used (i.e. Envoy)
-    // tonic_web supports http1 requests, but it's not well supported - CORS config is annoying
-    // See https://github.com/hyperium/tonic/issues/1524
+    // gRPC-web is served directly, from the origins in `server.cors_allowed_origins`.
+    // A proxy such as Envoy can still sit in front, i.e. to terminate TLS
     log::info!("server listening on {}", addr);
 
-    Server::builder().add_service(service).serve(addr).await?;
+    let server = festival_tickets_tonic::grpc_server(
+        app.ticketing.clone(),
+        app.health.clone(),
+        app.tokens.clone(),
+        &app.config.server,
+        app.shutdown.clone(),
+    )
+    .serve_with_shutdown(addr, app.shutdown.clone().cancelled_owned());
+    // Requests and the running job share one deadline
+    let res = shutdown::drain(server, &app.deadline).await;
+    let timeout = app.deadline.timeout();
+    app.finish().await;
+    match res {
+        Some(res) => res?,
+        None => log::warn!("requests still in flight after {:?}", timeout),
+    }
 
     Ok(())
 }
@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use festival_tickets_core::{metrics, TicketingService};
+use futures::future::BoxFuture;
+use http::{header, Method, Request, Response, StatusCode};
+use http_body::{Body, Full};
+use prost::Message;
+use prost_types::FileDescriptorSet;
+use tonic::body::BoxBody;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::auth::BearerAuth;
+use crate::pb;
+
+/// Route recorded for paths which aren't an RPC, so clients can't create a series per path
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Paths of every RPC served, i.e. `/purchase.ProductService/GetOrder`
+fn rpc_paths() -> HashSet<String> {
+    let descriptor_sets = [
+        pb::FILE_DESCRIPTOR_SET,
+        tonic_health::pb::FILE_DESCRIPTOR_SET,
+        tonic_reflection::pb::FILE_DESCRIPTOR_SET,
+    ];
+    let mut paths = HashSet::new();
+    for descriptor_set in descriptor_sets {
+        let descriptor_set =
+            FileDescriptorSet::decode(descriptor_set).expect("invalid file descriptor set");
+        for file in descriptor_set.file {
+            for service in &file.service {
+                for method in &service.method {
+                    paths.insert(format!(
+                        "/{}.{}/{}",
+                        file.package(),
+                        service.name(),
+                        method.name()
+                    ));
+                }
+            }
+        }
+    }
+    paths
+}
+
+/// Records the count and latency of each RPC, and serves `GET /metrics` to requests with the
+/// metrics token
+#[derive(Clone)]
+pub struct MetricsLayer {
+    ticketing: Arc<dyn TicketingService>,
+    rpc_paths: Arc<HashSet<String>>,
+    auth: BearerAuth,
+}
+
+impl MetricsLayer {
+    pub fn new(ticketing: Arc<dyn TicketingService>, token: Option<String>) -> Self {
+        Self {
+            ticketing,
+            rpc_paths: Arc::new(rpc_paths()),
+            auth: BearerAuth::new("metrics", token),
+        }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Metrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Metrics {
+            inner,
+            ticketing: self.ticketing.clone(),
+            rpc_paths: self.rpc_paths.clone(),
+            auth: self.auth.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Metrics<S> {
+    inner: S,
+    ticketing: Arc<dyn TicketingService>,
+    rpc_paths: Arc<HashSet<String>>,
+    auth: BearerAuth,
+}
+
+impl<S, B> Service<Request<B>> for Metrics<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        if req.method() == Method::GET && req.uri().path() == "/metrics" {
+            let authorization = req
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok());
+            if let Err(e) = self.auth.check(authorization) {
+                let body = Full::from(e.to_string())
+                    .map_err(|e| match e {})
+                    .boxed_unsync();
+                let mut res = Response::new(body);
+                *res.status_mut() = StatusCode::UNAUTHORIZED;
+                return Box::pin(async move { Ok(res) });
+            }
+
+            let ticketing = self.ticketing.clone();
+            return Box::pin(async move {
+                let body = metrics::render(ticketing.as_ref()).await;
+                let body = Full::from(body).map_err(|e| match e {}).boxed_unsync();
+                let mut res = Response::new(body);
+                res.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    header::HeaderValue::from_static(metrics::CONTENT_TYPE),
+                );
+                Ok(res)
+            });
+        }
+
+        let route = match self.rpc_paths.get(req.uri().path()) {
+            Some(path) => path.clone(),
+            None => UNMATCHED_ROUTE.to_string(),
+        };
+        let started = Instant::now();
+        let res = self.inner.call(req);
+        Box::pin(async move {
+            let res = res.await;
+            // Errors are sent in the headers, otherwise the status is in the trailers
+            let status = match &res {
+                Ok(res) => res
+                    .headers()
+                    .get("grpc-status")
+                    .map(|code| tonic::Code::from_bytes(code.as_bytes()))
+                    .unwrap_or(tonic::Code::Ok),
+                Err(_) => tonic::Code::Unknown,
+            };
+            metrics::record_request("grpc", &route, &format!("{:?}", status), started.elapsed());
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpc_paths_cover_every_service() {
+        let paths = rpc_paths();
+        for path in [
+            "/purchase.ProductService/AddTicketToBasket",
+            "/purchase.AdminService/RetryJob",
+            "/purchase.GateService/CheckIn",
+            "/grpc.health.v1.Health/Check",
+            "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+        ] {
+            assert!(paths.contains(path), "{}", path);
+        }
+        assert!(!paths.contains("/wp-login.php"));
+    }
+}
@@ -0,0 +1,94 @@
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use festival_tickets_core::telemetry::{self, REQUEST_ID_HEADER};
+use futures::future::BoxFuture;
+use http::{HeaderValue, Request, Response};
+use tonic::Code;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+/// Runs each request in a span with its request ID, from the `x-request-id` header or
+/// generated, which is also returned in the response. Logs the request once it's finished: at
+/// `warn` for client errors, or `error` for internal ones, with the error `ServiceError` recorded
+#[derive(Clone, Default)]
+pub struct TraceLayer;
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = Trace<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Trace { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct Trace<S> {
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for Trace<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let request_id = telemetry::request_id(
+            req.headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|id| id.to_str().ok()),
+        );
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            rpc = %req.uri().path(),
+            code = Empty,
+            error = Empty
+        );
+        // Valid, as request IDs are visible ASCII
+        let request_id = HeaderValue::from_str(&request_id).unwrap();
+        // Handlers can read it from the request metadata
+        req.headers_mut()
+            .insert(REQUEST_ID_HEADER, request_id.clone());
+
+        let started = Instant::now();
+        let res = span.in_scope(|| self.inner.call(req));
+        Box::pin(
+            async move {
+                let mut res = res.await?;
+                res.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+                // Errors are sent in the headers, otherwise the status is in the trailers
+                let code = res
+                    .headers()
+                    .get("grpc-status")
+                    .map(|code| Code::from_bytes(code.as_bytes()))
+                    .unwrap_or(Code::Ok);
+                let elapsed_ms = started.elapsed().as_millis() as u64;
+                let span = tracing::Span::current();
+                match code {
+                    Code::Ok => tracing::info!(elapsed_ms, "finished"),
+                    Code::Internal | Code::Unknown | Code::DataLoss => {
+                        span.record("code", tracing::field::debug(code));
+                        tracing::error!(elapsed_ms, "failed")
+                    }
+                    _ => {
+                        span.record("code", tracing::field::debug(code));
+                        tracing::warn!(elapsed_ms, "failed")
+                    }
+                }
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
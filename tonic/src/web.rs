@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+// Headers used by grpc-web clients, as in `tonic_web::enable`, plus `authorization` for the
+// admin and gate services
+const EXPOSED_HEADERS: [&str; 3] = ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+const ALLOW_HEADERS: [&str; 5] = [
+    "x-grpc-web",
+    "content-type",
+    "x-user-agent",
+    "grpc-timeout",
+    "authorization",
+];
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Origins which may call the gRPC services from a browser
+#[derive(Debug, PartialEq)]
+enum AllowedOrigins {
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+/// Parse a list of origins, where `*` allows any. Invalid origins are skipped
+fn parse_origins(value: &[String]) -> AllowedOrigins {
+    let mut origins = vec![];
+    for origin in value {
+        if origin == "*" {
+            return AllowedOrigins::Any;
+        }
+        match HeaderValue::from_str(origin.trim_end_matches('/')) {
+            Ok(origin) => origins.push(origin),
+            Err(_) => log::warn!("skipping invalid CORS origin {:?}", origin),
+        }
+    }
+    AllowedOrigins::List(origins)
+}
+
+/// CORS for gRPC-web requests, allowing `origins`.
+/// Only same origin requests are allowed if empty
+pub(crate) fn cors(origins: &[String]) -> CorsLayer {
+    let allow_origin = match parse_origins(origins) {
+        AllowedOrigins::Any => {
+            log::warn!("gRPC-web allowed from any origin");
+            AllowOrigin::any()
+        }
+        AllowedOrigins::List(origins) => {
+            if origins.is_empty() {
+                log::info!("CORS allowed origins not set, gRPC-web only allowed from same origin");
+            }
+            AllowOrigin::list(origins)
+        }
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::POST])
+        .allow_headers(ALLOW_HEADERS.map(HeaderName::from_static))
+        .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static))
+        .max_age(MAX_AGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origins(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_allowed_origins() {
+        assert_eq!(
+            parse_origins(&origins(&[
+                "http://localhost:5173",
+                "https://tickets.example.com/"
+            ])),
+            AllowedOrigins::List(vec![
+                HeaderValue::from_static("http://localhost:5173"),
+                HeaderValue::from_static("https://tickets.example.com"),
+            ])
+        );
+        assert_eq!(
+            parse_origins(&origins(&["https://tickets.example.com", "*"])),
+            AllowedOrigins::Any
+        );
+        assert_eq!(parse_origins(&[]), AllowedOrigins::List(vec![]));
+    }
+}
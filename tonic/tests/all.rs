@@ -1,19 +1,15 @@
+mod harness;
 mod test_client;
 use std::ops::Add;
 
-use test_client::pb::product_service_client::ProductServiceClient;
+use harness::TestServer;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
 use tokio_stream::StreamExt;
-use tonic::transport::Channel;
 
-async fn get_client() -> ProductServiceClient<Channel> {
-    ProductServiceClient::connect("http://localhost:50051")
-        .await
-        .unwrap()
-}
-
-#[tokio::test]
-async fn reserve_ticket() {
-    let mut client = get_client().await;
+#[sqlx::test(migrations = "../migrations")]
+async fn reserve_ticket(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.client();
     let res = client
         .get_ticket_types(test_client::pb::GetTicketTypesRequest {})
         .await
@@ -37,6 +33,7 @@ async fn reserve_ticket() {
         .add_ticket_to_basket(test_client::pb::AddTicketToBasketRequest {
             ticket_type_id: "chalet3".to_string(),
             duration: 3,
+            presale_code: None,
         })
         .await
         .unwrap()
@@ -61,14 +58,16 @@ async fn reserve_ticket() {
     );
 }
 
-#[tokio::test]
-async fn purchase_ticket() {
-    let mut client = get_client().await;
+#[sqlx::test(migrations = "../migrations")]
+async fn purchase_ticket(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.client();
 
     let res = client
         .add_ticket_to_basket(test_client::pb::AddTicketToBasketRequest {
             ticket_type_id: "chalet3".to_string(),
             duration: 3,
+            presale_code: None,
         })
         .await
         .unwrap()
@@ -126,17 +125,45 @@ async fn purchase_ticket() {
     assert!(res.order.unwrap().user_id.is_some());
 
     let res = client
-        .purchase_order(test_client::pb::PurchaseOrderRequest { id: order.id })
+        .purchase_order(test_client::pb::PurchaseOrderRequest {
+            id: order.id.clone(),
+        })
         .await
         .unwrap()
         .into_inner();
 
     assert!(res.order.unwrap().purchased_at.is_some());
+
+    // Signed ticket issued on purchase
+    let res = client
+        .get_tickets(test_client::pb::GetTicketsRequest {
+            order_id: order.id.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(res.tickets.len(), 1);
+    assert_eq!(res.tickets[0].holder_name, "Oscar");
+    assert!(res.tickets[0].credential.starts_with("FT1."));
+
+    let res = client
+        .get_ticket_qr_code(test_client::pb::GetTicketQrCodeRequest {
+            order_id: order.id,
+            format: test_client::pb::QrCodeFormat::Svg.into(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(res.content_type, "image/svg+xml");
+    assert!(String::from_utf8(res.image).unwrap().contains("<svg"));
 }
 
-#[tokio::test]
-async fn stream_order_stats() {
-    let mut client = get_client().await;
+#[sqlx::test(migrations = "../migrations")]
+async fn stream_order_stats(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.client();
 
     let stream = client
         .get_order_stats(test_client::pb::GetOrderStatsRequest {})
@@ -149,3 +176,1012 @@ async fn stream_order_stats() {
         println!("\treceived: {:#?}", item.unwrap());
     }
 }
+
+#[sqlx::test(migrations = "../migrations")]
+async fn apply_promo_code(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.client();
+
+    let order = client
+        .add_ticket_to_basket(test_client::pb::AddTicketToBasketRequest {
+            ticket_type_id: "chalet3".to_string(),
+            duration: 3,
+            presale_code: None,
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .order
+        .unwrap();
+
+    assert_eq!(order.discount, 0.0);
+
+    // Seeded code restricted to 4-day hotel tickets
+    let res = client
+        .apply_promo_code(test_client::pb::ApplyPromoCodeRequest {
+            order_id: order.id.clone(),
+            promo_code: "TESTHOTEL".to_string(),
+        })
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+
+    let res = client
+        .apply_promo_code(test_client::pb::ApplyPromoCodeRequest {
+            order_id: order.id.clone(),
+            promo_code: "TEST10PCT".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    let discounted = res.order.unwrap();
+    assert_eq!(discounted.promo_code.as_deref(), Some("TEST10PCT"));
+    assert!(discounted.discount > 0.0);
+    assert_eq!(discounted.price, order.price - discounted.discount);
+
+    // Only one code per order
+    let res = client
+        .apply_promo_code(test_client::pb::ApplyPromoCodeRequest {
+            order_id: order.id,
+            promo_code: "TEST10PCT".to_string(),
+        })
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn promo_code_per_customer_limit(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.client();
+
+    let user_info = |order_id: &String, email: &str| test_client::pb::AddUserInfoRequest {
+        user_name: "Promo".to_string(),
+        user_email: email.to_string(),
+        user_address: format!("{} Promo Road", email),
+        order_id: order_id.clone(),
+    };
+
+    // Seeded code each customer may use once
+    let mut order_ids = vec![];
+    for email in ["once@example.com", "other@example.com"] {
+        let order = client
+            .add_ticket_to_basket(test_client::pb::AddTicketToBasketRequest {
+                ticket_type_id: "chalet3".to_string(),
+                duration: 3,
+                presale_code: None,
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .order
+            .unwrap();
+        client
+            .add_user_info(user_info(&order.id, email))
+            .await
+            .unwrap();
+        client
+            .apply_promo_code(test_client::pb::ApplyPromoCodeRequest {
+                order_id: order.id.clone(),
+                promo_code: "TESTONCE".to_string(),
+            })
+            .await
+            .unwrap();
+        order_ids.push(order.id);
+    }
+
+    // Handing the second order to the first customer would use the code twice
+    let res = client
+        .add_user_info(user_info(&order_ids[1], "once+again@example.com"))
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+
+    for id in order_ids {
+        client
+            .purchase_order(test_client::pb::PurchaseOrderRequest { id: id.clone() })
+            .await
+            .unwrap();
+
+        // Nor can the customer be changed once it's purchased
+        let res = client
+            .add_user_info(user_info(&id, "someone@example.com"))
+            .await;
+        assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+    }
+}
+
+fn admin_request<T>(message: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", harness::ADMIN_TOKEN).parse().unwrap(),
+    );
+    request
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn import_presale_codes(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.admin_client();
+
+    let suffix = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+    let presale_id = format!("test-presale-{}", suffix);
+
+    // Admin API requires the admin token
+    let res = client
+        .get_presale_codes(test_client::pb::GetPresaleCodesRequest {
+            presale_id: presale_id.clone(),
+        })
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    // Presale in the future, so it doesn't affect the other tests
+    let starts_at = chrono::Utc::now().add(chrono::Duration::days(365));
+    let ends_at = starts_at.add(chrono::Duration::days(1));
+    let res = client
+        .upsert_presale(admin_request(test_client::pb::UpsertPresaleRequest {
+            id: presale_id.clone(),
+            starts_at: starts_at.to_rfc3339(),
+            ends_at: ends_at.to_rfc3339(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(res.presale.unwrap().id, presale_id);
+
+    let csv =
+        format!("code,email,ticket_limit\nCODE-{suffix},,4\n,fan-{suffix}@example.com,\n,,2\n");
+    let res = client
+        .import_presale_codes(admin_request(test_client::pb::ImportPresaleCodesRequest {
+            presale_id: presale_id.clone(),
+            csv,
+            default_ticket_limit: 2,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(res.imported, 2);
+    assert_eq!(res.rejected.len(), 1);
+
+    let res = client
+        .get_presale_codes(admin_request(test_client::pb::GetPresaleCodesRequest {
+            presale_id,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let codes = res.presale_codes;
+    assert_eq!(codes.len(), 2);
+    assert_eq!(codes[0].code, format!("code-{}", suffix));
+    assert_eq!(codes[0].ticket_limit, 4);
+    assert_eq!(codes[1].email, Some(format!("fan-{}@example.com", suffix)));
+    assert!(codes.iter().all(|c| c.redeemed == 0 && c.purchased == 0));
+}
+
+/// Codes are per presale, and allow-listed customers reserve with their email, which the order's
+/// user info must then match
+#[sqlx::test(migrations = "../migrations")]
+async fn presale_codes_and_allow_list(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.client();
+    let mut admin_client = server.admin_client();
+
+    let now = chrono::Utc::now();
+    let presales = [
+        ("running", now - chrono::Duration::hours(1)),
+        ("next", now + chrono::Duration::days(1)),
+    ];
+    for (id, starts_at) in presales {
+        admin_client
+            .upsert_presale(admin_request(test_client::pb::UpsertPresaleRequest {
+                id: id.to_string(),
+                starts_at: starts_at.to_rfc3339(),
+                ends_at: (starts_at + chrono::Duration::hours(2)).to_rfc3339(),
+            }))
+            .await
+            .unwrap();
+        let res = admin_client
+            .import_presale_codes(admin_request(test_client::pb::ImportPresaleCodesRequest {
+                presale_id: id.to_string(),
+                csv: "code,email,ticket_limit\nSHARED,,1\n,fan@example.com,1\n".to_string(),
+                default_ticket_limit: 1,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((res.imported, res.rejected.len()), (2, 0), "{}", id);
+    }
+
+    let reserve = |presale_code: Option<&str>| {
+        let req = test_client::pb::AddTicketToBasketRequest {
+            ticket_type_id: "chalet3".to_string(),
+            duration: 3,
+            presale_code: presale_code.map(str::to_string),
+        };
+        let mut client = client.clone();
+        async move { client.add_ticket_to_basket(req).await }
+    };
+    for code in [None, Some("unknown")] {
+        let res = reserve(code).await;
+        assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+    }
+    reserve(Some("shared")).await.unwrap();
+    let res = reserve(Some("SHARED")).await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+
+    let order_id = reserve(Some("Fan@Example.com"))
+        .await
+        .unwrap()
+        .into_inner()
+        .order
+        .unwrap()
+        .id;
+    let add_user_info = |email: &str| test_client::pb::AddUserInfoRequest {
+        user_name: "Fan".to_string(),
+        user_email: email.to_string(),
+        user_address: "1 Fan Street".to_string(),
+        order_id: order_id.clone(),
+    };
+    let res = client
+        .add_user_info(add_user_info("someone@example.com"))
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+    client
+        .add_user_info(add_user_info("fan@example.com"))
+        .await
+        .unwrap();
+
+    let codes = admin_client
+        .get_presale_codes(admin_request(test_client::pb::GetPresaleCodesRequest {
+            presale_id: "running".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .presale_codes;
+    let codes: Vec<_> = codes
+        .iter()
+        .map(|c| (c.code.as_str(), c.redeemed))
+        .collect();
+    assert_eq!(codes, vec![("fan@example.com", 1), ("shared", 1)]);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn purchase_limit_override(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.client();
+    let mut admin_client = server.admin_client();
+
+    let suffix = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+    let email = format!("limit-{}@example.com", suffix);
+
+    let res = admin_client
+        .set_purchase_limit_override(admin_request(
+            test_client::pb::SetPurchaseLimitOverrideRequest {
+                email: email.to_uppercase(),
+                ticket_limit: 1,
+                note: Some("test".to_string()),
+            },
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(res.purchase_limit_override.unwrap().email, email);
+
+    let mut order_ids = vec![];
+    for _ in 0..2 {
+        let res = client
+            .add_ticket_to_basket(test_client::pb::AddTicketToBasketRequest {
+                ticket_type_id: "chalet3".to_string(),
+                duration: 3,
+                presale_code: None,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        order_ids.push(res.order.unwrap().id);
+    }
+
+    let add_user_info = |order_id: &String| test_client::pb::AddUserInfoRequest {
+        user_name: "Limit".to_string(),
+        // Sub-addressed variant of the same mailbox counts against the same limit
+        user_email: email.replace('@', &format!("+{}@", order_id)),
+        user_address: format!("{} Limit Road", suffix),
+        order_id: order_id.clone(),
+    };
+
+    client
+        .add_user_info(add_user_info(&order_ids[0]))
+        .await
+        .unwrap();
+
+    let res = client.add_user_info(add_user_info(&order_ids[1])).await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+
+    // Default limit applies again once the override is removed
+    admin_client
+        .delete_purchase_limit_override(admin_request(
+            test_client::pb::DeletePurchaseLimitOverrideRequest {
+                email: email.clone(),
+            },
+        ))
+        .await
+        .unwrap();
+
+    client
+        .add_user_info(add_user_info(&order_ids[1]))
+        .await
+        .unwrap();
+}
+
+fn scanner_request<T>(message: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", harness::SCANNER_TOKEN)
+            .parse()
+            .unwrap(),
+    );
+    request
+}
+
+/// Reserve, fill in and purchase a 3 day ticket for a new customer
+async fn purchase_new_ticket(server: &TestServer) -> test_client::pb::Ticket {
+    let mut client = server.client();
+    let suffix = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+
+    let order = client
+        .add_ticket_to_basket(test_client::pb::AddTicketToBasketRequest {
+            ticket_type_id: "chalet3".to_string(),
+            duration: 3,
+            presale_code: None,
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .order
+        .unwrap();
+
+    client
+        .add_user_info(test_client::pb::AddUserInfoRequest {
+            user_name: "Gate".to_string(),
+            user_email: format!("gate-{}@example.com", suffix),
+            user_address: format!("{} Gate Road", suffix),
+            order_id: order.id.clone(),
+        })
+        .await
+        .unwrap();
+    client
+        .purchase_order(test_client::pb::PurchaseOrderRequest {
+            id: order.id.clone(),
+        })
+        .await
+        .unwrap();
+
+    client
+        .get_tickets(test_client::pb::GetTicketsRequest { order_id: order.id })
+        .await
+        .unwrap()
+        .into_inner()
+        .tickets
+        .pop()
+        .unwrap()
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn check_in_ticket(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.gate_client();
+    let ticket = purchase_new_ticket(&server).await;
+
+    let check_in = |direction: test_client::pb::ScanDirection| {
+        scanner_request(test_client::pb::CheckInRequest {
+            credential: ticket.credential.clone(),
+            direction: direction.into(),
+            gate: "north".to_string(),
+        })
+    };
+
+    // Gate API requires the scanner token
+    let res = client
+        .check_in(tonic::Request::new(
+            test_client::pb::CheckInRequest::default(),
+        ))
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    let config = client
+        .get_scanner_config(scanner_request(test_client::pb::GetScannerConfigRequest {}))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!config.verifying_key.is_empty());
+    assert_eq!(config.festival_utc_offset, "+00:00");
+
+    let festival_start: chrono::NaiveDate = config.festival_start_date.parse().unwrap();
+    let today = chrono::Utc::now().date_naive();
+    let festival_day = (today - festival_start).num_days() + 1;
+
+    let res = client
+        .check_in(check_in(test_client::pb::ScanDirection::Entry))
+        .await;
+    if !(1..=3).contains(&festival_day) {
+        // Outside the festival, so the ticket isn't valid today
+        assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+        return;
+    }
+
+    let res = res.unwrap().into_inner();
+    assert_eq!(res.check_in.unwrap().festival_day, festival_day as i32);
+    assert_eq!(res.ticket.unwrap().holder_name, "Gate");
+
+    // Duplicate entry is rejected
+    let res = client
+        .check_in(check_in(test_client::pb::ScanDirection::Entry))
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+
+    client
+        .check_in(check_in(test_client::pb::ScanDirection::Exit))
+        .await
+        .unwrap();
+    client
+        .check_in(check_in(test_client::pb::ScanDirection::Entry))
+        .await
+        .unwrap();
+
+    // Forged credentials are rejected
+    let res = client
+        .check_in(scanner_request(test_client::pb::CheckInRequest {
+            credential: ticket.credential.replace("FT1.", "FT1.e30"),
+            direction: test_client::pb::ScanDirection::Entry.into(),
+            gate: "north".to_string(),
+        }))
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn sync_offline_check_ins(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.gate_client();
+    let ticket = purchase_new_ticket(&server).await;
+
+    let config = client
+        .get_scanner_config(scanner_request(test_client::pb::GetScannerConfigRequest {}))
+        .await
+        .unwrap()
+        .into_inner();
+    let festival_start: chrono::NaiveDate = config.festival_start_date.parse().unwrap();
+    let scanned_at = |day: u64, hour: u32| {
+        (festival_start + chrono::Days::new(day - 1))
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+            .and_utc()
+            .to_rfc3339()
+    };
+
+    let scan = |direction: test_client::pb::ScanDirection, gate: &str, scanned_at: String| {
+        test_client::pb::OfflineCheckIn {
+            client_ref: uuid::Uuid::new_v4().to_string(),
+            credential: ticket.credential.clone(),
+            direction: direction.into(),
+            gate: gate.to_string(),
+            scanned_at,
+        }
+    };
+    let check_ins = vec![
+        scan(
+            test_client::pb::ScanDirection::Entry,
+            "north",
+            scanned_at(2, 10),
+        ),
+        scan(
+            test_client::pb::ScanDirection::Exit,
+            "north",
+            scanned_at(2, 18),
+        ),
+        // 3 day ticket isn't valid on day 4
+        scan(
+            test_client::pb::ScanDirection::Entry,
+            "north",
+            scanned_at(4, 10),
+        ),
+        test_client::pb::OfflineCheckIn {
+            credential: "FT1.forged.credential".to_string(),
+            ..scan(
+                test_client::pb::ScanDirection::Entry,
+                "north",
+                scanned_at(2, 10),
+            )
+        },
+    ];
+
+    let res = client
+        .sync_check_ins(scanner_request(test_client::pb::SyncCheckInsRequest {
+            check_ins: check_ins.clone(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(res.check_ins.len(), 3);
+    assert_eq!(res.rejected.len(), 1);
+    assert!(res.check_ins.iter().all(|c| c.offline));
+    assert_eq!(res.check_ins[0].conflict, None);
+    assert_eq!(res.check_ins[1].conflict, None);
+    assert!(res.check_ins[2].conflict.is_some());
+
+    // Same ticket let in earlier at another gate, by a scanner which synced later
+    let late = client
+        .sync_check_ins(scanner_request(test_client::pb::SyncCheckInsRequest {
+            check_ins: vec![scan(
+                test_client::pb::ScanDirection::Entry,
+                "south",
+                scanned_at(2, 9),
+            )],
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(late.check_ins[0].conflict.is_some());
+
+    // Syncing again doesn't record the scans twice, but shows the entry it duplicated
+    let again = client
+        .sync_check_ins(scanner_request(test_client::pb::SyncCheckInsRequest {
+            check_ins,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let ids = |res: &test_client::pb::SyncCheckInsResponse| {
+        res.check_ins.iter().map(|c| c.id).collect::<Vec<_>>()
+    };
+    assert_eq!(ids(&again), ids(&res));
+    assert!(again.check_ins[0].conflict.is_some());
+    assert_eq!(again.check_ins[1].conflict, None);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn inspect_background_jobs(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut admin_client = server.admin_client();
+
+    // Scheduled by the job runner once it starts
+    let mut jobs = vec![];
+    for _ in 0..50 {
+        jobs = admin_client
+            .get_jobs(admin_request(test_client::pb::GetJobsRequest {
+                state: None,
+                kind: Some("expire_reservations".to_string()),
+                limit: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .jobs;
+        if !jobs.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(!jobs.is_empty());
+    assert!(jobs
+        .iter()
+        .all(|j| j.kind == "expire_reservations" && j.state != "dead"));
+
+    let res = admin_client
+        .retry_job(admin_request(test_client::pb::RetryJobRequest { id: -1 }))
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+
+    let res = admin_client
+        .get_jobs(test_client::pb::GetJobsRequest::default())
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::Unauthenticated);
+}
+
+/// Accept webhook deliveries on a random local port, responding 200. Returns the url, and the
+/// headers and body of each delivery.
+fn webhook_receiver() -> (
+    String,
+    tokio::sync::mpsc::UnboundedReceiver<(String, String)>,
+) {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                headers.push_str(&line.to_lowercase());
+            }
+            let length = headers
+                .lines()
+                .find_map(|l| l.strip_prefix("content-length: "))
+                .map(|l| l.trim().parse().unwrap())
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                .unwrap();
+            let _ = tx.send((headers, String::from_utf8(body).unwrap()));
+        }
+    });
+    (url, rx)
+}
+
+fn check_webhook_signature(secret: &str, headers: &str, body: &str) {
+    use hmac::Mac;
+
+    let signature = headers
+        .lines()
+        .find_map(|l| l.strip_prefix("x-festival-signature: "))
+        .expect("signature header")
+        .trim();
+    let (timestamp, signature) = signature
+        .strip_prefix("t=")
+        .and_then(|s| s.split_once(",v1="))
+        .unwrap();
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    mac.verify_slice(&hex::decode(signature).unwrap()).unwrap();
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn deliver_webhooks(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut admin_client = server.admin_client();
+    let (url, mut deliveries) = webhook_receiver();
+
+    let res = admin_client
+        .create_webhook_endpoint(admin_request(
+            test_client::pb::CreateWebhookEndpointRequest {
+                url: url.clone(),
+                events: vec!["order.shipped".to_string()],
+            },
+        ))
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::FailedPrecondition);
+
+    let res = admin_client
+        .create_webhook_endpoint(admin_request(
+            test_client::pb::CreateWebhookEndpointRequest {
+                url,
+                events: vec!["order.purchased".to_string()],
+            },
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    let endpoint = res.endpoint.unwrap();
+
+    let delivery = admin_client
+        .test_webhook_endpoint(admin_request(test_client::pb::TestWebhookEndpointRequest {
+            endpoint_id: endpoint.id.clone(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .delivery
+        .unwrap();
+    assert_eq!(delivery.event, "webhook.test");
+    assert_eq!(delivery.response_status, Some(200));
+    assert!(delivery.delivered_at.is_some());
+
+    let (headers, body) = deliveries.recv().await.unwrap();
+    assert!(headers.contains("x-festival-event: webhook.test"));
+    assert_eq!(body, delivery.payload);
+    check_webhook_signature(&res.secret, &headers, &body);
+
+    // Purchases are delivered in the background
+    let ticket = purchase_new_ticket(&server).await;
+    let (headers, body) = loop {
+        let (headers, body) =
+            tokio::time::timeout(std::time::Duration::from_secs(10), deliveries.recv())
+                .await
+                .expect("order.purchased delivery")
+                .unwrap();
+        if body.contains(&ticket.order_id) {
+            break (headers, body);
+        }
+    };
+    assert!(headers.contains("x-festival-event: order.purchased"));
+    check_webhook_signature(&res.secret, &headers, &body);
+
+    let log = admin_client
+        .get_webhook_deliveries(admin_request(
+            test_client::pb::GetWebhookDeliveriesRequest {
+                endpoint_id: endpoint.id.clone(),
+                limit: None,
+            },
+        ))
+        .await
+        .unwrap()
+        .into_inner()
+        .deliveries;
+    assert!(log.iter().any(|d| d.event == "order.purchased"));
+
+    admin_client
+        .delete_webhook_endpoint(admin_request(
+            test_client::pb::DeleteWebhookEndpointRequest { id: endpoint.id },
+        ))
+        .await
+        .unwrap();
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn export_orders(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut admin_client = server.admin_client();
+    let started = chrono::Utc::now().add(chrono::Duration::seconds(-1));
+    let ticket = purchase_new_ticket(&server).await;
+
+    let export = |format, purchased_from: chrono::DateTime<chrono::Utc>| {
+        let mut admin_client = admin_client.clone();
+        async move {
+            let mut stream = admin_client
+                .export_orders(admin_request(test_client::pb::ExportOrdersRequest {
+                    format: format as i32,
+                    ticket_type_id: Some("chalet3".to_string()),
+                    duration: Some(3),
+                    purchased_from: Some(purchased_from.to_rfc3339()),
+                    purchased_to: None,
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            let mut data = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                data.extend(chunk.unwrap().data);
+            }
+            String::from_utf8(data).unwrap()
+        }
+    };
+
+    let csv = export(test_client::pb::ExportFormat::Csv, started).await;
+    let mut lines = csv.lines();
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("order_id,ticket_type_id,"));
+    let row = lines
+        .find(|l| l.starts_with(&ticket.order_id))
+        .expect("purchased order is exported");
+    assert!(row.contains(",chalet3,"));
+    assert!(row.contains("@example.com,"));
+
+    let ndjson = export(test_client::pb::ExportFormat::Ndjson, started).await;
+    let rows: Vec<serde_json::Value> = ndjson
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let row = rows
+        .iter()
+        .find(|r| r["order_id"] == ticket.order_id.as_str())
+        .expect("purchased order is exported");
+    assert_eq!(row["duration"], 3);
+    assert_eq!(row["holder_name"], "Gate");
+
+    // Only the header, as nothing has been purchased since
+    let csv = export(
+        test_client::pb::ExportFormat::Csv,
+        chrono::Utc::now().add(chrono::Duration::hours(1)),
+    )
+    .await;
+    assert_eq!(csv.lines().count(), 1);
+
+    let res = admin_client
+        .export_orders(test_client::pb::ExportOrdersRequest::default())
+        .await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::Unauthenticated);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn import_comp_tickets(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.client();
+    let mut admin_client = server.admin_client();
+    let suffix = chrono::Utc::now().timestamp_nanos_opt().unwrap();
+    let csv = format!(
+        "ticket_type_id,duration,name,email,address,allocation
+chalet3,3,Crew {suffix},crew-{suffix}@example.com,1 Crew Lane,
+chalet3,4,Artist {suffix},artist-{suffix}@example.com,2 Stage Road,artist
+nope,3,Nobody,nobody@example.com,3 Nowhere,
+chalet3,5,Nobody,nobody@example.com,3 Nowhere,
+"
+    );
+    let import = |dry_run| test_client::pb::ImportCompTicketsRequest {
+        csv: csv.clone(),
+        allocation: Some("crew".to_string()),
+        dry_run,
+    };
+
+    let report = admin_client
+        .import_comp_tickets(admin_request(import(true)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(report.imported, 2);
+    assert!(report.order_ids.is_empty());
+    assert_eq!(
+        report.rejected,
+        vec![
+            "line 4: unknown ticket type nope",
+            "line 5: invalid duration 5"
+        ]
+    );
+
+    let report = admin_client
+        .import_comp_tickets(admin_request(import(false)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(report.imported, 2);
+    assert_eq!(report.order_ids.len(), 2);
+    assert_eq!(report.rejected.len(), 2);
+
+    for order_id in report.order_ids {
+        let order = client
+            .get_order(test_client::pb::GetOrderRequest {
+                id: order_id.clone(),
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .order
+            .unwrap();
+        assert_eq!(order.price, 0.0);
+        assert!(order.purchased_at.is_some());
+
+        let tickets = client
+            .get_tickets(test_client::pb::GetTicketsRequest { order_id })
+            .await
+            .unwrap()
+            .into_inner()
+            .tickets;
+        assert_eq!(tickets.len(), 1);
+    }
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn grpc_web_from_allowed_origin(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let origin = harness::ALLOWED_ORIGIN;
+    let client = hyper::Client::new();
+
+    // Browsers check with a preflight request first
+    let preflight = hyper::Request::options(server.url("/purchase.ProductService/GetTicketTypes"))
+        .header("origin", origin)
+        .header("access-control-request-method", "POST")
+        .header("access-control-request-headers", "content-type,x-grpc-web")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let res = client.request(preflight).await.unwrap();
+    assert_eq!(res.headers()["access-control-allow-origin"], origin);
+
+    // Empty GetTicketTypesRequest, framed as an uncompressed message with zero length
+    let call = hyper::Request::post(server.url("/purchase.ProductService/GetTicketTypes"))
+        .header("origin", origin)
+        .header("content-type", "application/grpc-web+proto")
+        .header("x-grpc-web", "1")
+        .body(hyper::Body::from(vec![0u8; 5]))
+        .unwrap();
+    let res = client.request(call).await.unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["access-control-allow-origin"], origin);
+    assert_eq!(res.headers()["content-type"], "application/grpc-web+proto");
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    // Data frame with the ticket types, then a trailers frame with the status
+    assert_eq!(body[0], 0);
+    let trailers = String::from_utf8_lossy(&body);
+    assert!(trailers.contains("grpc-status:0"));
+
+    let other = hyper::Request::options(server.url("/purchase.ProductService/GetTicketTypes"))
+        .header("origin", "https://not-allowed.example.com")
+        .header("access-control-request-method", "POST")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let res = client.request(other).await.unwrap();
+    assert!(!res.headers().contains_key("access-control-allow-origin"));
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn health_check(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    use tonic_health::pb::health_check_response::ServingStatus;
+    use tonic_health::pb::HealthCheckRequest;
+
+    let server = TestServer::start(pool_options, options).await;
+
+    let mut client = server.health_client();
+    for service in ["", "purchase.ProductService", "purchase.GateService"] {
+        let res = client
+            .check(HealthCheckRequest {
+                service: service.to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(res.status(), ServingStatus::Serving, "{}", service);
+    }
+
+    let status = client
+        .check(HealthCheckRequest {
+            service: "purchase.Unknown".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn metrics(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.client();
+    client
+        .add_ticket_to_basket(test_client::pb::AddTicketToBasketRequest {
+            ticket_type_id: "chalet3".to_string(),
+            duration: 3,
+            presale_code: None,
+        })
+        .await
+        .unwrap();
+    // Paths which aren't an RPC are recorded under one route
+    hyper::Client::new()
+        .get(server.url("/wp-login.php").parse().unwrap())
+        .await
+        .unwrap();
+
+    // Only scrapers with the token can read metrics
+    let res = hyper::Client::new()
+        .get(server.url("/metrics").parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 401);
+
+    let body = server.metrics().await;
+    assert!(body.contains(
+        r#"festival_requests_total{api="grpc",route="/purchase.ProductService/AddTicketToBasket",status="Ok"}"#
+    ));
+    assert!(body.contains(r#"festival_orders_reserved_total{duration="3",ticket_type="chalet3"}"#));
+    assert!(body.contains(r#"festival_inventory_remaining{duration="3"}"#));
+    assert!(body.contains(r#"festival_db_pool_connections{state="max"}"#));
+    assert!(body.contains(r#"route="unmatched""#));
+    assert!(!body.contains("wp-login"));
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn request_ids(pool_options: PgPoolOptions, options: PgConnectOptions) {
+    let server = TestServer::start(pool_options, options).await;
+    let mut client = server.client();
+
+    let mut req = tonic::Request::new(test_client::pb::GetTicketTypesRequest {});
+    req.metadata_mut()
+        .insert("x-request-id", "test-request-1".parse().unwrap());
+    let res = client.get_ticket_types(req).await.unwrap();
+    assert_eq!(
+        res.metadata().get("x-request-id").unwrap(),
+        "test-request-1"
+    );
+
+    // Generated if not sent, and returned with errors too
+    let status = client
+        .get_order(test_client::pb::GetOrderRequest {
+            id: "not-a-uuid".to_string(),
+        })
+        .await
+        .unwrap_err();
+    let request_id = status.metadata().get("x-request-id").unwrap();
+    assert_eq!(request_id.to_str().unwrap().len(), 36);
+}
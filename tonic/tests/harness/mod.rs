@@ -0,0 +1,185 @@
+//! Runs the gRPC server in-process for each test, on a random port, against the database
+//! `sqlx::test` created and migrated for it. Background jobs, order stats and caches are started
+//! by `App::start`, as in the server, and run until the `TestServer` is dropped.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use festival_tickets_core::app::App;
+use festival_tickets_core::config::{ApiTokens, Config};
+use festival_tickets_core::db::DbPool;
+use festival_tickets_core::metrics;
+use festival_tickets_core::shutdown::CancellationToken;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::Executor;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Channel;
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+
+use crate::test_client::pb::admin_service_client::AdminServiceClient;
+use crate::test_client::pb::gate_service_client::GateServiceClient;
+use crate::test_client::pb::product_service_client::ProductServiceClient;
+
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+pub const SCANNER_TOKEN: &str = "test-scanner-token";
+pub const METRICS_TOKEN: &str = "test-metrics-token";
+pub const ALLOWED_ORIGIN: &str = "http://localhost:5173";
+
+const SIGNING_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+/// Services whose health is reported, where "" is the server as a whole
+const SERVICES: [&str; 4] = [
+    "",
+    "purchase.ProductService",
+    "purchase.AdminService",
+    "purchase.GateService",
+];
+
+/// Enough for the connections background tasks hold, and a few requests at once
+const MAX_CONNECTIONS: u32 = 10;
+
+pub struct TestServer {
+    pool: DbPool,
+    addr: SocketAddr,
+    channel: Channel,
+    shutdown: CancellationToken,
+}
+
+impl TestServer {
+    /// Seed the test database, then start the server and wait until it reports serving
+    pub async fn start(pool_options: PgPoolOptions, options: PgConnectOptions) -> Self {
+        let pool = pool_options
+            .max_connections(MAX_CONNECTIONS)
+            .connect_with(options)
+            .await
+            .unwrap();
+        // As `db::connect_to_pool` would, for the pool metrics
+        metrics::observe_pool(&pool);
+        pool.execute(include_str!("../../../seed_db.sql"))
+            .await
+            .unwrap();
+
+        let mut config = Config::default();
+        config.server.cors_allowed_origins = vec![ALLOWED_ORIGIN.to_string()];
+        config.reservations.max_tickets_per_customer = Some(4);
+        // So jobs queued by a request run promptly
+        config.workers.poll_interval_ms = 100;
+        config.tickets.signing_key = SIGNING_KEY.to_string();
+        // Festival starts today, so tickets can be checked in
+        config.tickets.festival_start_date = Some(chrono::Utc::now().date_naive());
+
+        let tokens = ApiTokens {
+            admin: Some(ADMIN_TOKEN.to_string()),
+            scanner: Some(SCANNER_TOKEN.to_string()),
+            metrics: Some(METRICS_TOKEN.to_string()),
+        };
+        let app = App::start(
+            pool.clone(),
+            None,
+            None,
+            tokens,
+            config,
+            CancellationToken::new(),
+        )
+        .unwrap();
+        let shutdown = app.shutdown.clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            festival_tickets_tonic::grpc_server(
+                app.ticketing.clone(),
+                app.health.clone(),
+                app.tokens.clone(),
+                &app.config.server,
+                shutdown.clone(),
+            )
+            .serve_with_incoming_shutdown(
+                TcpListenerStream::new(listener),
+                shutdown.clone().cancelled_owned(),
+            ),
+        );
+
+        let channel = Channel::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let server = Self {
+            pool,
+            addr,
+            channel,
+            shutdown,
+        };
+        server.wait_until_serving().await;
+        server
+    }
+
+    /// Health is reported for each service once the database and workers have been checked
+    async fn wait_until_serving(&self) {
+        let mut health = self.health_client();
+        for service in SERVICES {
+            let mut serving = false;
+            for _ in 0..100 {
+                let res = health
+                    .check(HealthCheckRequest {
+                        service: service.to_string(),
+                    })
+                    .await;
+                serving =
+                    matches!(res, Ok(res) if res.get_ref().status() == ServingStatus::Serving);
+                if serving {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert!(serving, "test server isn't serving {:?}", service);
+        }
+    }
+
+    /// `http://` url of `path` on the server, for plain HTTP requests
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Metrics, scraped with the metrics token
+    pub async fn metrics(&self) -> String {
+        let req = hyper::Request::get(self.url("/metrics"))
+            .header("authorization", format!("Bearer {}", METRICS_TOKEN))
+            .body(hyper::Body::empty())
+            .unwrap();
+        let res = hyper::Client::new().request(req).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    pub fn client(&self) -> ProductServiceClient<Channel> {
+        ProductServiceClient::new(self.channel.clone())
+    }
+
+    pub fn admin_client(&self) -> AdminServiceClient<Channel> {
+        AdminServiceClient::new(self.channel.clone())
+    }
+
+    pub fn gate_client(&self) -> GateServiceClient<Channel> {
+        GateServiceClient::new(self.channel.clone())
+    }
+
+    pub fn health_client(&self) -> HealthClient<Channel> {
+        HealthClient::new(self.channel.clone())
+    }
+}
+
+impl Drop for TestServer {
+    /// Stop the server and background tasks, and close the pool's connections once they're
+    /// returned, so `sqlx::test` can drop the test database
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+        let pool = self.pool.clone();
+        tokio::spawn(async move { pool.close().await });
+    }
+}
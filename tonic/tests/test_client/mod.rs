@@ -1,3 +1,4 @@
 pub mod pb {
+    #![allow(dead_code)]
     tonic::include_proto!("purchase");
 }